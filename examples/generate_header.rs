@@ -0,0 +1,15 @@
+// Regenerates the C header of the FFI exports in `include/unifiedstreams.h`. Run with
+// `cargo run --example generate_header` after changing an export.
+fn main() {
+    let crate_dir = env!("CARGO_MANIFEST_DIR");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("read cbindgen.toml");
+
+    // Only `ffi.rs` declares exports, parsing it alone avoids running `cargo metadata`.
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{}/src/ffi.rs", crate_dir))
+        .generate()
+        .expect("generate the C header");
+    bindings.write_to_file(format!("{}/include/unifiedstreams.h", crate_dir));
+}
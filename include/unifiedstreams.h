@@ -0,0 +1,294 @@
+#ifndef UNIFIEDSTREAMS_H
+#define UNIFIEDSTREAMS_H
+
+/* Generated by cbindgen from src/ffi.rs with `cargo run --example generate_header`, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct EntityStore EntityStore;
+
+typedef struct ApiPageStream ApiPageStream;
+
+typedef struct MockSubstreamsServer MockSubstreamsServer;
+
+#define FFI_OK 0
+
+#define FFI_ERROR 1
+
+#define FFI_INVALID_ARGUMENT 2
+
+#define FFI_PANIC 3
+
+#define FFI_END 4
+
+#define FFI_CANCELLED 5
+
+typedef struct ApiStreamHandle ApiStreamHandle;
+
+typedef struct AsyncRequest AsyncRequest;
+
+typedef struct SubstreamsSession SubstreamsSession;
+
+typedef struct WsSubscriptionHandle WsSubscriptionHandle;
+
+typedef struct FfiResult {
+  int32_t status;
+  const void *data;
+  size_t length;
+  const char *error;
+} FfiResult;
+
+typedef void (*FfiCompletionCallback)(void *user_data, struct FfiResult *result);
+
+typedef void (*WsSubscriptionCallback)(void *user_data, const char *json, int32_t is_error);
+
+typedef void (*ApiStreamCallback)(void *user_data, const char *json, int32_t is_error);
+
+typedef struct FfiByteArray {
+  const uint8_t *data;
+  size_t length;
+} FfiByteArray;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct FfiResult *last_panic_ffi(void);
+
+struct FfiResult *async_request_cancel_ffi(struct AsyncRequest *request);
+
+void free_async_request(struct AsyncRequest *request);
+
+struct FfiResult *substreams_call_ffi(const char *endpoint_url,
+                                      const char *package_file,
+                                      const char *module_name,
+                                      const char *range);
+
+struct FfiResult *substreams_call_async_ffi(const char *endpoint_url,
+                                            const char *package_file,
+                                            const char *module_name,
+                                            const char *range,
+                                            FfiCompletionCallback callback,
+                                            void *user_data,
+                                            struct AsyncRequest **out_request);
+
+struct FfiResult *firehose_call_ffi(const char *endpoint_url,
+                                    const char *range,
+                                    bool final_blocks_only);
+
+struct FfiResult *firehose_call_async_ffi(const char *endpoint_url,
+                                          const char *range,
+                                          bool final_blocks_only,
+                                          FfiCompletionCallback callback,
+                                          void *user_data,
+                                          struct AsyncRequest **out_request);
+
+struct FfiResult *firehose_fetch_block_ffi(const char *endpoint_url,
+                                           uint64_t block_num,
+                                           const char *block_hash,
+                                           const char *cursor);
+
+struct FfiResult *substreams_session_open_ffi(const char *endpoint_url,
+                                              const char *package_file,
+                                              const char *module_name,
+                                              const char *range,
+                                              const char *cursor,
+                                              const char *token,
+                                              struct SubstreamsSession **out_session);
+
+struct FfiResult *substreams_session_next_ffi(struct SubstreamsSession *session);
+
+void free_substreams_session(struct SubstreamsSession *session);
+
+void free_ffi_result(struct FfiResult *result);
+
+struct FfiResult *rpc_call_ffi(const char *rpc_endpoint,
+                               const char *method,
+                               const char *params_input,
+                               int32_t id);
+
+struct FfiResult *rpc_call_async_ffi(const char *rpc_endpoint,
+                                     const char *method,
+                                     const char *params_input,
+                                     int32_t id,
+                                     FfiCompletionCallback callback,
+                                     void *user_data,
+                                     struct AsyncRequest **out_request);
+
+struct FfiResult *rpc_batch_call_ffi(const char *rpc_endpoint,
+                                     const char *requests_json,
+                                     size_t max_batch_size);
+
+struct FfiResult *rpc_batch_call_async_ffi(const char *rpc_endpoint,
+                                           const char *requests_json,
+                                           size_t max_batch_size,
+                                           FfiCompletionCallback callback,
+                                           void *user_data,
+                                           struct AsyncRequest **out_request);
+
+struct FfiResult *rpc_group_call_ffi(const char *rpc_endpoints,
+                                     const char *strategy,
+                                     const char *method,
+                                     const char *params_input);
+
+struct FfiResult *rpc_group_call_async_ffi(const char *rpc_endpoints,
+                                           const char *strategy,
+                                           const char *method,
+                                           const char *params_input,
+                                           FfiCompletionCallback callback,
+                                           void *user_data,
+                                           struct AsyncRequest **out_request);
+
+struct FfiResult *rpc_group_health_ffi(const char *rpc_endpoints, const char *strategy);
+
+struct FfiResult *eth_block_number_ffi(const char *rpc_endpoint);
+
+struct FfiResult *eth_chain_id_ffi(const char *rpc_endpoint);
+
+struct FfiResult *eth_get_block_by_number_ffi(const char *rpc_endpoint,
+                                              const char *block,
+                                              bool full_transactions);
+
+struct FfiResult *eth_get_logs_ffi(const char *rpc_endpoint, const char *filter_json);
+
+struct FfiResult *eth_call_ffi(const char *rpc_endpoint,
+                               const char *to,
+                               const char *data,
+                               const char *block);
+
+struct FfiResult *eth_get_transaction_receipt_ffi(const char *rpc_endpoint, const char *hash);
+
+struct FfiResult *http_client_configure_ffi(const char *config_json);
+
+struct FfiResult *response_cache_configure_ffi(const char *config_json);
+
+struct FfiResult *endpoint_pool_configure_ffi(const char *config_json);
+
+struct FfiResult *response_cache_clear_ffi(void);
+
+struct FfiResult *ws_subscribe_ffi(const char *ws_endpoint,
+                                   const char *kind,
+                                   const char *filter_json,
+                                   WsSubscriptionCallback callback,
+                                   void *user_data,
+                                   struct WsSubscriptionHandle **out_handle);
+
+struct FfiResult *ws_unsubscribe_ffi(struct WsSubscriptionHandle *handle);
+
+struct FfiResult *api_call_ffi(const char *api_url, const char *optional_headers);
+
+struct FfiResult *api_call_async_ffi(const char *api_url,
+                                     const char *optional_headers,
+                                     FfiCompletionCallback callback,
+                                     void *user_data,
+                                     struct AsyncRequest **out_request);
+
+struct FfiResult *api_request_ffi(const char *api_url, const char *options_json);
+
+struct FfiResult *api_request_async_ffi(const char *api_url,
+                                        const char *options_json,
+                                        FfiCompletionCallback callback,
+                                        void *user_data,
+                                        struct AsyncRequest **out_request);
+
+struct FfiResult *api_paginate_ffi(const char *api_url,
+                                   const char *options_json,
+                                   const char *pagination_json,
+                                   size_t max_pages,
+                                   ApiPageStream **out_pages);
+
+struct FfiResult *api_page_next_ffi(ApiPageStream *pages);
+
+void free_api_page_iterator(ApiPageStream *pages);
+
+struct FfiResult *api_stream_ffi(const char *api_url,
+                                 const char *options_json,
+                                 const char *format,
+                                 ApiStreamCallback callback,
+                                 void *user_data,
+                                 struct ApiStreamHandle **out_handle);
+
+struct FfiResult *api_stream_close_ffi(struct ApiStreamHandle *handle);
+
+struct FfiResult *sink_deploy_ffi(const char *endpoint_url,
+                                  const char *package_file,
+                                  const char *parameters,
+                                  bool development_mode);
+
+struct FfiResult *sink_update_ffi(const char *endpoint_url,
+                                  const char *deployment_id,
+                                  const char *package_file,
+                                  bool reset);
+
+struct FfiResult *sink_list_ffi(const char *endpoint_url);
+
+struct FfiResult *sink_deployment_ffi(const char *endpoint_url,
+                                      const char *action,
+                                      const char *deployment_id);
+
+struct FfiResult *sink_wait_for_status_ffi(const char *endpoint_url,
+                                           const char *deployment_id,
+                                           const char *statuses,
+                                           uint64_t poll_interval_ms,
+                                           uint64_t timeout_ms);
+
+struct FfiResult *substreams_entities_call_ffi(const char *endpoint_url,
+                                               const char *package_file,
+                                               const char *module_name,
+                                               const char *range,
+                                               EntityStore **out_store);
+
+struct FfiResult *substreams_store_call_ffi(const char *endpoint_url,
+                                            const char *package_file,
+                                            const char *module_name,
+                                            const char *range);
+
+struct FfiResult *entity_store_new_ffi(EntityStore **out_store);
+
+struct FfiResult *entity_store_apply_ffi(EntityStore *store,
+                                         uint64_t block_num,
+                                         const uint8_t *data,
+                                         size_t length);
+
+struct FfiResult *entity_store_revert_ffi(EntityStore *store, uint64_t block_num);
+
+struct FfiResult *entity_store_finalize_ffi(EntityStore *store, uint64_t block_num);
+
+struct FfiResult *entity_store_get_ffi(const EntityStore *store,
+                                       const char *entity_type,
+                                       const char *id);
+
+struct FfiResult *entity_store_list_ffi(const EntityStore *store, const char *entity_type);
+
+void free_entity_store(EntityStore *store);
+
+struct FfiResult *substreams_sqlite_call_ffi(const char *endpoint_url,
+                                             const char *package_file,
+                                             const char *module_name,
+                                             const char *range,
+                                             const char *database_path);
+
+struct FfiResult *substreams_file_sink_call_ffi(const char *endpoint_url,
+                                                const char *package_file,
+                                                const char *module_name,
+                                                const char *range,
+                                                const char *output_dir,
+                                                const char *format,
+                                                uint64_t blocks_per_file);
+
+struct FfiResult *mock_substreams_server_start_ffi(const char *sessions_json,
+                                                   MockSubstreamsServer **out_server);
+
+struct FfiResult *mock_substreams_server_url_ffi(const MockSubstreamsServer *server);
+
+void free_mock_substreams_server(MockSubstreamsServer *server);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* UNIFIEDSTREAMS_H */
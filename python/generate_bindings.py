@@ -0,0 +1,121 @@
+"""Generates unifiedstreams/_bindings.py from the C header written by cbindgen.
+
+Run it after regenerating the header whenever the exported functions change:
+
+    cargo run --example generate_header
+    python python/generate_bindings.py
+"""
+
+import re
+import sys
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent
+HEADER = ROOT.parent / "include" / "unifiedstreams.h"
+OUTPUT = ROOT / "unifiedstreams" / "_bindings.py"
+
+SCALARS = {
+    "void": "None",
+    "bool": "ctypes.c_bool",
+    "int32_t": "ctypes.c_int32",
+    "uint64_t": "ctypes.c_uint64",
+    "size_t": "ctypes.c_size_t",
+}
+
+
+def ctype(declaration, callbacks, named=True, in_struct=False):
+    """Maps a C type, followed by a name when `named`, to a ctypes expression."""
+    declaration = re.sub(r"\b(const|struct)\b", "", declaration).strip()
+    if named:
+        declaration = re.sub(r"\w+$", "", declaration)
+    pointers = declaration.count("*")
+    base = declaration.replace("*", "").strip()
+
+    if pointers == 0:
+        if base in callbacks:
+            return base
+        return SCALARS[base]
+    if base == "char" or (base == "uint8_t" and not in_struct):
+        return "ctypes.c_char_p"
+    if base == "FfiResult":
+        return "ctypes.POINTER(FfiResult)"
+    return "ctypes.c_void_p"
+
+
+def split_parameters(parameters):
+    parameters = parameters.strip()
+    if parameters in ("", "void"):
+        return []
+    return [parameter.strip() for parameter in parameters.split(",")]
+
+
+def render(header):
+    # Comments are not part of any declaration.
+    header = re.sub(r"/\*.*?\*/|//[^\n]*", "", header, flags=re.S)
+
+    constants = re.findall(r"^#define (FFI_\w+) (-?\d+)$", header, flags=re.M)
+    callbacks = {
+        name: parameters
+        for name, parameters in re.findall(
+            r"typedef void \(\*(\w+)\)\(([^)]*)\);", header
+        )
+    }
+    structs = re.findall(r"typedef struct (\w+) \{(.*?)\} \1;", header, flags=re.S)
+
+    body = header.split('extern "C" {', 1)[1]
+    body = re.sub(r"^#.*$", "", body, flags=re.M)
+    functions = re.findall(r"([\w\s*]+?)\b(\w+)\(([^)]*)\);", body)
+
+    lines = [
+        '"""Generated by generate_bindings.py from include/unifiedstreams.h, do not edit."""',
+        "",
+        "import ctypes",
+        "",
+    ]
+    lines += ["{} = {}".format(name, value) for name, value in constants]
+
+    for name, fields in structs:
+        lines += ["", "", "class {}(ctypes.Structure):".format(name), "    _fields_ = ["]
+        for field in fields.strip().rstrip(";").split(";"):
+            field_name = field.split()[-1].lstrip("*")
+            lines.append(
+                '        ("{}", {}),'.format(field_name, ctype(field, callbacks, in_struct=True))
+            )
+        lines.append("    ]")
+
+    lines.append("")
+    for name, parameters in callbacks.items():
+        arguments = [ctype(p, callbacks) for p in split_parameters(parameters)]
+        lines += ["", "{} = ctypes.CFUNCTYPE(None, {})".format(name, ", ".join(arguments))]
+
+    lines += ["", "FUNCTIONS = {"]
+    for result, name, parameters in functions:
+        arguments = [ctype(p, callbacks) for p in split_parameters(parameters)]
+        lines.append(
+            '    "{}": ({}, [{}]),'.format(
+                name, ctype(result, callbacks, named=False), ", ".join(arguments)
+            )
+        )
+    lines += [
+        "}",
+        "",
+        "",
+        "def bind(library):",
+        '    """Declares the signature of every exported function of `library`."""',
+        "    for name, (restype, argtypes) in FUNCTIONS.items():",
+        "        function = getattr(library, name)",
+        "        function.restype = restype",
+        "        function.argtypes = argtypes",
+        "    return library",
+        "",
+    ]
+    return "\n".join(lines)
+
+
+def main():
+    OUTPUT.write_text(render(HEADER.read_text()))
+    print("Wrote {}".format(OUTPUT))
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,196 @@
+"""Runs the bindings against local servers. Build the crate first, then from `python/`:
+
+    python -m unittest discover tests
+"""
+
+import json
+import os
+import sys
+import tempfile
+import threading
+import unittest
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+from pathlib import Path
+from urllib.parse import parse_qs, urlparse
+
+sys.path.insert(0, str(Path(__file__).resolve().parents[1]))
+
+import generate_bindings  # noqa: E402
+import unifiedstreams  # noqa: E402
+from unifiedstreams.testing import MockSubstreamsServer, mock_package  # noqa: E402
+
+MODULE = "map_events"
+
+
+class Handler(BaseHTTPRequestHandler):
+    def log_message(self, *args):
+        pass
+
+    def reply(self, status, body, headers=()):
+        body = body if isinstance(body, bytes) else json.dumps(body).encode()
+        self.send_response(status)
+        for name, value in headers:
+            self.send_header(name, value)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    def do_POST(self):
+        request = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+        if request["method"] == "eth_fail":
+            error = {"code": -32000, "message": "execution reverted", "data": "0x01"}
+            self.reply(200, {"jsonrpc": "2.0", "id": request["id"], "error": error})
+        else:
+            result = {"method": request["method"], "params": request["params"]}
+            self.reply(200, {"jsonrpc": "2.0", "id": request["id"], "result": result})
+
+    def do_GET(self):
+        url = urlparse(self.path)
+        if url.path == "/items":
+            page = int(parse_qs(url.query).get("page", ["1"])[0])
+            self.reply(200, [page] if page <= 2 else [])
+        elif url.path == "/missing":
+            self.reply(404, b"not here")
+        elif url.path == "/binary":
+            self.reply(200, b"\xff\x00", [("X-Tag", "a"), ("X-Tag", "b")])
+        else:
+            self.reply(200, {"auth": self.headers.get("Authorization")})
+
+
+class BindingsTest(unittest.TestCase):
+    @classmethod
+    def setUpClass(cls):
+        cls.server = ThreadingHTTPServer(("127.0.0.1", 0), Handler)
+        threading.Thread(target=cls.server.serve_forever, daemon=True).start()
+        cls.url = "http://127.0.0.1:{}".format(cls.server.server_address[1])
+
+        cls.directory = tempfile.TemporaryDirectory()
+        cls.package = os.path.join(cls.directory.name, "mock.spkg")
+        with open(cls.package, "wb") as package:
+            package.write(mock_package(MODULE, initial_block=1))
+        os.environ["SUBSTREAMS_API_TOKEN"] = "test"
+
+    @classmethod
+    def tearDownClass(cls):
+        cls.server.shutdown()
+        cls.directory.cleanup()
+
+    def test_bindings_match_the_header(self):
+        header = generate_bindings.HEADER.read_text()
+        self.assertEqual(generate_bindings.render(header), generate_bindings.OUTPUT.read_text())
+
+    def test_substreams_call_returns_outputs(self):
+        sessions = [
+            [
+                {"type": "session", "trace_id": "trace"},
+                {"type": "data", "module": MODULE, "block_num": 1, "output": "0x0a01"},
+                {"type": "data", "module": MODULE, "block_num": 2},
+                {"type": "data", "module": MODULE, "block_num": 3, "output": "0x0a03"},
+            ]
+        ]
+        with MockSubstreamsServer(sessions) as server:
+            outputs = unifiedstreams.substreams_call(server.url, self.package, MODULE, "1:4")
+        self.assertEqual(outputs, [b"\x0a\x01", b"\x0a\x03"])
+
+    def test_substreams_call_drops_undone_outputs(self):
+        sessions = [
+            [
+                {"type": "data", "module": MODULE, "block_num": 1, "output": "0x01"},
+                {"type": "data", "module": MODULE, "block_num": 2, "output": "0x02"},
+                {"type": "undo", "last_valid_block": 1},
+                {"type": "data", "module": MODULE, "block_num": 2, "output": "0x22"},
+            ]
+        ]
+        with MockSubstreamsServer(sessions) as server:
+            outputs = unifiedstreams.substreams_call(server.url, self.package, MODULE, "1:3")
+        self.assertEqual(outputs, [b"\x01", b"\x22"])
+
+    def test_session_yields_blocks_and_resumes_after_disconnect(self):
+        sessions = [
+            [
+                {"type": "data", "module": MODULE, "block_num": 1, "output": "0x01"},
+                {"type": "disconnect"},
+            ],
+            [{"type": "undo", "last_valid_block": 1}],
+        ]
+        with MockSubstreamsServer(sessions) as server:
+            session = unifiedstreams.SubstreamsSession(
+                server.url, self.package, MODULE, token="test"
+            )
+            with session:
+                items = list(session)
+
+        self.assertEqual(
+            items,
+            [
+                unifiedstreams.Block(1, "block-1", "cursor-1", 1, b"\x01"),
+                unifiedstreams.Undo(1, "cursor-1"),
+            ],
+        )
+
+    def test_session_raises_errors(self):
+        sessions = [[{"type": "fatal", "module": MODULE, "reason": "boom"}]]
+        with MockSubstreamsServer(sessions) as server:
+            session = unifiedstreams.SubstreamsSession(
+                server.url, self.package, MODULE, token="test"
+            )
+            with self.assertRaises(unifiedstreams.UnifiedStreamsError) as raised:
+                next(session)
+        self.assertIn("boom", str(raised.exception))
+        self.assertEqual(list(session), [])
+
+        missing = unifiedstreams.SubstreamsSession(self.url, "/no/such.spkg", MODULE)
+        with self.assertRaises(unifiedstreams.UnifiedStreamsError):
+            next(missing)
+
+        with self.assertRaises(unifiedstreams.InvalidArgumentError):
+            unifiedstreams.SubstreamsSession(self.url, None, MODULE)
+
+    def test_mock_server_reports_invalid_sessions(self):
+        with self.assertRaises(unifiedstreams.InvalidArgumentError) as raised:
+            MockSubstreamsServer([[{"type": "teleport"}]])
+        self.assertIn("teleport", raised.exception.message)
+
+    def test_rpc_call(self):
+        result = unifiedstreams.rpc_call(self.url, "eth_getBalance", ["0xabc", "latest"])
+        self.assertEqual(result, {"method": "eth_getBalance", "params": ["0xabc", "latest"]})
+
+        with self.assertRaises(unifiedstreams.JsonRpcError) as raised:
+            unifiedstreams.rpc_call(self.url, "eth_fail")
+        self.assertEqual(raised.exception.code, -32000)
+        self.assertEqual(raised.exception.data, "0x01")
+
+        with self.assertRaises(unifiedstreams.InvalidArgumentError):
+            unifiedstreams.rpc_call(None, "eth_blockNumber")
+
+    def test_api_call_and_request(self):
+        body = unifiedstreams.api_call(self.url + "/me", {"Authorization": "Bearer token"})
+        self.assertEqual(json.loads(body), {"auth": "Bearer token"})
+
+        response = unifiedstreams.api_request(self.url + "/missing")
+        self.assertEqual((response.status, response.text), (404, "not here"))
+
+        response = unifiedstreams.api_request(self.url + "/binary")
+        self.assertEqual(response.body, b"\xff\x00")
+        self.assertEqual(response.headers["x-tag"], ["a", "b"])
+
+        with self.assertRaises(unifiedstreams.InvalidRequestError):
+            unifiedstreams.api_request(self.url, method="GE T")
+        with self.assertRaises(unifiedstreams.TransportError):
+            unifiedstreams.api_request("http://127.0.0.1:1/")
+
+    def test_api_paginate(self):
+        pages = unifiedstreams.api_paginate(self.url + "/items", {"type": "page"})
+        self.assertEqual([page.response.json() for page in pages], [[1], [2], []])
+
+        pages = unifiedstreams.api_paginate(self.url + "/missing", {"type": "page"})
+        with self.assertRaises(unifiedstreams.HttpError) as raised:
+            list(pages)
+        self.assertEqual(raised.exception.status, 404)
+
+        with self.assertRaises(unifiedstreams.InvalidRequestError):
+            list(unifiedstreams.api_paginate(self.url, {"type": "unknown"}))
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,229 @@
+"""Python bindings of the unifiedstreams library, over its C ABI.
+
+Calls block until the library answers, without holding the GIL. Failures raise a
+`UnifiedStreamsError` subclass matching the error kind.
+"""
+
+import ctypes
+import json as _json
+from typing import NamedTuple, Optional
+
+from ._library import END, encode, library, take
+from .errors import (
+    HttpError,
+    InvalidArgumentError,
+    InvalidRequestError,
+    InvalidResponseError,
+    JsonRpcError,
+    NoQuorumError,
+    PanicError,
+    TransportError,
+    UnifiedStreamsError,
+)
+
+__all__ = [
+    "ApiPage",
+    "ApiResponse",
+    "Block",
+    "HttpError",
+    "InvalidArgumentError",
+    "InvalidRequestError",
+    "InvalidResponseError",
+    "JsonRpcError",
+    "NoQuorumError",
+    "PanicError",
+    "SubstreamsSession",
+    "TransportError",
+    "Undo",
+    "UnifiedStreamsError",
+    "api_call",
+    "api_paginate",
+    "api_request",
+    "rpc_call",
+    "substreams_call",
+]
+
+
+def _decode_hex(value):
+    return bytes.fromhex(value[2:] if value.startswith("0x") else value)
+
+
+def substreams_call(endpoint, package, module, range=None):
+    """Streams `module` of `package` (a file, URL or `name@version`) over `range`, e.g.
+    `"17000000:+1000"`, and returns the non-empty outputs as protobuf encoded bytes. The
+    outputs of blocks undone by a fork are dropped."""
+    result = library().substreams_call_ffi(
+        encode(endpoint), encode(package), encode(module), encode(range)
+    )
+    return take(result, arrays=True)
+
+
+class Block(NamedTuple):
+    block_num: int
+    block_id: str
+    cursor: str
+    final_block_height: int
+    output: bytes
+
+
+class Undo(NamedTuple):
+    """The blocks after `last_valid_block` were forked out and must be discarded."""
+
+    last_valid_block: Optional[int]
+    last_valid_cursor: str
+
+
+class SubstreamsSession:
+    """Iterates over the blocks of a Substreams module as they are produced, yielding `Block`
+    and `Undo` items. Pass the `cursor` of the last processed block to resume. Without a
+    `token`, `SUBSTREAMS_API_TOKEN` is used.
+
+        with SubstreamsSession(endpoint, "uniswap-v3@v0.2.10", "map_pools_created") as session:
+            for block in session:
+                ...
+    """
+
+    def __init__(self, endpoint, package, module, range=None, cursor=None, token=None):
+        session = ctypes.c_void_p()
+        self._session = None
+        take(
+            library().substreams_session_open_ffi(
+                encode(endpoint),
+                encode(package),
+                encode(module),
+                encode(range),
+                encode(cursor),
+                encode(token),
+                ctypes.byref(session),
+            )
+        )
+        self._session = session.value
+
+    def __iter__(self):
+        return self
+
+    def __next__(self):
+        if not self._session:
+            raise StopIteration
+
+        try:
+            text = take(library().substreams_session_next_ffi(self._session))
+        except UnifiedStreamsError:
+            self.close()
+            raise
+        if text is END:
+            self.close()
+            raise StopIteration
+
+        item = _json.loads(text)
+        if item["type"] == "undo":
+            return Undo(item["last_valid_block"], item["last_valid_cursor"])
+        return Block(
+            item["block_num"],
+            item["block_id"],
+            item["cursor"],
+            item["final_block_height"],
+            _decode_hex(item["output"]),
+        )
+
+    def close(self):
+        """Stops the stream. Also done when iteration ends or fails."""
+        if self._session:
+            library().free_substreams_session(self._session)
+            self._session = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc_info):
+        self.close()
+
+    def __del__(self):
+        self.close()
+
+
+def rpc_call(endpoint, method, params=None, id=1):
+    """Sends a JSON-RPC request and returns its decoded `result`."""
+    params = _json.dumps([] if params is None else params)
+    result = library().rpc_call_ffi(encode(endpoint), encode(method), encode(params), id)
+    return _json.loads(take(result))
+
+
+def api_call(url, headers=None):
+    """Sends a GET request and returns the response body as text, whatever the status."""
+    headers = None if headers is None else _json.dumps(headers)
+    return take(library().api_call_ffi(encode(url), encode(headers)))
+
+
+class ApiResponse(NamedTuple):
+    status: int
+    # Header names are lower case. A header sent several times has a list of values.
+    headers: dict
+    body: bytes
+
+    @property
+    def text(self):
+        return self.body.decode("utf-8", "replace")
+
+    def json(self):
+        return _json.loads(self.body)
+
+
+def _response(envelope):
+    if envelope.get("body_hex") is not None:
+        body = _decode_hex(envelope["body_hex"])
+    else:
+        body = (envelope.get("body") or "").encode("utf-8")
+    return ApiResponse(envelope["status"], envelope["headers"], body)
+
+
+def _request_options(method, headers, query, json, body):
+    options = {"method": method, "headers": headers, "query": query}
+    if json is not None:
+        options["json"] = json
+    if body is not None:
+        options["body"] = body
+    return _json.dumps({key: value for key, value in options.items() if value is not None})
+
+
+def api_request(url, method="GET", headers=None, query=None, json=None, body=None):
+    """Sends an HTTP request and returns its `ApiResponse`, error statuses included. `json`
+    is sent as a JSON body, `body` as a raw string body. Query values may be lists to repeat
+    a parameter."""
+    options = _request_options(method, headers, query, json, body)
+    return _response(_json.loads(take(library().api_request_ffi(encode(url), encode(options)))))
+
+
+class ApiPage(NamedTuple):
+    page: int
+    url: str
+    response: ApiResponse
+
+
+def api_paginate(
+    url, pagination, method="GET", headers=None, query=None, json=None, body=None, max_pages=0
+):
+    """Yields the `ApiPage` of a paginated API, requesting each page as it is reached.
+    `pagination` is a dict such as `{"type": "page", "size": 100}`, `{"type": "offset"}`,
+    `{"type": "cursor", "next": "/meta/next"}` or `{"type": "link"}`. A page answered with an
+    error status raises `HttpError`."""
+    options = _request_options(method, headers, query, json, body)
+    pages = ctypes.c_void_p()
+    take(
+        library().api_paginate_ffi(
+            encode(url),
+            encode(options),
+            encode(_json.dumps(pagination)),
+            max_pages,
+            ctypes.byref(pages),
+        )
+    )
+    try:
+        while True:
+            text = take(library().api_page_next_ffi(pages))
+            if text is END:
+                return
+            page = _json.loads(text)
+            yield ApiPage(page["page"], page["url"], _response(page))
+    finally:
+        library().free_api_page_iterator(pages)
@@ -0,0 +1,104 @@
+"""Generated by generate_bindings.py from include/unifiedstreams.h, do not edit."""
+
+import ctypes
+
+FFI_OK = 0
+FFI_ERROR = 1
+FFI_INVALID_ARGUMENT = 2
+FFI_PANIC = 3
+FFI_END = 4
+FFI_CANCELLED = 5
+
+
+class FfiResult(ctypes.Structure):
+    _fields_ = [
+        ("status", ctypes.c_int32),
+        ("data", ctypes.c_void_p),
+        ("length", ctypes.c_size_t),
+        ("error", ctypes.c_char_p),
+    ]
+
+
+class FfiByteArray(ctypes.Structure):
+    _fields_ = [
+        ("data", ctypes.c_void_p),
+        ("length", ctypes.c_size_t),
+    ]
+
+
+FfiCompletionCallback = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.POINTER(FfiResult))
+
+WsSubscriptionCallback = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.c_char_p, ctypes.c_int32)
+
+ApiStreamCallback = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.c_char_p, ctypes.c_int32)
+
+FUNCTIONS = {
+    "last_panic_ffi": (ctypes.POINTER(FfiResult), []),
+    "async_request_cancel_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "free_async_request": (None, [ctypes.c_void_p]),
+    "substreams_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "substreams_call_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "firehose_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_bool]),
+    "firehose_call_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_bool, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "firehose_fetch_block_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_uint64, ctypes.c_char_p, ctypes.c_char_p]),
+    "substreams_session_open_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_void_p]),
+    "substreams_session_next_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "free_substreams_session": (None, [ctypes.c_void_p]),
+    "free_ffi_result": (None, [ctypes.POINTER(FfiResult)]),
+    "rpc_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_int32]),
+    "rpc_call_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_int32, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "rpc_batch_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_size_t]),
+    "rpc_batch_call_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_size_t, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "rpc_group_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "rpc_group_call_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "rpc_group_health_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p]),
+    "eth_block_number_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p]),
+    "eth_chain_id_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p]),
+    "eth_get_block_by_number_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_bool]),
+    "eth_get_logs_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p]),
+    "eth_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "eth_get_transaction_receipt_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p]),
+    "http_client_configure_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p]),
+    "response_cache_configure_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p]),
+    "endpoint_pool_configure_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p]),
+    "response_cache_clear_ffi": (ctypes.POINTER(FfiResult), []),
+    "ws_subscribe_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, WsSubscriptionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "ws_unsubscribe_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "api_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p]),
+    "api_call_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "api_request_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p]),
+    "api_request_async_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, FfiCompletionCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "api_paginate_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_size_t, ctypes.c_void_p]),
+    "api_page_next_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "free_api_page_iterator": (None, [ctypes.c_void_p]),
+    "api_stream_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ApiStreamCallback, ctypes.c_void_p, ctypes.c_void_p]),
+    "api_stream_close_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "sink_deploy_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_bool]),
+    "sink_update_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_bool]),
+    "sink_list_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p]),
+    "sink_deployment_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "sink_wait_for_status_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_uint64, ctypes.c_uint64]),
+    "substreams_entities_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_void_p]),
+    "substreams_store_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "entity_store_new_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "entity_store_apply_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p, ctypes.c_uint64, ctypes.c_char_p, ctypes.c_size_t]),
+    "entity_store_revert_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p, ctypes.c_uint64]),
+    "entity_store_finalize_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p, ctypes.c_uint64]),
+    "entity_store_get_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "entity_store_list_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p, ctypes.c_char_p]),
+    "free_entity_store": (None, [ctypes.c_void_p]),
+    "substreams_sqlite_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p]),
+    "substreams_file_sink_call_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_uint64]),
+    "mock_substreams_server_start_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_char_p, ctypes.c_void_p]),
+    "mock_substreams_server_url_ffi": (ctypes.POINTER(FfiResult), [ctypes.c_void_p]),
+    "free_mock_substreams_server": (None, [ctypes.c_void_p]),
+}
+
+
+def bind(library):
+    """Declares the signature of every exported function of `library`."""
+    for name, (restype, argtypes) in FUNCTIONS.items():
+        function = getattr(library, name)
+        function.restype = restype
+        function.argtypes = argtypes
+    return library
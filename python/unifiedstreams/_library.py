@@ -0,0 +1,79 @@
+"""Loads the shared library and turns its `FfiResult` into Python values."""
+
+import ctypes
+import os
+import sys
+import threading
+from pathlib import Path
+
+from . import _bindings
+from .errors import error_from_json
+
+LIBRARY_ENV = "UNIFIEDSTREAMS_LIBRARY"
+
+_lock = threading.Lock()
+_library = None
+
+# Returned by `take` for an `FFI_END` result.
+END = object()
+
+
+def _library_name():
+    if sys.platform == "darwin":
+        return "libunifiedstreams.dylib"
+    if sys.platform == "win32":
+        return "unifiedstreams.dll"
+    return "libunifiedstreams.so"
+
+
+def find_library():
+    """The path in `UNIFIEDSTREAMS_LIBRARY`, else the library shipped in the package, else
+    the most recent build of the repository the package sits in."""
+    path = os.environ.get(LIBRARY_ENV)
+    if path:
+        return path
+
+    name = _library_name()
+    package = Path(__file__).resolve().parent
+    if (package / name).exists():
+        return str(package / name)
+
+    target = package.parents[1] / "target"
+    builds = [target / profile / name for profile in ("release", "debug")]
+    builds = [build for build in builds if build.exists()]
+    if not builds:
+        raise OSError(
+            "{} not found, build the crate or set {}".format(name, LIBRARY_ENV)
+        )
+    return str(max(builds, key=lambda build: build.stat().st_mtime))
+
+
+def library():
+    """The loaded library, loaded on first use."""
+    global _library
+    with _lock:
+        if _library is None:
+            _library = _bindings.bind(ctypes.CDLL(find_library()))
+        return _library
+
+
+def encode(value):
+    """A string argument, `None` being passed as a null pointer."""
+    return None if value is None else value.encode("utf-8")
+
+
+def take(result, arrays=False):
+    """Reads and releases an `FfiResult`. Returns its text, or its byte arrays when `arrays`,
+    or `END`. Raises the error of a failed result."""
+    try:
+        contents = result.contents
+        if contents.status == _bindings.FFI_OK:
+            if not arrays:
+                return ctypes.string_at(contents.data, contents.length).decode("utf-8")
+            items = (_bindings.FfiByteArray * contents.length).from_address(contents.data)
+            return [ctypes.string_at(item.data, item.length) for item in items]
+        if contents.status == _bindings.FFI_END:
+            return END
+        raise error_from_json((contents.error or b"").decode("utf-8", "replace"))
+    finally:
+        library().free_ffi_result(result)
@@ -0,0 +1,78 @@
+"""Exceptions raised for the errors reported by the library."""
+
+import json
+
+
+class UnifiedStreamsError(Exception):
+    """A call failed. `kind` is the error kind of the library, `details` the whole error."""
+
+    def __init__(self, kind, message, details=None):
+        super().__init__(message)
+        self.kind = kind
+        self.message = message
+        self.details = details or {}
+
+
+class InvalidArgumentError(UnifiedStreamsError, ValueError):
+    """An argument was rejected before anything was sent."""
+
+
+class InvalidRequestError(UnifiedStreamsError, ValueError):
+    """The request could not be built, e.g. from malformed JSON options."""
+
+
+class TransportError(UnifiedStreamsError, ConnectionError):
+    """No response was received."""
+
+
+class HttpError(UnifiedStreamsError):
+    """The server answered with an error status."""
+
+    def __init__(self, kind, message, details=None):
+        super().__init__(kind, message, details)
+        self.status = self.details.get("status")
+        self.body = self.details.get("body")
+
+
+class JsonRpcError(UnifiedStreamsError):
+    """The JSON-RPC response holds an error object."""
+
+    def __init__(self, kind, message, details=None):
+        super().__init__(kind, message, details)
+        self.code = self.details.get("code")
+        self.data = self.details.get("data")
+
+
+class InvalidResponseError(UnifiedStreamsError):
+    """The response could not be understood."""
+
+
+class NoQuorumError(UnifiedStreamsError):
+    """Not enough providers of a group agreed on a result."""
+
+
+class PanicError(UnifiedStreamsError):
+    """The library panicked, which is a bug to report."""
+
+
+KINDS = {
+    "invalid_argument": InvalidArgumentError,
+    "invalid_request": InvalidRequestError,
+    "transport": TransportError,
+    "http": HttpError,
+    "json_rpc": JsonRpcError,
+    "invalid_response": InvalidResponseError,
+    "no_quorum": NoQuorumError,
+    "panic": PanicError,
+}
+
+
+def error_from_json(text):
+    """Builds the exception matching the `{"kind", "message", ...}` error of a result."""
+    try:
+        details = json.loads(text)
+    except ValueError:
+        details = {"kind": "failed", "message": text}
+
+    kind = details.get("kind", "failed")
+    return KINDS.get(kind, UnifiedStreamsError)(kind, details.get("message", ""), details)
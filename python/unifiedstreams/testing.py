@@ -0,0 +1,77 @@
+"""Helpers to test Substreams code without a provider."""
+
+import ctypes
+import json
+
+from ._library import encode, library, take
+
+
+class MockSubstreamsServer:
+    """A local Substreams server replaying scripted sessions, one per connection. A session
+    is a list of events:
+
+        {"type": "session", "trace_id": "trace"}
+        {"type": "progress"}
+        {"type": "data", "module": "map_events", "block_num": 1, "output": "0x0a01"}
+        {"type": "undo", "last_valid_block": 1}
+        {"type": "fatal", "module": "map_events", "reason": "..."}
+        {"type": "disconnect"}
+        {"type": "delay", "ms": 100}
+
+    The cursor of block `n` is `cursor-<n>`. Calls need a token, of any value, passed or set
+    in `SUBSTREAMS_API_TOKEN`.
+    """
+
+    def __init__(self, sessions):
+        server = ctypes.c_void_p()
+        self._server = None
+        take(
+            library().mock_substreams_server_start_ffi(
+                encode(json.dumps(sessions)), ctypes.byref(server)
+            )
+        )
+        self._server = server.value
+        self.url = take(library().mock_substreams_server_url_ffi(self._server))
+
+    def close(self):
+        if self._server:
+            library().free_mock_substreams_server(self._server)
+            self._server = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc_info):
+        self.close()
+
+    def __del__(self):
+        self.close()
+
+
+def _varint(value):
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def _field(number, payload):
+    if isinstance(payload, int):
+        return _varint(number << 3) + _varint(payload)
+    if isinstance(payload, str):
+        payload = payload.encode("utf-8")
+    return _varint(number << 3 | 2) + _varint(len(payload)) + payload
+
+
+def mock_package(module, initial_block=0, output_type="proto:mock.Output"):
+    """An encoded `sf.substreams.v1.Package` holding a single map module, enough for a
+    `MockSubstreamsServer`. Write it to a `.spkg` file to pass it as the package."""
+    kind_map = _field(1, output_type)
+    module = _field(1, module) + _field(2, kind_map) + _field(8, initial_block)
+    modules = _field(1, module)
+    return _field(6, modules)
@@ -0,0 +1,389 @@
+use reqwest::{Method, Request, Url};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+use crate::eth::encode_data;
+use crate::http_client::HttpClient;
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("request failed: {0}")]
+    Transport(String),
+    // Only returned where a response has to be understood, e.g. when paginating.
+    #[error("HTTP status {status}: {body}")]
+    Http { status: u16, body: String },
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+}
+
+impl ApiError {
+    pub fn to_json(&self) -> Value {
+        match self {
+            ApiError::InvalidRequest(_) => json!({
+                "kind": "invalid_request",
+                "message": self.to_string(),
+            }),
+            ApiError::Transport(_) => json!({
+                "kind": "transport",
+                "message": self.to_string(),
+            }),
+            ApiError::Http { status, body } => json!({
+                "kind": "http",
+                "message": self.to_string(),
+                "status": status,
+                "body": body,
+            }),
+            ApiError::InvalidResponse(_) => json!({
+                "kind": "invalid_response",
+                "message": self.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiBody {
+    None,
+    // Sent with `Content-Type: application/json` unless a header overrides it.
+    Json(Value),
+    Raw(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    pub body: ApiBody,
+}
+
+// Header and query values may be strings, numbers or booleans. An array repeats the query
+// parameter once per value.
+fn string_values(key: &str, value: &Value) -> Result<Vec<String>, ApiError> {
+    match value {
+        Value::String(s) => Ok(vec![s.clone()]),
+        Value::Number(n) => Ok(vec![n.to_string()]),
+        Value::Bool(b) => Ok(vec![b.to_string()]),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| string_values(key, value))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|values| values.concat()),
+        Value::Null => Ok(Vec::new()),
+        Value::Object(_) => Err(ApiError::InvalidRequest(format!(
+            "value of '{}' must be a string, a number or a boolean",
+            key
+        ))),
+    }
+}
+
+fn string_pairs(options: &Value, key: &str) -> Result<Vec<(String, String)>, ApiError> {
+    match &options[key] {
+        Value::Null => Ok(Vec::new()),
+        Value::Object(map) => {
+            let mut pairs = Vec::new();
+            for (name, value) in map {
+                for value in string_values(name, value)? {
+                    pairs.push((name.clone(), value));
+                }
+            }
+            Ok(pairs)
+        }
+        _ => Err(ApiError::InvalidRequest(format!(
+            "'{}' must be a JSON object",
+            key
+        ))),
+    }
+}
+
+impl ApiRequest {
+    // A GET request, defaulting to `https://` when no scheme is given.
+    pub fn get(url: &str) -> Self {
+        let url = if url.starts_with("http") {
+            url.to_string()
+        } else {
+            format!("https://{}", url)
+        };
+
+        ApiRequest {
+            method: Method::GET,
+            url,
+            headers: Vec::new(),
+            query: Vec::new(),
+            body: ApiBody::None,
+        }
+    }
+
+    // Reads `{"method", "headers", "query", "json", "body"}`, every key being optional:
+    // `json` is sent as a JSON body, `body` as a raw string body.
+    pub fn from_json(url: &str, options: &Value) -> Result<Self, ApiError> {
+        if !options.is_object() && !options.is_null() {
+            return Err(ApiError::InvalidRequest(
+                "request options must be a JSON object".to_string(),
+            ));
+        }
+
+        let method = match options["method"].as_str() {
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|_| {
+                ApiError::InvalidRequest(format!("invalid HTTP method '{}'", method))
+            })?,
+            None => Method::GET,
+        };
+
+        let body = match (&options["json"], &options["body"]) {
+            (Value::Null, Value::Null) => ApiBody::None,
+            (json, Value::Null) => ApiBody::Json(json.clone()),
+            (Value::Null, Value::String(body)) => ApiBody::Raw(body.clone().into_bytes()),
+            (Value::Null, _) => {
+                return Err(ApiError::InvalidRequest(
+                    "'body' must be a string, use 'json' for JSON bodies".to_string(),
+                ))
+            }
+            _ => {
+                return Err(ApiError::InvalidRequest(
+                    "'json' and 'body' cannot both be set".to_string(),
+                ))
+            }
+        };
+
+        Ok(ApiRequest {
+            method,
+            headers: string_pairs(options, "headers")?,
+            query: string_pairs(options, "query")?,
+            body,
+            ..ApiRequest::get(url)
+        })
+    }
+
+    // The URL with the query parameters appended.
+    pub fn full_url(&self) -> Result<Url, ApiError> {
+        let mut url = Url::parse(&self.url)
+            .map_err(|e| ApiError::InvalidRequest(format!("invalid URL '{}': {}", self.url, e)))?;
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.query);
+        }
+        Ok(url)
+    }
+
+    pub fn build(&self, client: &HttpClient) -> Result<Request, ApiError> {
+        let mut builder = client
+            .client()
+            .request(self.method.clone(), self.full_url()?);
+        // Headers go first, `json` only sets `Content-Type` when none was given.
+        for (key, value) in &self.headers {
+            builder = builder.header(key, value);
+        }
+        builder = match &self.body {
+            ApiBody::None => builder,
+            ApiBody::Json(json) => builder.json(json),
+            ApiBody::Raw(body) => builder.body(body.clone()),
+        };
+
+        builder
+            .build()
+            .map_err(|e| ApiError::InvalidRequest(format!("{}", e)))
+    }
+
+    // GET, HEAD, PUT, DELETE and OPTIONS can be retried on 502/503/504 and connection
+    // errors, POST and PATCH only on 429.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self.method,
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiResponse {
+    pub status: u16,
+    // In the order received, a header sent several times appears several times.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ApiResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    // Value of the first `name` header, case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // `{"status", "headers", "body"}`. Repeated headers become arrays. A body that is not
+    // UTF-8 is given as `0x` prefixed `body_hex` instead of `body`.
+    pub fn to_json(&self) -> Value {
+        let mut headers = Map::new();
+        for (key, value) in &self.headers {
+            match headers.get_mut(key) {
+                Some(Value::Array(values)) => values.push(json!(value)),
+                Some(existing) => *existing = json!([existing.clone(), value]),
+                None => {
+                    headers.insert(key.clone(), json!(value));
+                }
+            }
+        }
+
+        let mut envelope = json!({
+            "status": self.status,
+            "headers": headers,
+        });
+        match std::str::from_utf8(&self.body) {
+            Ok(body) => envelope["body"] = json!(body),
+            Err(_) => {
+                envelope["body"] = Value::Null;
+                envelope["body_hex"] = json!(encode_data(&self.body));
+            }
+        }
+        envelope
+    }
+}
+
+// Sends the request through `client`. Error statuses are returned as responses, only
+// requests that got no response at all fail.
+pub async fn send_api_request(
+    client: &HttpClient,
+    request: &ApiRequest,
+) -> Result<ApiResponse, ApiError> {
+    let built = request.build(client)?;
+    let response = client
+        .send(built, request.is_idempotent())
+        .await
+        .map_err(|e| ApiError::Transport(format!("{}", e)))?;
+
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(key, value)| {
+            (
+                key.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| ApiError::Transport(format!("read response: {}", e)))?
+        .to_vec();
+
+    Ok(ApiResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    // Answers with a 201 echoing the raw request it received.
+    async fn serve_echo() -> String {
+        let server = MockHttpServer::start(|request, _| {
+            MockHttpResponse::new("201 Created", &["X-Echo: 1", "X-Echo: 2"], request.raw())
+        })
+        .await;
+        server.url()
+    }
+
+    #[tokio::test]
+    async fn sends_method_query_headers_and_body() {
+        let url = serve_echo().await;
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let request = ApiRequest::from_json(
+            &format!("{}/items", url),
+            &json!({
+                "method": "post",
+                "headers": {"X-Api-Key": "secret", "X-Version": 2},
+                "query": {"tag": ["a", "b"], "limit": 10},
+                "json": {"name": "item"},
+            }),
+        )
+        .unwrap();
+        assert!(!request.is_idempotent());
+
+        let response = send_api_request(&client, &request).await.unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("x-echo"), Some("1"));
+
+        let echoed = response.text();
+        assert!(echoed.starts_with("POST /items?limit=10&tag=a&tag=b HTTP/1.1\r\n"));
+        assert!(echoed.contains("x-api-key: secret\r\n"));
+        assert!(echoed.contains("x-version: 2\r\n"));
+        assert!(echoed.contains("content-type: application/json\r\n"));
+        assert!(echoed.ends_with("\r\n\r\n{\"name\":\"item\"}"));
+
+        let envelope = response.to_json();
+        assert_eq!(envelope["status"], json!(201));
+        assert_eq!(envelope["headers"]["x-echo"], json!(["1", "2"]));
+    }
+
+    #[tokio::test]
+    async fn user_content_type_replaces_the_json_one() {
+        let url = serve_echo().await;
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let request = ApiRequest::from_json(
+            &url,
+            &json!({
+                "method": "post",
+                "headers": {"Content-Type": "application/vnd.api+json"},
+                "json": {"name": "item"},
+            }),
+        )
+        .unwrap();
+
+        let echoed = send_api_request(&client, &request).await.unwrap().text();
+        assert_eq!(echoed.matches("content-type: ").count(), 1);
+        assert!(echoed.contains("content-type: application/vnd.api+json\r\n"));
+    }
+
+    #[test]
+    fn binary_bodies_are_hex_encoded() {
+        let response = ApiResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: vec![0xff, 0x00],
+        };
+        let envelope = response.to_json();
+        assert_eq!(envelope["body"], Value::Null);
+        assert_eq!(envelope["body_hex"], json!("0xff00"));
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(ApiRequest::from_json("example.com", &json!({"json": {}, "body": "x"})).is_err());
+        assert!(ApiRequest::from_json("example.com", &json!({"headers": {"a": {}}})).is_err());
+        assert!(ApiRequest::from_json("example.com", &json!({"method": "GE T"})).is_err());
+
+        let request = ApiRequest::from_json("example.com", &json!({"method": "delete"})).unwrap();
+        assert_eq!(request.method, Method::DELETE);
+        assert_eq!(request.url, "https://example.com");
+        assert!(request.is_idempotent());
+    }
+}
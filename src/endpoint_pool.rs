@@ -0,0 +1,422 @@
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Error};
+use http::{uri::Scheme, Uri};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tokio::{net::TcpStream, time::timeout};
+use tonic::codegen::http;
+
+use crate::substreams::SubstreamsEndpoint;
+
+const DEFAULT_MAX_CONSECUTIVE_FAILURES: u32 = 3;
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    // Applied to the pools opened by `substreams_call` and `firehose_call`.
+    static ref SHARED_POOL_CONFIG: RwLock<EndpointPoolConfig> =
+        RwLock::new(EndpointPoolConfig::default());
+}
+
+pub fn shared_pool_config() -> EndpointPoolConfig {
+    SHARED_POOL_CONFIG.read().unwrap().clone()
+}
+
+// Replaces the shared configuration, pools already opened keep the previous one.
+pub fn configure_shared_pool(config: EndpointPoolConfig) {
+    *SHARED_POOL_CONFIG.write().unwrap() = config;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointPoolConfig {
+    pub max_consecutive_failures: u32,
+    pub prefer_lowest_latency: bool,
+}
+
+impl Default for EndpointPoolConfig {
+    fn default() -> Self {
+        EndpointPoolConfig {
+            max_consecutive_failures: DEFAULT_MAX_CONSECUTIVE_FAILURES,
+            prefer_lowest_latency: false,
+        }
+    }
+}
+
+impl EndpointPoolConfig {
+    // Parses a JSON configuration, every key is optional:
+    // `{"max_consecutive_failures", "prefer_lowest_latency"}`
+    pub fn from_json(input: &str) -> Result<Self, Error> {
+        let value: Value =
+            serde_json::from_str(input).context("Invalid JSON for endpoint pool config")?;
+        if !value.is_object() {
+            return Err(anyhow!("Endpoint pool config must be a JSON object"));
+        }
+
+        let mut config = EndpointPoolConfig::default();
+        if let Some(max_consecutive_failures) = value.get("max_consecutive_failures") {
+            config.max_consecutive_failures = max_consecutive_failures
+                .as_u64()
+                .filter(|max| *max > 0)
+                .ok_or_else(|| anyhow!("'max_consecutive_failures' must be a positive number"))?
+                as u32;
+        }
+        if let Some(prefer_lowest_latency) = value.get("prefer_lowest_latency") {
+            config.prefer_lowest_latency = prefer_lowest_latency
+                .as_bool()
+                .ok_or_else(|| anyhow!("'prefer_lowest_latency' must be a boolean"))?;
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EndpointHealth {
+    pub consecutive_failures: u32,
+    pub latency: Option<Duration>,
+    pub healthy: bool,
+    // The error of the latest failed health check or connection, cleared on success.
+    pub last_error: Option<String>,
+}
+
+struct PooledEndpoint {
+    endpoint: Arc<SubstreamsEndpoint>,
+    health: Mutex<EndpointHealth>,
+}
+
+// A group of Substreams providers serving the same network. `SubstreamsStream` always
+// asks the pool for the endpoint to connect to, so failing over to another provider
+// keeps the stream's latest cursor and resumes exactly where the previous one stopped.
+pub struct SubstreamsEndpointPool {
+    endpoints: Vec<PooledEndpoint>,
+    active: Mutex<usize>,
+    max_consecutive_failures: u32,
+    prefer_lowest_latency: bool,
+}
+
+impl SubstreamsEndpointPool {
+    pub fn new(endpoints: Vec<Arc<SubstreamsEndpoint>>) -> Result<Self, Error> {
+        if endpoints.is_empty() {
+            return Err(anyhow!("endpoint pool requires at least one endpoint"));
+        }
+
+        Ok(SubstreamsEndpointPool {
+            endpoints: endpoints
+                .into_iter()
+                .map(|endpoint| PooledEndpoint {
+                    endpoint,
+                    health: Mutex::new(EndpointHealth {
+                        healthy: true,
+                        ..Default::default()
+                    }),
+                })
+                .collect(),
+            active: Mutex::new(0),
+            max_consecutive_failures: DEFAULT_MAX_CONSECUTIVE_FAILURES,
+            prefer_lowest_latency: false,
+        })
+    }
+
+    pub async fn from_urls<S: AsRef<str>>(
+        urls: &[S],
+        token: Option<String>,
+    ) -> Result<Self, Error> {
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            endpoints.push(Arc::new(SubstreamsEndpoint::new(url, token.clone()).await?));
+        }
+
+        Self::new(endpoints)
+    }
+
+    pub fn single(endpoint: Arc<SubstreamsEndpoint>) -> Self {
+        Self::new(vec![endpoint]).expect("a single endpoint pool is never empty")
+    }
+
+    // Number of consecutive connection errors tolerated on the active endpoint before
+    // switching to the next healthy one.
+    pub fn with_max_consecutive_failures(mut self, max_consecutive_failures: u32) -> Self {
+        self.max_consecutive_failures = max_consecutive_failures.max(1);
+        self
+    }
+
+    // When enabled, `health_check` makes the lowest-latency healthy endpoint the active one.
+    pub fn with_prefer_lowest_latency(mut self, prefer_lowest_latency: bool) -> Self {
+        self.prefer_lowest_latency = prefer_lowest_latency;
+        self
+    }
+
+    pub fn with_config(self, config: &EndpointPoolConfig) -> Self {
+        self.with_max_consecutive_failures(config.max_consecutive_failures)
+            .with_prefer_lowest_latency(config.prefer_lowest_latency)
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    pub fn current(&self) -> Arc<SubstreamsEndpoint> {
+        let active = *self.active.lock().unwrap();
+        self.endpoints[active].endpoint.clone()
+    }
+
+    pub fn health(&self) -> Vec<(String, EndpointHealth)> {
+        self.endpoints
+            .iter()
+            .map(|e| (e.endpoint.uri.clone(), e.health.lock().unwrap().clone()))
+            .collect()
+    }
+
+    pub fn report_success(&self, endpoint: &SubstreamsEndpoint) {
+        if let Some(pooled) = self.find(endpoint) {
+            let mut health = pooled.health.lock().unwrap();
+            health.consecutive_failures = 0;
+            health.healthy = true;
+            health.last_error = None;
+        }
+    }
+
+    // Records a connection or stream error against `endpoint` and fails over to the next
+    // healthy endpoint once the failure threshold is reached. Returns true when the active
+    // endpoint changed.
+    pub fn report_failure(&self, endpoint: &SubstreamsEndpoint, error: impl Display) -> bool {
+        let Some(index) = self.index_of(endpoint) else {
+            return false;
+        };
+
+        {
+            let mut health = self.endpoints[index].health.lock().unwrap();
+            health.last_error = Some(format!("{:#}", error));
+            health.consecutive_failures += 1;
+            if health.consecutive_failures < self.max_consecutive_failures {
+                return false;
+            }
+            health.healthy = false;
+        }
+
+        let mut active = self.active.lock().unwrap();
+        if *active != index {
+            return false;
+        }
+
+        let next = self.next_candidate(index);
+        if next == index {
+            return false;
+        }
+
+        // Give the endpoint we leave a fresh budget so it can be picked again once the
+        // others have failed too.
+        self.endpoints[index]
+            .health
+            .lock()
+            .unwrap()
+            .consecutive_failures = 0;
+        *active = next;
+        true
+    }
+
+    // Probes every endpoint with a TCP connect, recording reachability and latency. When
+    // latency preference is enabled the fastest healthy endpoint becomes the active one.
+    pub async fn health_check(&self) {
+        for pooled in &self.endpoints {
+            let latency = probe(&pooled.endpoint.uri).await;
+
+            let mut health = pooled.health.lock().unwrap();
+            match latency {
+                Ok(latency) => {
+                    health.latency = Some(latency);
+                    health.healthy = true;
+                    health.last_error = None;
+                }
+                Err(e) => {
+                    health.latency = None;
+                    health.healthy = false;
+                    health.last_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+
+        let mut active = self.active.lock().unwrap();
+        let best = if self.prefer_lowest_latency {
+            self.endpoints
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| {
+                    let health = e.health.lock().unwrap();
+                    match (health.healthy, health.latency) {
+                        (true, Some(latency)) => Some((i, latency)),
+                        _ => None,
+                    }
+                })
+                .min_by_key(|(_, latency)| *latency)
+                .map(|(i, _)| i)
+        } else if !self.endpoints[*active].health.lock().unwrap().healthy {
+            Some(self.next_candidate(*active))
+        } else {
+            None
+        };
+
+        if let Some(best) = best {
+            *active = best;
+        }
+    }
+
+    fn next_candidate(&self, from: usize) -> usize {
+        let len = self.endpoints.len();
+        (1..len)
+            .map(|offset| (from + offset) % len)
+            .find(|i| self.endpoints[*i].health.lock().unwrap().healthy)
+            // Nobody is known to be healthy, simply rotate so we keep trying everybody.
+            .unwrap_or((from + 1) % len)
+    }
+
+    fn index_of(&self, endpoint: &SubstreamsEndpoint) -> Option<usize> {
+        self.endpoints
+            .iter()
+            .position(|e| std::ptr::eq(e.endpoint.as_ref(), endpoint))
+    }
+
+    fn find(&self, endpoint: &SubstreamsEndpoint) -> Option<&PooledEndpoint> {
+        self.index_of(endpoint).map(|i| &self.endpoints[i])
+    }
+}
+
+async fn probe(uri: &str) -> Result<Duration, Error> {
+    let uri = uri.parse::<Uri>()?;
+    let host = uri
+        .host()
+        .ok_or_else(|| anyhow!("endpoint {} has no host", uri))?;
+    let port = uri.port_u16().unwrap_or_else(|| {
+        if uri.scheme() == Some(&Scheme::HTTPS) {
+            443
+        } else {
+            80
+        }
+    });
+
+    let started = Instant::now();
+    timeout(HEALTH_CHECK_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| anyhow!("timed out after {:?}", HEALTH_CHECK_TIMEOUT))??;
+
+    Ok(started.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http_server::closed_url;
+    use tokio::net::TcpListener;
+
+    async fn pool(urls: &[String]) -> SubstreamsEndpointPool {
+        SubstreamsEndpointPool::from_urls(urls, None).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn fails_over_once_failures_reach_the_threshold() {
+        let urls = vec![closed_url().await, closed_url().await];
+        let pool = pool(&urls).await.with_max_consecutive_failures(2);
+        let first = pool.current();
+
+        assert!(!pool.report_failure(&first, "connection refused"));
+        assert_eq!(
+            pool.health()[0].1.last_error.as_deref(),
+            Some("connection refused")
+        );
+        pool.report_success(&first);
+        assert!(pool.health()[0].1.last_error.is_none());
+        assert!(!pool.report_failure(&first, "connection refused"));
+        assert_eq!(pool.health()[0].1.consecutive_failures, 1);
+
+        assert!(pool.report_failure(&first, "connection refused"));
+        let second = pool.current();
+        assert_eq!(second.uri, pool.health()[1].0);
+        assert!(!pool.health()[0].1.healthy);
+        assert_eq!(pool.health()[0].1.consecutive_failures, 0);
+
+        // Failures of an endpoint that is not the active one never switch.
+        assert!(!pool.report_failure(&first, "connection refused"));
+        assert!(!pool.report_failure(&first, "connection refused"));
+        assert_eq!(pool.current().uri, second.uri);
+    }
+
+    #[tokio::test]
+    async fn single_endpoint_never_fails_over() {
+        let pool = pool(&[closed_url().await])
+            .await
+            .with_max_consecutive_failures(1);
+        let endpoint = pool.current();
+
+        assert!(!pool.report_failure(&endpoint, "connection refused"));
+        assert_eq!(pool.current().uri, endpoint.uri);
+        assert!(SubstreamsEndpointPool::new(vec![]).is_err());
+    }
+
+    #[tokio::test]
+    async fn health_check_moves_away_from_unreachable_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = format!("http://{}", listener.local_addr().unwrap());
+        let pool = pool(&[closed_url().await, reachable.clone()]).await;
+
+        pool.health_check().await;
+
+        let health = pool.health();
+        assert!(!health[0].1.healthy);
+        assert!(health[0].1.latency.is_none());
+        assert!(health[0].1.last_error.is_some());
+        assert!(health[1].1.healthy);
+        assert!(health[1].1.latency.is_some());
+        assert!(health[1].1.last_error.is_none());
+        assert_eq!(pool.current().uri, health[1].0);
+    }
+
+    #[tokio::test]
+    async fn health_check_prefers_the_fastest_endpoint_when_asked() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = format!("http://{}", listener.local_addr().unwrap());
+        let pool = pool(&[closed_url().await, closed_url().await, reachable])
+            .await
+            .with_prefer_lowest_latency(true);
+
+        pool.health_check().await;
+
+        assert_eq!(pool.current().uri, pool.health()[2].0);
+    }
+
+    #[tokio::test]
+    async fn configuration_is_applied_to_pools() {
+        let config = EndpointPoolConfig::from_json(
+            r#"{"max_consecutive_failures": 1, "prefer_lowest_latency": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            EndpointPoolConfig {
+                max_consecutive_failures: 1,
+                prefer_lowest_latency: true,
+            }
+        );
+        assert_eq!(
+            EndpointPoolConfig::from_json("{}").unwrap(),
+            EndpointPoolConfig::default()
+        );
+        assert!(EndpointPoolConfig::from_json(r#"{"max_consecutive_failures": 0}"#).is_err());
+        assert!(EndpointPoolConfig::from_json(r#"{"prefer_lowest_latency": "yes"}"#).is_err());
+        assert!(EndpointPoolConfig::from_json("[]").is_err());
+
+        let pool = pool(&[closed_url().await, closed_url().await])
+            .await
+            .with_config(&config);
+        let first = pool.current();
+        assert!(pool.report_failure(&first, "connection refused"));
+        assert!(pool.prefer_lowest_latency);
+    }
+}
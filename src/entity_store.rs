@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Context, Error};
+use prost::Message;
+use serde_json::{json, Value as JsonValue};
+
+use crate::pb::sf::substreams::entity::v1::{
+    entity_change::Operation, value::Typed, EntityChange, EntityChanges, Value,
+};
+
+// A decoded `sf.substreams.entity.v1.Value`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityValue {
+    Int32(i32),
+    BigDecimal(String),
+    BigInt(String),
+    String(String),
+    Bytes(String),
+    Bool(bool),
+    Array(Vec<EntityValue>),
+    Null,
+}
+
+impl From<&Value> for EntityValue {
+    fn from(value: &Value) -> Self {
+        match &value.typed {
+            Some(Typed::Int32(v)) => EntityValue::Int32(*v),
+            Some(Typed::Bigdecimal(v)) => EntityValue::BigDecimal(v.clone()),
+            Some(Typed::Bigint(v)) => EntityValue::BigInt(v.clone()),
+            Some(Typed::String(v)) => EntityValue::String(v.clone()),
+            Some(Typed::Bytes(v)) => EntityValue::Bytes(v.clone()),
+            Some(Typed::Bool(v)) => EntityValue::Bool(*v),
+            Some(Typed::Array(array)) => {
+                EntityValue::Array(array.value.iter().map(EntityValue::from).collect())
+            }
+            None => EntityValue::Null,
+        }
+    }
+}
+
+impl EntityValue {
+    // Big numbers are kept as strings so no precision is lost on the host side.
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            EntityValue::Int32(v) => json!(v),
+            EntityValue::BigDecimal(v)
+            | EntityValue::BigInt(v)
+            | EntityValue::String(v)
+            | EntityValue::Bytes(v) => json!(v),
+            EntityValue::Bool(v) => json!(v),
+            EntityValue::Array(values) => {
+                JsonValue::Array(values.iter().map(EntityValue::to_json).collect())
+            }
+            EntityValue::Null => JsonValue::Null,
+        }
+    }
+}
+
+pub type Entity = BTreeMap<String, EntityValue>;
+
+pub fn entity_to_json(id: &str, entity: &Entity) -> JsonValue {
+    let mut object = serde_json::Map::new();
+    object.insert("id".to_string(), json!(id));
+    for (name, value) in entity {
+        object.insert(name.clone(), value.to_json());
+    }
+    JsonValue::Object(object)
+}
+
+pub fn decode_entity_changes(bytes: &[u8]) -> Result<EntityChanges, Error> {
+    EntityChanges::decode(bytes).context("decode entity changes")
+}
+
+struct HistoryEntry {
+    block_num: u64,
+    entity_type: String,
+    id: String,
+    // State of the entity before the change, `None` when it did not exist.
+    previous: Option<Entity>,
+}
+
+// In-memory view of the entities produced by a module outputting `EntityChanges`. Every
+// change is journaled with the block it happened in so the store can be reverted when the
+// stream sends a `BlockUndoSignal`.
+#[derive(Default)]
+pub struct EntityStore {
+    entities: HashMap<String, BTreeMap<String, Entity>>,
+    history: Vec<HistoryEntry>,
+}
+
+impl EntityStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Applies every change of a block, or none of them when one is invalid.
+    pub fn apply(&mut self, block_num: u64, changes: &EntityChanges) -> Result<(), Error> {
+        for change in &changes.entity_changes {
+            operation(change)?;
+        }
+
+        for change in &changes.entity_changes {
+            self.apply_change(block_num, change)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_change(&mut self, block_num: u64, change: &EntityChange) -> Result<(), Error> {
+        let operation = operation(change)?;
+
+        let entities = self.entities.entry(change.entity.clone()).or_default();
+        let previous = entities.get(&change.id).cloned();
+
+        match operation {
+            Operation::Create | Operation::Update => {
+                let entity = entities.entry(change.id.clone()).or_default();
+                for field in &change.fields {
+                    let value = field
+                        .new_value
+                        .as_ref()
+                        .map(EntityValue::from)
+                        .unwrap_or(EntityValue::Null);
+                    entity.insert(field.name.clone(), value);
+                }
+            }
+            Operation::Delete => {
+                entities.remove(&change.id);
+            }
+            // Finality is tracked through `finalize`, nothing changes in the entity itself.
+            Operation::Final | Operation::Unset => return Ok(()),
+        }
+
+        self.history.push(HistoryEntry {
+            block_num,
+            entity_type: change.entity.clone(),
+            id: change.id.clone(),
+            previous,
+        });
+
+        Ok(())
+    }
+
+    // Reverts every change applied after `block_num`, which is the last valid block of a
+    // `BlockUndoSignal`.
+    pub fn revert_to(&mut self, block_num: u64) {
+        while let Some(entry) = self.history.last() {
+            if entry.block_num <= block_num {
+                break;
+            }
+
+            let entry = self.history.pop().unwrap();
+            let entities = self.entities.entry(entry.entity_type).or_default();
+            match entry.previous {
+                Some(previous) => {
+                    entities.insert(entry.id, previous);
+                }
+                None => {
+                    entities.remove(&entry.id);
+                }
+            }
+        }
+    }
+
+    // Forgets the history up to `block_num` included, those blocks can no longer be undone.
+    pub fn finalize(&mut self, block_num: u64) {
+        self.history.retain(|entry| entry.block_num > block_num);
+    }
+
+    pub fn get(&self, entity_type: &str, id: &str) -> Option<&Entity> {
+        self.entities.get(entity_type)?.get(id)
+    }
+
+    pub fn list(&self, entity_type: &str) -> Vec<(&String, &Entity)> {
+        self.entities
+            .get(entity_type)
+            .map(|entities| entities.iter().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn entity_types(&self) -> Vec<&String> {
+        let mut types: Vec<&String> = self
+            .entities
+            .iter()
+            .filter(|(_, entities)| !entities.is_empty())
+            .map(|(entity_type, _)| entity_type)
+            .collect();
+        types.sort();
+        types
+    }
+
+    pub fn get_json(&self, entity_type: &str, id: &str) -> JsonValue {
+        self.get(entity_type, id)
+            .map(|entity| entity_to_json(id, entity))
+            .unwrap_or(JsonValue::Null)
+    }
+
+    pub fn list_json(&self, entity_type: &str) -> JsonValue {
+        JsonValue::Array(
+            self.list(entity_type)
+                .into_iter()
+                .map(|(id, entity)| entity_to_json(id, entity))
+                .collect(),
+        )
+    }
+}
+
+// The operation of a change, which must be set to a known value.
+fn operation(change: &EntityChange) -> Result<Operation, Error> {
+    match Operation::try_from(change.operation) {
+        Ok(Operation::Unset) => Err(anyhow!(
+            "operation is not set on {} {}",
+            change.entity,
+            change.id
+        )),
+        Ok(operation) => Ok(operation),
+        Err(_) => Err(anyhow!(
+            "unknown operation {} on {}",
+            change.operation,
+            change.id
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::sf::substreams::entity::v1::Field;
+
+    fn change(operation: Operation, id: &str, fields: Vec<(&str, Typed)>) -> EntityChange {
+        EntityChange {
+            entity: "Token".to_string(),
+            id: id.to_string(),
+            ordinal: 0,
+            operation: operation as i32,
+            fields: fields
+                .into_iter()
+                .map(|(name, typed)| Field {
+                    name: name.to_string(),
+                    new_value: Some(Value { typed: Some(typed) }),
+                    old_value: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn applies_create_update_delete() {
+        let mut store = EntityStore::new();
+
+        store
+            .apply_change(
+                1,
+                &change(
+                    Operation::Create,
+                    "0xa",
+                    vec![
+                        ("symbol", Typed::String("AAA".to_string())),
+                        ("decimals", Typed::Int32(18)),
+                    ],
+                ),
+            )
+            .unwrap();
+        store
+            .apply_change(
+                2,
+                &change(
+                    Operation::Update,
+                    "0xa",
+                    vec![("supply", Typed::Bigint("1000".to_string()))],
+                ),
+            )
+            .unwrap();
+
+        assert_eq!(
+            store.get_json("Token", "0xa"),
+            json!({"id": "0xa", "symbol": "AAA", "decimals": 18, "supply": "1000"})
+        );
+
+        store
+            .apply_change(3, &change(Operation::Delete, "0xa", vec![]))
+            .unwrap();
+        assert!(store.get("Token", "0xa").is_none());
+        assert!(store
+            .apply_change(3, &change(Operation::Unset, "0xa", vec![]))
+            .is_err());
+    }
+
+    #[test]
+    fn revert_restores_previous_state() {
+        let mut store = EntityStore::new();
+        store
+            .apply_change(
+                1,
+                &change(Operation::Create, "0xa", vec![("v", Typed::Int32(1))]),
+            )
+            .unwrap();
+        store
+            .apply_change(
+                2,
+                &change(Operation::Update, "0xa", vec![("v", Typed::Int32(2))]),
+            )
+            .unwrap();
+        store
+            .apply_change(
+                2,
+                &change(Operation::Create, "0xb", vec![("v", Typed::Int32(3))]),
+            )
+            .unwrap();
+        store
+            .apply_change(3, &change(Operation::Delete, "0xa", vec![]))
+            .unwrap();
+
+        store.revert_to(1);
+
+        assert_eq!(store.get("Token", "0xa").unwrap()["v"], EntityValue::Int32(1));
+        assert!(store.get("Token", "0xb").is_none());
+        assert_eq!(store.list_json("Token").as_array().unwrap().len(), 1);
+
+        store.finalize(1);
+        store.revert_to(0);
+        assert!(store.get("Token", "0xa").is_some());
+    }
+
+    #[test]
+    fn invalid_changes_leave_the_store_untouched() {
+        let mut store = EntityStore::new();
+        let changes = EntityChanges {
+            entity_changes: vec![
+                change(Operation::Create, "0xa", vec![("v", Typed::Int32(1))]),
+                change(Operation::Unset, "0xb", vec![]),
+            ],
+        };
+
+        assert!(store.apply(1, &changes).is_err());
+
+        assert!(store.get("Token", "0xa").is_none());
+        assert!(store.entity_types().is_empty());
+        assert!(store.history.is_empty());
+    }
+}
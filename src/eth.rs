@@ -0,0 +1,806 @@
+use std::{fmt::Write, sync::Arc};
+
+use serde_json::{json, Value};
+
+use crate::http_client::{shared_client, HttpClient};
+use crate::json_rpc::{JsonRpcRequest, RpcError};
+use crate::response_cache::{cached_rpc_request, shared_cache};
+
+// Typed helpers for the common `eth_*` methods. Quantities are parsed into integers, data
+// into bytes, while hashes and addresses stay `0x` prefixed hex strings. Amounts in wei are
+// `u128`, which every real balance and fee fits in.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockTag {
+    Number(u64),
+    Earliest,
+    Latest,
+    Pending,
+    Safe,
+    Finalized,
+}
+
+impl BlockTag {
+    // A tag name, a decimal block number or a `0x` hex one.
+    pub fn parse(tag: &str) -> Result<Self, RpcError> {
+        match tag.trim() {
+            "" | "latest" => Ok(BlockTag::Latest),
+            "earliest" => Ok(BlockTag::Earliest),
+            "pending" => Ok(BlockTag::Pending),
+            "safe" => Ok(BlockTag::Safe),
+            "finalized" => Ok(BlockTag::Finalized),
+            x => {
+                let number = match x.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => x.parse(),
+                };
+                number
+                    .map(BlockTag::Number)
+                    .map_err(|_| RpcError::InvalidRequest(format!("invalid block tag '{}'", x)))
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            BlockTag::Number(number) => json!(format!("0x{:x}", number)),
+            BlockTag::Earliest => json!("earliest"),
+            BlockTag::Latest => json!("latest"),
+            BlockTag::Pending => json!("pending"),
+            BlockTag::Safe => json!("safe"),
+            BlockTag::Finalized => json!("finalized"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transaction {
+    pub hash: String,
+    pub from: String,
+    // None for contract creations.
+    pub to: Option<String>,
+    pub nonce: u64,
+    pub value: u128,
+    pub gas: u64,
+    pub gas_price: Option<u128>,
+    pub max_fee_per_gas: Option<u128>,
+    pub max_priority_fee_per_gas: Option<u128>,
+    pub input: Vec<u8>,
+    pub transaction_type: Option<u64>,
+    pub block_number: Option<u64>,
+    pub transaction_index: Option<u64>,
+}
+
+impl Transaction {
+    pub fn from_json(value: &Value) -> Result<Self, RpcError> {
+        Ok(Transaction {
+            hash: string_field(value, "hash")?,
+            from: string_field(value, "from")?,
+            to: optional_string_field(value, "to"),
+            nonce: quantity_field(value, "nonce")?,
+            value: big_quantity_field(value, "value")?,
+            gas: quantity_field(value, "gas")?,
+            gas_price: optional_big_quantity_field(value, "gasPrice")?,
+            max_fee_per_gas: optional_big_quantity_field(value, "maxFeePerGas")?,
+            max_priority_fee_per_gas: optional_big_quantity_field(value, "maxPriorityFeePerGas")?,
+            input: data_field(value, "input")?,
+            transaction_type: optional_quantity_field(value, "type")?,
+            block_number: optional_quantity_field(value, "blockNumber")?,
+            transaction_index: optional_quantity_field(value, "transactionIndex")?,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "hash": self.hash,
+            "from": self.from,
+            "to": self.to,
+            "nonce": self.nonce,
+            "value": self.value.to_string(),
+            "gas": self.gas,
+            "gas_price": self.gas_price.map(|x| x.to_string()),
+            "max_fee_per_gas": self.max_fee_per_gas.map(|x| x.to_string()),
+            "max_priority_fee_per_gas": self.max_priority_fee_per_gas.map(|x| x.to_string()),
+            "input": encode_data(&self.input),
+            "type": self.transaction_type,
+            "block_number": self.block_number,
+            "transaction_index": self.transaction_index,
+        })
+    }
+}
+
+// `eth_getBlockByNumber` returns either the transaction hashes or the full transactions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockTransactions {
+    Hashes(Vec<String>),
+    Full(Vec<Transaction>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    // None for pending blocks.
+    pub number: Option<u64>,
+    pub hash: Option<String>,
+    pub parent_hash: String,
+    pub timestamp: u64,
+    pub miner: String,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    pub base_fee_per_gas: Option<u128>,
+    pub transactions: BlockTransactions,
+}
+
+impl Block {
+    pub fn from_json(value: &Value) -> Result<Self, RpcError> {
+        let transactions = value["transactions"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let transactions = if transactions.iter().all(Value::is_string) {
+            BlockTransactions::Hashes(
+                transactions
+                    .iter()
+                    .filter_map(|hash| hash.as_str().map(str::to_string))
+                    .collect(),
+            )
+        } else {
+            BlockTransactions::Full(
+                transactions
+                    .iter()
+                    .map(Transaction::from_json)
+                    .collect::<Result<_, _>>()?,
+            )
+        };
+
+        Ok(Block {
+            number: optional_quantity_field(value, "number")?,
+            hash: optional_string_field(value, "hash"),
+            parent_hash: string_field(value, "parentHash")?,
+            timestamp: quantity_field(value, "timestamp")?,
+            miner: optional_string_field(value, "miner").unwrap_or_default(),
+            gas_limit: quantity_field(value, "gasLimit")?,
+            gas_used: quantity_field(value, "gasUsed")?,
+            base_fee_per_gas: optional_big_quantity_field(value, "baseFeePerGas")?,
+            transactions,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "number": self.number,
+            "hash": self.hash,
+            "parent_hash": self.parent_hash,
+            "timestamp": self.timestamp,
+            "miner": self.miner,
+            "gas_limit": self.gas_limit,
+            "gas_used": self.gas_used,
+            "base_fee_per_gas": self.base_fee_per_gas.map(|x| x.to_string()),
+            "transactions": match &self.transactions {
+                BlockTransactions::Hashes(hashes) => json!(hashes),
+                BlockTransactions::Full(transactions) => {
+                    json!(transactions.iter().map(Transaction::to_json).collect::<Vec<_>>())
+                }
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: Vec<u8>,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<String>,
+    pub transaction_hash: Option<String>,
+    pub transaction_index: Option<u64>,
+    pub log_index: Option<u64>,
+    pub removed: bool,
+}
+
+impl Log {
+    pub fn from_json(value: &Value) -> Result<Self, RpcError> {
+        Ok(Log {
+            address: string_field(value, "address")?,
+            topics: value["topics"]
+                .as_array()
+                .map(|topics| {
+                    topics
+                        .iter()
+                        .filter_map(|topic| topic.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            data: data_field(value, "data")?,
+            block_number: optional_quantity_field(value, "blockNumber")?,
+            block_hash: optional_string_field(value, "blockHash"),
+            transaction_hash: optional_string_field(value, "transactionHash"),
+            transaction_index: optional_quantity_field(value, "transactionIndex")?,
+            log_index: optional_quantity_field(value, "logIndex")?,
+            removed: value["removed"].as_bool().unwrap_or_default(),
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "address": self.address,
+            "topics": self.topics,
+            "data": encode_data(&self.data),
+            "block_number": self.block_number,
+            "block_hash": self.block_hash,
+            "transaction_hash": self.transaction_hash,
+            "transaction_index": self.transaction_index,
+            "log_index": self.log_index,
+            "removed": self.removed,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionReceipt {
+    pub transaction_hash: String,
+    pub transaction_index: u64,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub from: String,
+    pub to: Option<String>,
+    pub contract_address: Option<String>,
+    pub gas_used: u64,
+    pub cumulative_gas_used: u64,
+    pub effective_gas_price: Option<u128>,
+    // None before Byzantium, where receipts carry a state root instead.
+    pub success: Option<bool>,
+    pub logs: Vec<Log>,
+}
+
+impl TransactionReceipt {
+    pub fn from_json(value: &Value) -> Result<Self, RpcError> {
+        Ok(TransactionReceipt {
+            transaction_hash: string_field(value, "transactionHash")?,
+            transaction_index: quantity_field(value, "transactionIndex")?,
+            block_number: quantity_field(value, "blockNumber")?,
+            block_hash: string_field(value, "blockHash")?,
+            from: string_field(value, "from")?,
+            to: optional_string_field(value, "to"),
+            contract_address: optional_string_field(value, "contractAddress"),
+            gas_used: quantity_field(value, "gasUsed")?,
+            cumulative_gas_used: quantity_field(value, "cumulativeGasUsed")?,
+            effective_gas_price: optional_big_quantity_field(value, "effectiveGasPrice")?,
+            success: optional_quantity_field(value, "status")?.map(|status| status == 1),
+            logs: value["logs"]
+                .as_array()
+                .map(|logs| logs.iter().map(Log::from_json).collect::<Result<_, _>>())
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "transaction_hash": self.transaction_hash,
+            "transaction_index": self.transaction_index,
+            "block_number": self.block_number,
+            "block_hash": self.block_hash,
+            "from": self.from,
+            "to": self.to,
+            "contract_address": self.contract_address,
+            "gas_used": self.gas_used,
+            "cumulative_gas_used": self.cumulative_gas_used,
+            "effective_gas_price": self.effective_gas_price.map(|x| x.to_string()),
+            "success": self.success,
+            "logs": self.logs.iter().map(Log::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogFilter {
+    // Default to `latest`.
+    pub from_block: Option<BlockTag>,
+    pub to_block: Option<BlockTag>,
+    // Selects the logs of that single block instead of a range.
+    pub block_hash: Option<String>,
+    pub addresses: Vec<String>,
+    // Positional, `None` matching any topic and several topics matching any of them.
+    pub topics: Vec<Option<Vec<String>>>,
+}
+
+impl LogFilter {
+    // Reads the same object as `eth_getLogs` takes: `fromBlock` and `toBlock` or
+    // `blockHash`, `address` as a string or an array, and `topics`.
+    pub fn from_json(value: &Value) -> Result<Self, RpcError> {
+        let block_hash = optional_string_field(value, "blockHash");
+        if block_hash.is_some()
+            && (field(value, "fromBlock").is_some() || field(value, "toBlock").is_some())
+        {
+            return Err(RpcError::InvalidRequest(
+                "blockHash cannot be combined with fromBlock or toBlock".to_string(),
+            ));
+        }
+
+        let block = |key: &str| match value[key].as_str() {
+            Some(tag) => BlockTag::parse(tag).map(Some),
+            None => match value[key].as_u64() {
+                Some(number) => Ok(Some(BlockTag::Number(number))),
+                None => Ok(None),
+            },
+        };
+
+        let strings = |value: &Value| match value {
+            Value::String(s) => vec![s.clone()],
+            Value::Array(values) => values
+                .iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(LogFilter {
+            from_block: block("fromBlock")?,
+            to_block: block("toBlock")?,
+            block_hash,
+            addresses: strings(&value["address"]),
+            topics: value["topics"]
+                .as_array()
+                .map(|topics| {
+                    topics
+                        .iter()
+                        .map(|topic| (!topic.is_null()).then(|| strings(topic)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    // The filter for `range`, or for the block hash when there is none.
+    fn to_json(&self, range: Option<(u64, u64)>) -> Value {
+        let mut filter = json!({ "topics": self.topics });
+        match range {
+            Some((from_block, to_block)) => {
+                filter["fromBlock"] = BlockTag::Number(from_block).to_json();
+                filter["toBlock"] = BlockTag::Number(to_block).to_json();
+            }
+            None => filter["blockHash"] = json!(self.block_hash),
+        }
+        match self.addresses.len() {
+            0 => {}
+            1 => filter["address"] = json!(self.addresses[0]),
+            _ => filter["address"] = json!(self.addresses),
+        }
+        filter
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CallRequest {
+    pub from: Option<String>,
+    pub to: String,
+    pub data: Vec<u8>,
+    pub value: Option<u128>,
+    pub gas: Option<u64>,
+}
+
+impl CallRequest {
+    pub fn to_json(&self) -> Value {
+        let mut call = json!({
+            "to": self.to,
+            "data": encode_data(&self.data),
+        });
+        if let Some(from) = &self.from {
+            call["from"] = json!(from);
+        }
+        if let Some(value) = self.value {
+            call["value"] = json!(format!("0x{:x}", value));
+        }
+        if let Some(gas) = self.gas {
+            call["gas"] = json!(format!("0x{:x}", gas));
+        }
+        call
+    }
+}
+
+// Whether a provider refused an `eth_getLogs` because the range holds too many logs.
+// Providers word it differently, e.g. "query returned more than 10000 results" or "Log
+// response size exceeded".
+fn is_too_many_results(error: &RpcError) -> bool {
+    let Some(error) = error.json_rpc_error() else {
+        return false;
+    };
+    // Only the message tells, code -32005 also stands for "limit exceeded" on rate limits.
+    let message = error.message.to_lowercase();
+    [
+        "too many results",
+        "query returned more than",
+        "response size exceeded",
+        "block range is too wide",
+        "range too large",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+pub struct EthRpc {
+    endpoint: String,
+    client: Arc<HttpClient>,
+}
+
+impl EthRpc {
+    // Uses the shared HTTP client, defaulting to `https://` when no scheme is given.
+    pub fn new(endpoint: &str) -> Self {
+        let endpoint = if endpoint.starts_with("http") {
+            endpoint.to_string()
+        } else {
+            format!("https://{}", endpoint)
+        };
+
+        EthRpc {
+            endpoint,
+            client: shared_client(),
+        }
+    }
+
+    pub fn with_client(mut self, client: Arc<HttpClient>) -> Self {
+        self.client = client;
+        self
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        cached_rpc_request(
+            &self.client,
+            shared_cache().as_deref(),
+            &self.endpoint,
+            &JsonRpcRequest::new(method, params),
+            1,
+        )
+        .await
+    }
+
+    pub async fn block_number(&self) -> Result<u64, RpcError> {
+        parse_quantity(&self.request("eth_blockNumber", json!([])).await?)
+    }
+
+    pub async fn chain_id(&self) -> Result<u64, RpcError> {
+        parse_quantity(&self.request("eth_chainId", json!([])).await?)
+    }
+
+    // None when the node does not know the block.
+    pub async fn get_block_by_number(
+        &self,
+        block: BlockTag,
+        full_transactions: bool,
+    ) -> Result<Option<Block>, RpcError> {
+        let block = self
+            .request(
+                "eth_getBlockByNumber",
+                json!([block.to_json(), full_transactions]),
+            )
+            .await?;
+        match block {
+            Value::Null => Ok(None),
+            block => Block::from_json(&block).map(Some),
+        }
+    }
+
+    // None while the transaction is not mined.
+    pub async fn get_transaction_receipt(
+        &self,
+        hash: &str,
+    ) -> Result<Option<TransactionReceipt>, RpcError> {
+        let receipt = self
+            .request("eth_getTransactionReceipt", json!([hash]))
+            .await?;
+        match receipt {
+            Value::Null => Ok(None),
+            receipt => TransactionReceipt::from_json(&receipt).map(Some),
+        }
+    }
+
+    // Returns the bytes returned by the call.
+    pub async fn call(&self, call: &CallRequest, block: BlockTag) -> Result<Vec<u8>, RpcError> {
+        let result = self
+            .request("eth_call", json!([call.to_json(), block.to_json()]))
+            .await?;
+        decode_data(&result)
+    }
+
+    // Resolves the filter range to block numbers and fetches it, splitting the range in
+    // halves whenever the provider answers there are too many results. Logs are returned in
+    // block order. A filter on a block hash is sent as is.
+    pub async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<Log>, RpcError> {
+        if filter.block_hash.is_some() {
+            let result = self
+                .request("eth_getLogs", json!([filter.to_json(None)]))
+                .await?;
+            return result
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(Log::from_json)
+                .collect();
+        }
+
+        let from_block = self
+            .resolve_block(filter.from_block.unwrap_or(BlockTag::Latest))
+            .await?;
+        let to_block = self
+            .resolve_block(filter.to_block.unwrap_or(BlockTag::Latest))
+            .await?;
+        if from_block > to_block {
+            return Ok(Vec::new());
+        }
+
+        let mut logs = Vec::new();
+        let mut ranges = vec![(from_block, to_block)];
+        while let Some((from, to)) = ranges.pop() {
+            match self
+                .request("eth_getLogs", json!([filter.to_json(Some((from, to)))]))
+                .await
+            {
+                Ok(result) => {
+                    for log in result.as_array().into_iter().flatten() {
+                        logs.push(Log::from_json(log)?);
+                    }
+                }
+                Err(e) if from < to && is_too_many_results(&e) => {
+                    let middle = from + (to - from) / 2;
+                    ranges.push((middle + 1, to));
+                    ranges.push((from, middle));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(logs)
+    }
+
+    async fn resolve_block(&self, block: BlockTag) -> Result<u64, RpcError> {
+        match block {
+            BlockTag::Number(number) => Ok(number),
+            BlockTag::Earliest => Ok(0),
+            BlockTag::Latest => self.block_number().await,
+            tag => self
+                .get_block_by_number(tag, false)
+                .await?
+                .and_then(|block| block.number)
+                .ok_or_else(|| {
+                    RpcError::InvalidResponse(format!("no block number for {}", tag.to_json()))
+                }),
+        }
+    }
+}
+
+// Parses a `0x` prefixed hex quantity.
+pub fn parse_quantity(value: &Value) -> Result<u64, RpcError> {
+    parse_big_quantity(value).and_then(|quantity| {
+        u64::try_from(quantity)
+            .map_err(|_| RpcError::InvalidResponse(format!("quantity {} overflows u64", value)))
+    })
+}
+
+pub fn parse_big_quantity(value: &Value) -> Result<u128, RpcError> {
+    value
+        .as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .and_then(|hex| u128::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| RpcError::InvalidResponse(format!("invalid hex quantity {}", value)))
+}
+
+pub fn decode_data(value: &Value) -> Result<Vec<u8>, RpcError> {
+    let invalid = || RpcError::InvalidResponse(format!("invalid hex data {}", value));
+    let hex = value
+        .as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .ok_or_else(invalid)?;
+    // Only ASCII digits are accepted, which also makes slicing by byte index safe.
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+pub fn encode_data(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::from("0x"), |mut hex, b| {
+        let _ = write!(hex, "{:02x}", b);
+        hex
+    })
+}
+
+fn field<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    value.get(key).filter(|v| !v.is_null())
+}
+
+fn missing(key: &str) -> RpcError {
+    RpcError::InvalidResponse(format!("missing field '{}'", key))
+}
+
+fn string_field(value: &Value, key: &str) -> Result<String, RpcError> {
+    optional_string_field(value, key).ok_or_else(|| missing(key))
+}
+
+fn optional_string_field(value: &Value, key: &str) -> Option<String> {
+    field(value, key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+fn quantity_field(value: &Value, key: &str) -> Result<u64, RpcError> {
+    optional_quantity_field(value, key)?.ok_or_else(|| missing(key))
+}
+
+fn optional_quantity_field(value: &Value, key: &str) -> Result<Option<u64>, RpcError> {
+    field(value, key).map(parse_quantity).transpose()
+}
+
+fn big_quantity_field(value: &Value, key: &str) -> Result<u128, RpcError> {
+    optional_big_quantity_field(value, key)?.ok_or_else(|| missing(key))
+}
+
+fn optional_big_quantity_field(value: &Value, key: &str) -> Result<Option<u128>, RpcError> {
+    field(value, key).map(parse_big_quantity).transpose()
+}
+
+fn data_field(value: &Value, key: &str) -> Result<Vec<u8>, RpcError> {
+    field(value, key)
+        .map(decode_data)
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::json_rpc::JsonRpcError;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    // A node answering `eth_getLogs` with one log per block, refusing ranges wider than
+    // `max_range` blocks and `eth_blockNumber` with `latest`. Every requested log range is
+    // recorded.
+    async fn serve_logs(latest: u64, max_range: u64) -> (String, Arc<Mutex<Vec<(u64, u64)>>>) {
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+
+        let requested = ranges.clone();
+        let server = MockHttpServer::start(move |request, _| {
+            let body = request.json();
+            let mut response = match body["method"].as_str().unwrap() {
+                "eth_blockNumber" => json!({"result": format!("0x{:x}", latest)}),
+                _ => {
+                    let filter = &body["params"][0];
+                    let from = parse_quantity(&filter["fromBlock"]).unwrap();
+                    let to = parse_quantity(&filter["toBlock"]).unwrap();
+                    requested.lock().unwrap().push((from, to));
+                    if to - from + 1 > max_range {
+                        json!({"error": {"code": -32005, "message": "query returned more than 10000 results"}})
+                    } else {
+                        json!({"result": (from..=to).map(|n| json!({
+                            "address": "0xabc",
+                            "topics": [],
+                            "data": "0x01",
+                            "blockNumber": format!("0x{:x}", n),
+                            "logIndex": "0x0",
+                        })).collect::<Vec<_>>()})
+                    }
+                }
+            };
+
+            response["jsonrpc"] = json!("2.0");
+            response["id"] = body["id"].clone();
+            MockHttpResponse::json("200 OK", &response)
+        })
+        .await;
+
+        (server.url(), ranges)
+    }
+
+    #[tokio::test]
+    async fn splits_log_ranges_with_too_many_results() {
+        let (url, ranges) = serve_logs(109, 3).await;
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        let eth = EthRpc::new(&url).with_client(Arc::new(client));
+
+        let filter = LogFilter::from_json(&json!({"fromBlock": 100, "address": "0xabc"})).unwrap();
+        let logs = eth.get_logs(&filter).await.unwrap();
+
+        let blocks: Vec<u64> = logs.iter().filter_map(|log| log.block_number).collect();
+        assert_eq!(blocks, (100..=109).collect::<Vec<_>>());
+        assert_eq!(logs[0].data, vec![1]);
+
+        let ranges = ranges.lock().unwrap();
+        assert_eq!(ranges[0], (100, 109));
+        assert!(ranges.contains(&(100, 102)));
+        assert!(ranges.iter().all(|(from, to)| from <= to));
+    }
+
+    #[tokio::test]
+    async fn sends_block_hash_filters_as_is() {
+        let server = MockHttpServer::start(|request, _| {
+            let log = json!({"address": "0xabc", "topics": [], "data": "0x01", "logIndex": "0x0"});
+            let body = json!({"jsonrpc": "2.0", "id": request.json()["id"], "result": [log]});
+            MockHttpResponse::json("200 OK", &body)
+        })
+        .await;
+        let eth = EthRpc::new(&server.url());
+
+        let filter =
+            LogFilter::from_json(&json!({"blockHash": "0x02", "address": "0xabc"})).unwrap();
+        let logs = eth.get_logs(&filter).await.unwrap();
+        assert_eq!(logs.len(), 1);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].json()["params"][0],
+            json!({"blockHash": "0x02", "address": "0xabc", "topics": []})
+        );
+
+        assert!(matches!(
+            LogFilter::from_json(&json!({"blockHash": "0x02", "fromBlock": 100})),
+            Err(RpcError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn rate_limits_are_not_too_many_results() {
+        let error = |message: &str| RpcError::JsonRpc(JsonRpcError::new(-32005, message, None));
+        assert!(is_too_many_results(&error(
+            "query returned more than 10000 results"
+        )));
+        assert!(!is_too_many_results(&error("limit exceeded")));
+    }
+
+    #[test]
+    fn decodes_receipts() {
+        let receipt = TransactionReceipt::from_json(&json!({
+            "transactionHash": "0x01",
+            "transactionIndex": "0x2",
+            "blockNumber": "0x10",
+            "blockHash": "0x02",
+            "from": "0xaa",
+            "to": null,
+            "contractAddress": "0xbb",
+            "gasUsed": "0x5208",
+            "cumulativeGasUsed": "0xa410",
+            "effectiveGasPrice": "0x3b9aca00",
+            "status": "0x1",
+            "logs": [{"address": "0xbb", "topics": ["0x03"], "data": "0x", "logIndex": "0x0"}],
+        }))
+        .unwrap();
+
+        assert_eq!(receipt.block_number, 16);
+        assert_eq!(receipt.gas_used, 21000);
+        assert_eq!(receipt.effective_gas_price, Some(1_000_000_000));
+        assert_eq!(receipt.success, Some(true));
+        assert_eq!(receipt.to, None);
+        assert_eq!(receipt.logs[0].data, Vec::<u8>::new());
+
+        assert_eq!(BlockTag::parse("0x10").unwrap(), BlockTag::Number(16));
+        assert_eq!(BlockTag::parse("16").unwrap().to_json(), json!("0x10"));
+        assert!(parse_quantity(&json!("0x1ffffffffffffffff")).is_err());
+        assert!(decode_data(&json!("0x123")).is_err());
+    }
+
+    #[test]
+    fn decodes_hex_data() {
+        assert_eq!(
+            decode_data(&json!("0x00ff1A")).unwrap(),
+            vec![0x00, 0xff, 0x1a]
+        );
+        assert_eq!(encode_data(&[0x00, 0xff, 0x1a]), "0x00ff1a");
+
+        // Multi-byte characters must not be sliced through, nor signs taken as digits.
+        assert!(decode_data(&json!("0x0é0")).is_err());
+        assert!(decode_data(&json!("0xé")).is_err());
+        assert!(decode_data(&json!("0x+f")).is_err());
+        assert!(decode_data(&json!("00ff")).is_err());
+    }
+}
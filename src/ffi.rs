@@ -1,52 +1,171 @@
+// Every exported function receives raw pointers from the host and checks them for null
+// before dereferencing, so they are kept as safe `extern "C"` functions.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::cell::RefCell;
 use std::ffi::{CString, CStr};
-use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::os::raw::{c_char, c_void};
+use std::future::Future;
+use std::pin::Pin;
 use lazy_static::lazy_static;
 use tokio::runtime::Runtime;
-use crate::{rpc_call, api_call, substreams_call};
+use prost::Message;
+use crate::{rpc_call, rpc_batch_call, rpc_group_call, api_call, api_paginate, api_request_call, api_stream, substreams_call, firehose_call, firehose_fetch_block, sink_service_call};
+use crate::sink_service::{parse_statuses, SinkCommand};
+use crate::entity_store::{decode_entity_changes, EntityStore};
+use crate::{substreams_entities_call, substreams_file_sink_call, substreams_session, substreams_sqlite_call, substreams_store_call};
+use crate::http_stream::StreamEvent;
+use crate::pagination::ApiPageStream;
+use crate::eth::{decode_data, encode_data, BlockTag, CallRequest, EthRpc, LogFilter};
+use crate::file_sink::FileFormat;
+use crate::endpoint_pool::{configure_shared_pool, EndpointPoolConfig};
+use crate::http_client::{configure_shared_client, HttpClientConfig};
+use crate::json_rpc::RpcError;
+use crate::mock_server::{mock_sessions_from_json, MockSubstreamsServer};
+use crate::response_cache::{configure_shared_cache, shared_cache, CacheConfig};
+use crate::rpc_provider_group::shared_group;
+use crate::websocket::{SubscriptionKind, WsSubscription};
+use futures03::{FutureExt, Stream, StreamExt};
+use tokio::sync::oneshot;
+use crate::firehose::BlockReference;
+use crate::substreams_stream::BlockResponse;
+use crate::pb::sf::firehose::v2::SingleBlockResponse;
 
 // Struct to represent raw byte array
 #[repr(C)]
 pub struct FfiByteArray {
-    pub data: *mut u8,   // Pointer to the raw byte array
+    pub data: *const u8, // Pointer to the raw byte array
     pub length: usize,   // Length of the byte array
 }
 
-impl FfiByteArray {
-    pub fn new(bytes: Vec<u8>) -> Self {
-        let length = bytes.len();
-        let data = bytes.as_ptr() as *mut u8;
+// Status of an `FfiResult`.
+pub const FFI_OK: i32 = 0;
+// The call failed, `error` says why.
+pub const FFI_ERROR: i32 = 1;
+// An argument is null or invalid, nothing was sent.
+pub const FFI_INVALID_ARGUMENT: i32 = 2;
+// The library panicked, `error` holds the panic message.
+pub const FFI_PANIC: i32 = 3;
+// An iterator has no more items.
+pub const FFI_END: i32 = 4;
+// An `_async` call was cancelled before it completed.
+pub const FFI_CANCELLED: i32 = 5;
 
-        // Prevent Rust from freeing the memory
-        std::mem::forget(bytes);
+// Returned by every exported call producing data, owned by the host and released with
+// `free_ffi_result`. On `FFI_OK`, `data` holds `length` bytes of text, JSON unless noted,
+// followed by a NUL. Text from a server may hold NULs of its own, so read `length` bytes.
+// Raw block calls hold `length` `FfiByteArray` instead, and calls only reporting a status
+// leave `data` null. On failure `data` is null and `error` is a JSON object
+// `{"kind", "message", ...}`.
+#[repr(C)]
+pub struct FfiResult {
+    pub status: i32,
+    pub data: *const c_void,
+    pub length: usize,
+    pub error: *const c_char,
+}
 
-        Self { data, length }
-    }
+// The allocations an `FfiResult` points into.
+enum ResultData {
+    None,
+    // The text followed by a NUL.
+    Text(Vec<u8>),
+    // The buffers are only kept alive, the host reads them through `arrays`.
+    Arrays {
+        _buffers: Vec<Box<[u8]>>,
+        arrays: Vec<FfiByteArray>,
+    },
 }
 
-// String wrapper for C compatibility
-pub struct FfiString {
-    ptr: *mut c_char,
+// `result` comes first, a pointer to it is a pointer to the whole allocation, which
+// `free_ffi_result` drops at once.
+#[repr(C)]
+struct OwnedResult {
+    result: FfiResult,
+    data: ResultData,
+    error: Option<CString>,
 }
 
-impl FfiString {
-    pub fn new(s: String) -> Self {
-        let c_string = CString::new(s).unwrap();
-        let ptr = c_string.into_raw(); // Allocate memory
-        Self { ptr }
+impl FfiResult {
+    fn new(status: i32, data: ResultData, error: Option<serde_json::Value>) -> *mut FfiResult {
+        // Moving the owners into the box below does not move the heap buffers pointed at.
+        let (data_ptr, length) = match &data {
+            ResultData::None => (std::ptr::null(), 0),
+            ResultData::Text(text) => (text.as_ptr() as *const c_void, text.len() - 1),
+            ResultData::Arrays { arrays, .. } => (arrays.as_ptr() as *const c_void, arrays.len()),
+        };
+        let error = error.map(|error| CString::new(error.to_string()).unwrap_or_default());
+        let error_ptr = error.as_ref().map_or(std::ptr::null(), |error| error.as_ptr());
+
+        let owned = Box::new(OwnedResult {
+            result: FfiResult {
+                status,
+                data: data_ptr,
+                length,
+                error: error_ptr,
+            },
+            data,
+            error,
+        });
+        Box::into_raw(owned) as *mut FfiResult
     }
 
-    pub fn as_ptr(&self) -> *mut c_char {
-        self.ptr
+    fn text(text: String) -> *mut FfiResult {
+        let mut text = text.into_bytes();
+        text.push(0);
+        FfiResult::new(FFI_OK, ResultData::Text(text), None)
     }
-}
 
-impl Drop for FfiString {
-    fn drop(&mut self) {
-        if !self.ptr.is_null() {
-            unsafe {
-                let _ = CString::from_raw(self.ptr); // Deallocate the memory
-            }
-        }
+    fn json(value: &serde_json::Value) -> *mut FfiResult {
+        FfiResult::text(value.to_string())
+    }
+
+    fn arrays(buffers: Vec<Vec<u8>>) -> *mut FfiResult {
+        let buffers: Vec<Box<[u8]>> = buffers.into_iter().map(Vec::into_boxed_slice).collect();
+        let arrays = buffers
+            .iter()
+            .map(|buffer| FfiByteArray {
+                data: buffer.as_ptr(),
+                length: buffer.len(),
+            })
+            .collect();
+        FfiResult::new(
+            FFI_OK,
+            ResultData::Arrays {
+                _buffers: buffers,
+                arrays,
+            },
+            None,
+        )
+    }
+
+    fn ok() -> *mut FfiResult {
+        FfiResult::new(FFI_OK, ResultData::None, None)
+    }
+
+    fn end() -> *mut FfiResult {
+        FfiResult::new(FFI_END, ResultData::None, None)
+    }
+
+    // `error` is the `to_json()` of an `RpcError` or an `ApiError`.
+    fn error(error: serde_json::Value) -> *mut FfiResult {
+        FfiResult::new(FFI_ERROR, ResultData::None, Some(error))
+    }
+
+    fn failed(err: &anyhow::Error) -> *mut FfiResult {
+        FfiResult::error(serde_json::json!({
+            "kind": "failed",
+            "message": format!("{:#}", err),
+        }))
+    }
+
+    fn invalid_argument(message: &str) -> *mut FfiResult {
+        let error = serde_json::json!({
+            "kind": "invalid_argument",
+            "message": message,
+        });
+        FfiResult::new(FFI_INVALID_ARGUMENT, ResultData::None, Some(error))
     }
 }
 
@@ -55,17 +174,218 @@ lazy_static! {
     static ref RUNTIME: Runtime = Runtime::new().unwrap();
 }
 
-// Substreams call for raw bytes
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
+thread_local! {
+    // The panic `catch_panic` last turned into a default value on this thread, taken by
+    // `last_panic_ffi`.
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Runs the body of an exported function, returning `default` if it panics: unwinding
+// into the host is undefined behavior. The panic message is kept for `last_panic_ffi`.
+fn catch_panic<T>(default: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = panic_message(&*payload);
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(message));
+            default
+        }
+    }
+}
+
+// For the release functions, whose panics do not show: returns an `FFI_PANIC` result with
+// the message of the last one on the calling thread, or an `FFI_OK` result without data
+// when there was none since the previous call.
+#[no_mangle]
+pub extern "C" fn last_panic_ffi() -> *mut FfiResult {
+    catch_panic_result(|| match LAST_PANIC.with(|last| last.borrow_mut().take()) {
+        Some(message) => {
+            let error = serde_json::json!({
+                "kind": "panic",
+                "message": message,
+            });
+            FfiResult::new(FFI_PANIC, ResultData::None, Some(error))
+        }
+        None => FfiResult::ok(),
+    })
+}
+
+// As `catch_panic`, for calls returning an `FfiResult`: a panic becomes an `FFI_PANIC`
+// result.
+fn catch_panic_result(body: impl FnOnce() -> *mut FfiResult) -> *mut FfiResult {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| panic_result(&*payload))
+}
+
+fn panic_result(payload: &(dyn std::any::Any + Send)) -> *mut FfiResult {
+    let error = serde_json::json!({
+        "kind": "panic",
+        "message": panic_message(payload),
+    });
+    FfiResult::new(FFI_PANIC, ResultData::None, Some(error))
+}
+
+// A result on its way from a runtime thread to the host, which alone owns it.
+struct SendResult(*mut FfiResult);
+unsafe impl Send for SendResult {}
+
+// A call built from the host's arguments, which are copied before it starts. Blocking
+// exports run it with `block_on_call`, their `_async` variants with `spawn_call`.
+type FfiCall = Pin<Box<dyn Future<Output = SendResult> + Send>>;
+
+fn invalid_call(message: &'static str) -> FfiCall {
+    Box::pin(async move { SendResult(FfiResult::invalid_argument(message)) })
+}
+
+// Blocks the host thread on `call`, every blocking export goes through it. A runtime
+// thread, e.g. one running a callback, cannot wait for the runtime it is part of: the call
+// fails with the `runtime_thread` kind instead. Unlike an `FfiCall`, `call` may borrow the
+// arguments, e.g. a handle.
+fn block_on_call(call: impl Future<Output = SendResult>) -> *mut FfiResult {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return FfiResult::error(serde_json::json!({
+            "kind": "runtime_thread",
+            "message": "blocking call made from a runtime thread, use its _async variant",
+        }));
+    }
+
+    RUNTIME.block_on(call).0
+}
+
+// Called once, from a runtime thread, with the result of an `_async` call. The host owns the
+// result and releases it with `free_ffi_result`. `user_data` must stay valid until the
+// callback returns.
+pub type FfiCompletionCallback =
+    Option<extern "C" fn(user_data: *mut c_void, result: *mut FfiResult)>;
+
+// A running `_async` call, released with `free_async_request`.
+pub struct AsyncRequest {
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+// Spawns `call` on the runtime and returns at once. `callback` receives its result, an
+// `FFI_PANIC` result if it panics, or an `FFI_CANCELLED` result if it is cancelled first.
+// Stores the request in `out_request` before the call starts and returns an `FFI_OK`
+// result without data, or `FFI_INVALID_ARGUMENT` when `callback` or `out_request` is null,
+// nothing being spawned then.
+fn spawn_call(
+    call: FfiCall,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    let Some(callback) = callback else {
+        return FfiResult::invalid_argument("Null callback passed");
+    };
+    if out_request.is_null() {
+        return FfiResult::invalid_argument("Null pointer passed");
+    }
+
+    let (cancel, cancelled) = oneshot::channel::<()>();
+    let user_data = UserData(user_data);
+    unsafe { *out_request = Box::into_raw(Box::new(AsyncRequest { cancel: Some(cancel) })) };
+
+    RUNTIME.spawn(async move {
+        let user_data = user_data;
+        let result = tokio::select! {
+            result = AssertUnwindSafe(call).catch_unwind() => {
+                result.unwrap_or_else(|payload| SendResult(panic_result(&*payload)))
+            }
+            // Freeing the request drops the sender without cancelling the call.
+            Ok(()) = cancelled => {
+                let error = serde_json::json!({
+                    "kind": "cancelled",
+                    "message": "The request was cancelled",
+                });
+                SendResult(FfiResult::new(FFI_CANCELLED, ResultData::None, Some(error)))
+            }
+        };
+        callback(user_data.0, result.0);
+    });
+
+    FfiResult::ok()
+}
+
+// Cancels an `_async` call: unless it already completed, its callback receives an
+// `FFI_CANCELLED` result. Does not wait for the callback, so it may be called from one.
+// Returns an `FFI_OK` result without data.
+#[no_mangle]
+pub extern "C" fn async_request_cancel_ffi(request: *mut AsyncRequest) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if request.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let request = unsafe { &mut *request };
+        if let Some(cancel) = request.cancel.take() {
+            let _ = cancel.send(());
+        }
+
+        FfiResult::ok()
+    })
+}
+
+// Releases a request returned by an `_async` call. A call still running is not cancelled and
+// its callback still runs.
+#[no_mangle]
+pub extern "C" fn free_async_request(request: *mut AsyncRequest) {
+    catch_panic((), || {
+        if request.is_null() {
+            return;
+        }
+
+        unsafe {
+            drop(Box::from_raw(request));
+        }
+    })
+}
+
+// Substreams call for raw bytes, the result holds one `FfiByteArray` per module output
 #[no_mangle]
 pub extern "C" fn substreams_call_ffi(
     endpoint_url: *const c_char,
     package_file: *const c_char,
     module_name: *const c_char,
     range: *const c_char,
-    out_length: *mut usize,
-) -> *mut FfiByteArray {
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_substreams_call(endpoint_url, package_file, module_name, range);
+        block_on_call(call)
+    })
+}
+
+// `substreams_call_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn substreams_call_async_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_substreams_call(endpoint_url, package_file, module_name, range);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
+
+fn prepare_substreams_call(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+) -> FfiCall {
     if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() {
-        return std::ptr::null_mut();
+        return invalid_call("Null pointer passed");
     }
 
     let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
@@ -79,142 +399,2021 @@ pub extern "C" fn substreams_call_ffi(
         }
     };
 
-    let result = RUNTIME.block_on(substreams_call(endpoint_url, &package_file, &module_name, range));
+    Box::pin(async move {
+        let result = substreams_call(endpoint_url, &package_file, &module_name, range).await;
 
-    match result {
-        Ok(results) => {
-            let mut ffi_results: Vec<FfiByteArray> = Vec::new();
-            for bytes in results {
-                ffi_results.push(FfiByteArray::new(bytes));
-            }
+        SendResult(match result {
+            Ok(results) => FfiResult::arrays(results),
+            Err(err) => FfiResult::failed(&err),
+        })
+    })
+}
+
+// Firehose call for raw chain blocks, the result holds one `FfiByteArray` per block
+#[no_mangle]
+pub extern "C" fn firehose_call_ffi(
+    endpoint_url: *const c_char,
+    range: *const c_char,
+    final_blocks_only: bool,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_firehose_call(endpoint_url, range, final_blocks_only);
+        block_on_call(call)
+    })
+}
+
+// `firehose_call_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn firehose_call_async_ffi(
+    endpoint_url: *const c_char,
+    range: *const c_char,
+    final_blocks_only: bool,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_firehose_call(endpoint_url, range, final_blocks_only);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
 
-            unsafe {
-                if !out_length.is_null() {
-                    *out_length = ffi_results.len();
+fn prepare_firehose_call(
+    endpoint_url: *const c_char,
+    range: *const c_char,
+    final_blocks_only: bool,
+) -> FfiCall {
+    if endpoint_url.is_null() {
+        return invalid_call("Null pointer passed");
+    }
+
+    let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+    let range = unsafe {
+        if range.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(range).to_string_lossy().to_string())
+        }
+    };
+
+    Box::pin(async move {
+        let result = firehose_call(endpoint_url, range, final_blocks_only).await;
+
+        SendResult(match result {
+            Ok(results) => FfiResult::arrays(results),
+            Err(err) => FfiResult::failed(&err),
+        })
+    })
+}
+
+// Single block fetch, the reference used is `cursor` when given, then `block_hash` with
+// `block_num`, then `block_num` alone. The result holds a single `FfiByteArray` with an
+// encoded `sf.firehose.v2.SingleBlockResponse` (block `Any` and `BlockMetadata`).
+#[no_mangle]
+pub extern "C" fn firehose_fetch_block_ffi(
+    endpoint_url: *const c_char,
+    block_num: u64,
+    block_hash: *const c_char,
+    cursor: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if endpoint_url.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+        let reference = unsafe {
+            if !cursor.is_null() {
+                BlockReference::Cursor(CStr::from_ptr(cursor).to_string_lossy().to_string())
+            } else if !block_hash.is_null() {
+                BlockReference::HashAndNumber {
+                    num: block_num,
+                    hash: CStr::from_ptr(block_hash).to_string_lossy().to_string(),
                 }
+            } else {
+                BlockReference::Number(block_num)
             }
+        };
+
+        block_on_call(async move {
+            SendResult(match firehose_fetch_block(endpoint_url, reference).await {
+                Ok(fetched) => {
+                    let response = SingleBlockResponse {
+                        block: Some(fetched.block),
+                        metadata: fetched.metadata,
+                    };
+                    FfiResult::arrays(vec![response.encode_to_vec()])
+                }
+                Err(err) => FfiResult::failed(&err),
+            })
+        })
+    })
+}
+
+pub struct SubstreamsSession {
+    stream: Pin<Box<dyn Stream<Item = Result<BlockResponse, anyhow::Error>> + Send>>,
+}
 
-            Box::into_raw(ffi_results.into_boxed_slice()) as *mut FfiByteArray
+// Opens a Substreams session read one block at a time with `substreams_session_next_ffi`,
+// from `cursor` when given. A null `token` reads `SUBSTREAMS_API_TOKEN`. Errors opening the
+// stream are reported by the first `substreams_session_next_ffi` call. Stores the session
+// to release with `free_substreams_session` in `out_session` and returns an `FFI_OK`
+// result without data, or `FFI_INVALID_ARGUMENT` when a required argument is null.
+#[no_mangle]
+pub extern "C" fn substreams_session_open_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+    cursor: *const c_char,
+    token: *const c_char,
+    out_session: *mut *mut SubstreamsSession,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() || out_session.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
         }
-        Err(_) => std::ptr::null_mut(),
-    }
+
+        let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+        let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
+        let range = if range.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(range).to_string_lossy().to_string() })
+        };
+        let cursor = if cursor.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(cursor).to_string_lossy().to_string() })
+        };
+        let token = if token.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(token).to_string_lossy().to_string() })
+        };
+
+        let stream =
+            substreams_session(endpoint_url, package_file, module_name, range, cursor, token);
+        let session = SubstreamsSession {
+            stream: Box::pin(stream),
+        };
+        unsafe { *out_session = Box::into_raw(Box::new(session)) };
+        FfiResult::ok()
+    })
 }
 
-// Free a string pointer
+// Waits for the next block of the session. Returns `{"type": "data", "block_num", "block_id",
+// "cursor", "final_block_height", "output"}`, the module output being `0x` hex, or
+// `{"type": "undo", "last_valid_block", "last_valid_cursor"}` when the blocks after
+// `last_valid_block` were forked out. The status is `FFI_END` once the range is done. The
+// session ends after an error.
 #[no_mangle]
-pub extern "C" fn free_string(ptr: *mut c_char) {
-    if ptr.is_null() {
-        return;
-    }
-    unsafe {
-        let _ = CString::from_raw(ptr); // Safely deallocates the memory
-    }
+pub extern "C" fn substreams_session_next_ffi(session: *mut SubstreamsSession) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if session.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let session = unsafe { &mut *session };
+        block_on_call(async {
+            SendResult(match session.stream.next().await {
+                Some(Ok(BlockResponse::New(data))) => {
+                    let clock = data.clock.unwrap_or_default();
+                    let output = data
+                        .output
+                        .and_then(|output| output.map_output)
+                        .map(|output| output.value)
+                        .unwrap_or_default();
+                    FfiResult::json(&serde_json::json!({
+                        "type": "data",
+                        "block_num": clock.number,
+                        "block_id": clock.id,
+                        "cursor": data.cursor,
+                        "final_block_height": data.final_block_height,
+                        "output": encode_data(&output),
+                    }))
+                }
+                Some(Ok(BlockResponse::Undo(undo))) => FfiResult::json(&serde_json::json!({
+                    "type": "undo",
+                    "last_valid_block": undo.last_valid_block.map(|block| block.number),
+                    "last_valid_cursor": undo.last_valid_cursor,
+                })),
+                Some(Err(err)) => FfiResult::failed(&err),
+                None => FfiResult::end(),
+            })
+        })
+    })
 }
 
-// Free a byte array
 #[no_mangle]
-pub extern "C" fn free_byte_array(ptr: *mut FfiByteArray, length: usize) {
-    if ptr.is_null() {
-        return;
-    }
+pub extern "C" fn free_substreams_session(session: *mut SubstreamsSession) {
+    catch_panic((), || {
+        if session.is_null() {
+            return;
+        }
 
-    unsafe {
-        let slice = std::slice::from_raw_parts_mut(ptr, length);
-        for array in &mut *slice {
-            let _ = Vec::from_raw_parts(array.data, array.length, array.length);
+        unsafe {
+            let _ = Box::from_raw(session);
         }
-        let _ = Box::from_raw(slice);
-    }
+    })
 }
 
+// Free a result, its data and its error
+#[no_mangle]
+pub extern "C" fn free_ffi_result(result: *mut FfiResult) {
+    catch_panic((), || {
+        if result.is_null() {
+            return;
+        }
 
+        unsafe {
+            let _ = Box::from_raw(result as *mut OwnedResult);
+        }
+    })
+}
 
+// Sends a JSON-RPC request and returns its `result` as JSON. `id` must not be negative. On
+// failure the error `kind` is `json_rpc` (with `code`, `message` and `data`), `http` (with
+// `status`, `body` and the JSON-RPC `error` object the body holds, if any),
+// `invalid_request`, `invalid_response` or `transport`.
 #[no_mangle]
 pub extern "C" fn rpc_call_ffi(
     rpc_endpoint: *const c_char,
     method: *const c_char,
     params_input: *const c_char,
     id: i32,
-) -> *mut c_char {
+) -> *mut FfiResult {
+    catch_panic_result(|| block_on_call(prepare_rpc_call(rpc_endpoint, method, params_input, id)))
+}
+
+// `rpc_call_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn rpc_call_async_ffi(
+    rpc_endpoint: *const c_char,
+    method: *const c_char,
+    params_input: *const c_char,
+    id: i32,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_rpc_call(rpc_endpoint, method, params_input, id);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
+
+fn prepare_rpc_call(
+    rpc_endpoint: *const c_char,
+    method: *const c_char,
+    params_input: *const c_char,
+    id: i32,
+) -> FfiCall {
     if rpc_endpoint.is_null() || method.is_null() || params_input.is_null() {
-        return FfiString::new("Error: Null pointer passed".to_string()).as_ptr();
+        return invalid_call("Null pointer passed");
     }
 
     let rpc_endpoint = unsafe { CStr::from_ptr(rpc_endpoint).to_string_lossy().to_string() };
     let method = unsafe { CStr::from_ptr(method).to_string_lossy().to_string() };
     let params_input = unsafe { CStr::from_ptr(params_input).to_string_lossy().to_string() };
 
-    let result = RUNTIME.block_on(rpc_call(&rpc_endpoint, &method, &params_input, id));
+    Box::pin(async move {
+        let result = rpc_call(&rpc_endpoint, &method, &params_input, id).await;
 
-    match result {
-        Ok(value) => {
-            let ffi_string = FfiString::new(value.to_string());
-            println!("rpc_call_ffi: Allocated pointer {:?}", ffi_string.as_ptr());
-            ffi_string.as_ptr()
-        }
-        Err(err) => {
-            let ffi_string = FfiString::new(format!("Error: {}", err));
-            println!("rpc_call_ffi: Allocated error pointer {:?}", ffi_string.as_ptr());
-            ffi_string.as_ptr()
-        }
+        SendResult(match result {
+            Ok(value) => FfiResult::json(&value),
+            Err(err) => FfiResult::error(err.to_json()),
+        })
+    })
+}
+
+// Sends a JSON array of `{"method", "params"}` requests as JSON-RPC batches. Returns a JSON
+// array with one `{"result": ...}` or `{"error": {"code", "message", "data"}}` object per
+// request, in order. The call only fails, as `rpc_call_ffi` does, when the batch itself
+// fails.
+#[no_mangle]
+pub extern "C" fn rpc_batch_call_ffi(
+    rpc_endpoint: *const c_char,
+    requests_json: *const c_char,
+    max_batch_size: usize,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_rpc_batch_call(rpc_endpoint, requests_json, max_batch_size);
+        block_on_call(call)
+    })
+}
+
+// `rpc_batch_call_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn rpc_batch_call_async_ffi(
+    rpc_endpoint: *const c_char,
+    requests_json: *const c_char,
+    max_batch_size: usize,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_rpc_batch_call(rpc_endpoint, requests_json, max_batch_size);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
+
+fn prepare_rpc_batch_call(
+    rpc_endpoint: *const c_char,
+    requests_json: *const c_char,
+    max_batch_size: usize,
+) -> FfiCall {
+    if rpc_endpoint.is_null() || requests_json.is_null() {
+        return invalid_call("Null pointer passed");
     }
+
+    let rpc_endpoint = unsafe { CStr::from_ptr(rpc_endpoint).to_string_lossy().to_string() };
+    let requests_json = unsafe { CStr::from_ptr(requests_json).to_string_lossy().to_string() };
+
+    Box::pin(async move {
+        let result = rpc_batch_call(&rpc_endpoint, &requests_json, max_batch_size).await;
+
+        SendResult(match result {
+            Ok(items) => {
+                let items: Vec<serde_json::Value> = items
+                    .into_iter()
+                    .map(|item| match item {
+                        Ok(result) => serde_json::json!({ "result": result }),
+                        Err(error) => serde_json::json!({ "error": error.to_json() }),
+                    })
+                    .collect();
+                FfiResult::json(&serde_json::Value::Array(items))
+            }
+            Err(err) => FfiResult::error(err.to_json()),
+        })
+    })
 }
 
+// Sends a JSON-RPC request to a group of comma separated providers, `strategy` being
+// `fallback`, `round_robin` or `quorum:<agree>/<providers>`. Returns a JSON object with the
+// `result`, the `provider` that answered, the `agreeing` providers and every provider
+// `attempts`. Errors are those of `rpc_call_ffi`, plus `no_quorum`.
 #[no_mangle]
-pub extern "C" fn api_call_ffi(
-    api_url: *const c_char,
-    optional_headers: *const c_char,
-) -> *mut c_char {
-    if api_url.is_null() {
-        return CString::new("Null pointer passed").unwrap().into_raw();
+pub extern "C" fn rpc_group_call_ffi(
+    rpc_endpoints: *const c_char,
+    strategy: *const c_char,
+    method: *const c_char,
+    params_input: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_rpc_group_call(rpc_endpoints, strategy, method, params_input);
+        block_on_call(call)
+    })
+}
+
+// `rpc_group_call_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn rpc_group_call_async_ffi(
+    rpc_endpoints: *const c_char,
+    strategy: *const c_char,
+    method: *const c_char,
+    params_input: *const c_char,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_rpc_group_call(rpc_endpoints, strategy, method, params_input);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
+
+fn prepare_rpc_group_call(
+    rpc_endpoints: *const c_char,
+    strategy: *const c_char,
+    method: *const c_char,
+    params_input: *const c_char,
+) -> FfiCall {
+    if rpc_endpoints.is_null() || method.is_null() || params_input.is_null() {
+        return invalid_call("Null pointer passed");
     }
 
-    let api_url = unsafe { CStr::from_ptr(api_url).to_string_lossy().to_string() };
-    let optional_headers = unsafe {
-        if optional_headers.is_null() {
-            None
-        } else {
-            Some(CStr::from_ptr(optional_headers).to_string_lossy().to_string())
-        }
+    let rpc_endpoints = unsafe { CStr::from_ptr(rpc_endpoints).to_string_lossy().to_string() };
+    let strategy = if strategy.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(strategy).to_string_lossy().to_string() }
     };
+    let method = unsafe { CStr::from_ptr(method).to_string_lossy().to_string() };
+    let params_input = unsafe { CStr::from_ptr(params_input).to_string_lossy().to_string() };
+
+    Box::pin(async move {
+        let result = rpc_group_call(&rpc_endpoints, &strategy, &method, &params_input).await;
+
+        SendResult(match result {
+            Ok(response) => FfiResult::json(&response.to_json()),
+            Err(err) => FfiResult::error(err.to_json()),
+        })
+    })
+}
 
-    let result = RUNTIME.block_on(api_call(&api_url, optional_headers.as_deref()));
+// Returns the health of every provider of a group as a JSON array.
+#[no_mangle]
+pub extern "C" fn rpc_group_health_ffi(
+    rpc_endpoints: *const c_char,
+    strategy: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if rpc_endpoints.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let rpc_endpoints = unsafe { CStr::from_ptr(rpc_endpoints).to_string_lossy().to_string() };
+        let strategy = if strategy.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(strategy).to_string_lossy().to_string() }
+        };
 
+        match shared_group(&rpc_endpoints, &strategy) {
+            Ok(group) => FfiResult::json(&group.health_json()),
+            Err(err) => FfiResult::invalid_argument(&format!("{:#}", err)),
+        }
+    })
+}
+
+// Returns the result of a typed Ethereum helper as JSON, errors being those of
+// `rpc_call_ffi`.
+fn eth_call_result<T>(
+    result: Result<T, RpcError>,
+    to_json: impl FnOnce(T) -> serde_json::Value,
+) -> *mut FfiResult {
     match result {
-        Ok(response) => CString::new(response).unwrap().into_raw(),
-        Err(err) => CString::new(format!("Error: {}", err)).unwrap().into_raw(),
+        Ok(value) => FfiResult::json(&to_json(value)),
+        Err(err) => FfiResult::error(err.to_json()),
     }
 }
 
-// #[no_mangle]
-// pub extern "C" fn substreams_call_ffi(
-//     endpoint_url: *const c_char,
-//     package_file: *const c_char,
-//     module_name: *const c_char,
-//     range: *const c_char,
-// ) -> *mut c_char {
-//     if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() {
-//         return CString::new("Null pointer passed").unwrap().into_raw();
-//     }
+// Reads the strings of an Ethereum helper, or `None` if any is null.
+fn eth_ffi_strings<const N: usize>(strings: [*const c_char; N]) -> Option<[String; N]> {
+    if strings.iter().any(|s| s.is_null()) {
+        return None;
+    }
 
-//     let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
-//     let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
-//     let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
-//     let range = unsafe {
-//         if range.is_null() {
-//             None
-//         } else {
-//             Some(CStr::from_ptr(range).to_string_lossy().to_string())
-//         }
-//     };
+    Some(strings.map(|s| unsafe { CStr::from_ptr(s).to_string_lossy().to_string() }))
+}
 
-//     let result = RUNTIME.block_on(substreams_call(endpoint_url, &package_file, &module_name, range));
+// Returns the latest block number as a JSON number.
+#[no_mangle]
+pub extern "C" fn eth_block_number_ffi(rpc_endpoint: *const c_char) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some([rpc_endpoint]) = eth_ffi_strings([rpc_endpoint]) else {
+            return FfiResult::invalid_argument("Null pointer passed");
+        };
 
-//     match result {
-//         Ok(debug_values) => {
-//             let output = format!("{:?}", debug_values);
-//             CString::new(output).unwrap().into_raw()
-//         }
-//         Err(err) => CString::new(format!("Error: {}", err)).unwrap().into_raw(),
-//     }
-// }
+        block_on_call(async move {
+            let result = EthRpc::new(&rpc_endpoint).block_number().await;
+            SendResult(eth_call_result(result, |number| serde_json::json!(number)))
+        })
+    })
+}
+
+// Returns the chain id as a JSON number.
+#[no_mangle]
+pub extern "C" fn eth_chain_id_ffi(rpc_endpoint: *const c_char) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some([rpc_endpoint]) = eth_ffi_strings([rpc_endpoint]) else {
+            return FfiResult::invalid_argument("Null pointer passed");
+        };
+
+        block_on_call(async move {
+            let result = EthRpc::new(&rpc_endpoint).chain_id().await;
+            SendResult(eth_call_result(result, |chain_id| serde_json::json!(chain_id)))
+        })
+    })
+}
+
+// Returns the block as JSON, `null` if the node does not know it. `block` is a number (decimal
+// or `0x` hex) or a tag such as `latest` or `finalized`. Quantities are numbers, amounts in
+// wei decimal strings.
+#[no_mangle]
+pub extern "C" fn eth_get_block_by_number_ffi(
+    rpc_endpoint: *const c_char,
+    block: *const c_char,
+    full_transactions: bool,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some([rpc_endpoint, block]) = eth_ffi_strings([rpc_endpoint, block]) else {
+            return FfiResult::invalid_argument("Null pointer passed");
+        };
+
+        block_on_call(async move {
+            let result = match BlockTag::parse(&block) {
+                Ok(block) => EthRpc::new(&rpc_endpoint).get_block_by_number(block, full_transactions).await,
+                Err(err) => Err(err),
+            };
+            SendResult(eth_call_result(result, |block| block.map(|b| b.to_json()).unwrap_or_default()))
+        })
+    })
+}
+
+// Returns the logs matching an `eth_getLogs` filter object as a JSON array. Ranges the
+// provider refuses for holding too many results are split until they pass.
+#[no_mangle]
+pub extern "C" fn eth_get_logs_ffi(
+    rpc_endpoint: *const c_char,
+    filter_json: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some([rpc_endpoint, filter_json]) = eth_ffi_strings([rpc_endpoint, filter_json]) else {
+            return FfiResult::invalid_argument("Null pointer passed");
+        };
+
+        let filter = serde_json::from_str(&filter_json)
+            .map_err(|e| RpcError::InvalidRequest(format!("invalid JSON for filter: {}", e)))
+            .and_then(|filter| LogFilter::from_json(&filter));
+        block_on_call(async move {
+            let result = match filter {
+                Ok(filter) => EthRpc::new(&rpc_endpoint).get_logs(&filter).await,
+                Err(err) => Err(err),
+            };
+            SendResult(eth_call_result(result, |logs| {
+                serde_json::Value::Array(logs.iter().map(|log| log.to_json()).collect())
+            }))
+        })
+    })
+}
+
+// Calls contract `to` with the `0x` hex `data` at `block` and returns the output as a JSON
+// hex string.
+#[no_mangle]
+pub extern "C" fn eth_call_ffi(
+    rpc_endpoint: *const c_char,
+    to: *const c_char,
+    data: *const c_char,
+    block: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some([rpc_endpoint, to, data, block]) = eth_ffi_strings([rpc_endpoint, to, data, block])
+        else {
+            return FfiResult::invalid_argument("Null pointer passed");
+        };
+
+        let request = decode_data(&serde_json::json!(data))
+            .map_err(|_| RpcError::InvalidRequest(format!("invalid hex data '{}'", data)))
+            .and_then(|data| Ok((data, BlockTag::parse(&block)?)));
+        block_on_call(async move {
+            let result = match request {
+                Ok((data, block)) => {
+                    let call = CallRequest { to, data, ..Default::default() };
+                    EthRpc::new(&rpc_endpoint).call(&call, block).await
+                }
+                Err(err) => Err(err),
+            };
+            SendResult(eth_call_result(result, |output| serde_json::json!(encode_data(&output))))
+        })
+    })
+}
+
+// Returns the receipt of a transaction as JSON, `null` while it is not mined.
+#[no_mangle]
+pub extern "C" fn eth_get_transaction_receipt_ffi(
+    rpc_endpoint: *const c_char,
+    hash: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some([rpc_endpoint, hash]) = eth_ffi_strings([rpc_endpoint, hash]) else {
+            return FfiResult::invalid_argument("Null pointer passed");
+        };
+
+        block_on_call(async move {
+            let result = EthRpc::new(&rpc_endpoint).get_transaction_receipt(&hash).await;
+            SendResult(eth_call_result(result, |receipt| receipt.map(|r| r.to_json()).unwrap_or_default()))
+        })
+    })
+}
+
+// Replaces the HTTP client shared by `rpc_call_ffi`, `rpc_batch_call_ffi`, `api_call_ffi`
+// and `api_request_ffi` with one built from a JSON configuration (timeouts, retries, rate limits,
+// see `HttpClientConfig::from_json`). Returns an `FFI_OK` result without data, or an
+// `FFI_INVALID_ARGUMENT` one when the configuration is invalid.
+#[no_mangle]
+pub extern "C" fn http_client_configure_ffi(config_json: *const c_char) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if config_json.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let config_json = unsafe { CStr::from_ptr(config_json).to_string_lossy().to_string() };
+
+        let config = match HttpClientConfig::from_json(&config_json) {
+            Ok(config) => config,
+            Err(err) => return FfiResult::invalid_argument(&format!("{:#}", err)),
+        };
+
+        match configure_shared_client(config) {
+            Ok(()) => FfiResult::ok(),
+            Err(err) => FfiResult::failed(&err),
+        }
+    })
+}
+
+// Enables the response cache used by `rpc_call_ffi`, `api_call_ffi`, `api_request_ffi` and
+// the `eth_*` helpers, or disables it when `config_json` is null. See
+// `CacheConfig::from_json`. Returns an `FFI_OK` result without data, or an
+// `FFI_INVALID_ARGUMENT` one when the configuration is invalid.
+#[no_mangle]
+pub extern "C" fn response_cache_configure_ffi(config_json: *const c_char) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let config = if config_json.is_null() {
+            None
+        } else {
+            let config_json = unsafe { CStr::from_ptr(config_json).to_string_lossy().to_string() };
+            match CacheConfig::from_json(&config_json) {
+                Ok(config) => Some(config),
+                Err(err) => return FfiResult::invalid_argument(&format!("{:#}", err)),
+            }
+        };
+
+        match configure_shared_cache(config) {
+            Ok(()) => FfiResult::ok(),
+            Err(err) => FfiResult::failed(&err),
+        }
+    })
+}
+
+// Configures how `substreams_call_ffi` and `firehose_call_ffi` fail over between several
+// endpoints, see `EndpointPoolConfig::from_json`. A null configuration restores the
+// defaults. Returns an `FFI_OK` result without data, or `FFI_INVALID_ARGUMENT`.
+#[no_mangle]
+pub extern "C" fn endpoint_pool_configure_ffi(config_json: *const c_char) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let config = if config_json.is_null() {
+            EndpointPoolConfig::default()
+        } else {
+            let config_json = unsafe { CStr::from_ptr(config_json).to_string_lossy().to_string() };
+            match EndpointPoolConfig::from_json(&config_json) {
+                Ok(config) => config,
+                Err(err) => return FfiResult::invalid_argument(&format!("{:#}", err)),
+            }
+        };
+
+        configure_shared_pool(config);
+        FfiResult::ok()
+    })
+}
+
+// Drops every cached response. Returns an `FFI_OK` result without data, or an `FFI_ERROR`
+// one when the disk cache cannot be emptied.
+#[no_mangle]
+pub extern "C" fn response_cache_clear_ffi() -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some(cache) = shared_cache() else {
+            return FfiResult::ok();
+        };
+
+        match cache.clear() {
+            Ok(()) => FfiResult::ok(),
+            Err(err) => FfiResult::failed(&err),
+        }
+    })
+}
+
+// Called for every notification with its JSON `result` (`is_error` = 0), or once with an
+// error message (`is_error` = 1) when the subscription ends on its own. The string is only
+// valid during the call.
+pub type WsSubscriptionCallback =
+    Option<extern "C" fn(user_data: *mut c_void, json: *const c_char, is_error: i32)>;
+
+// Host pointer handed back to the callback, the host is responsible for its thread safety.
+struct UserData(*mut c_void);
+unsafe impl Send for UserData {}
+
+pub struct WsSubscriptionHandle {
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+// Subscribes with `eth_subscribe` over WebSocket. `kind` is `newHeads`, `logs` or
+// `newPendingTransactions`, `filter_json` the optional log filter. Notifications are
+// delivered to `callback` from a runtime thread. Stores the handle to pass to
+// `ws_unsubscribe_ffi` in `out_handle` and returns an `FFI_OK` result without data, or an
+// `FFI_INVALID_ARGUMENT` one when the arguments are invalid.
+#[no_mangle]
+pub extern "C" fn ws_subscribe_ffi(
+    ws_endpoint: *const c_char,
+    kind: *const c_char,
+    filter_json: *const c_char,
+    callback: WsSubscriptionCallback,
+    user_data: *mut c_void,
+    out_handle: *mut *mut WsSubscriptionHandle,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some(callback) = callback else {
+            return FfiResult::invalid_argument("Null callback passed");
+        };
+        if ws_endpoint.is_null() || kind.is_null() || out_handle.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let ws_endpoint = unsafe { CStr::from_ptr(ws_endpoint).to_string_lossy().to_string() };
+        let kind = unsafe { CStr::from_ptr(kind).to_string_lossy().to_string() };
+        let filter = if filter_json.is_null() {
+            None
+        } else {
+            let filter_json = unsafe { CStr::from_ptr(filter_json).to_string_lossy().to_string() };
+            match serde_json::from_str(&filter_json) {
+                Ok(filter) => Some(filter),
+                Err(err) => return FfiResult::invalid_argument(&format!("invalid filter JSON: {}", err)),
+            }
+        };
+
+        let kind = match SubscriptionKind::parse(&kind, filter) {
+            Ok(kind) => kind,
+            Err(err) => return FfiResult::invalid_argument(&format!("{:#}", err)),
+        };
+
+        let (stop, mut stop_rx) = oneshot::channel::<()>();
+        let user_data = UserData(user_data);
+
+        let task = RUNTIME.spawn(async move {
+            let user_data = user_data;
+            let mut subscription = WsSubscription::subscribe(&ws_endpoint, kind);
+
+            loop {
+                // Stopping comes first, no callback runs once it was asked for.
+                let item = tokio::select! {
+                    biased;
+                    _ = &mut stop_rx => {
+                        subscription.unsubscribe().await;
+                        return;
+                    }
+                    item = subscription.next() => item,
+                };
+
+                let (text, is_error) = match item {
+                    Some(Ok(value)) => (value.to_string(), 0),
+                    Some(Err(err)) => (format!("{:#}", err), 1),
+                    None => return,
+                };
+                let text = CString::new(text).unwrap_or_default();
+                callback(user_data.0, text.as_ptr(), is_error);
+
+                if is_error == 1 {
+                    return;
+                }
+            }
+        });
+
+        let handle = Box::new(WsSubscriptionHandle {
+            stop: Some(stop),
+            task: Some(task),
+        });
+        unsafe { *out_handle = Box::into_raw(handle) };
+        FfiResult::ok()
+    })
+}
+
+// Stops the task behind a subscription or stream handle and waits for it to end. A runtime
+// thread, e.g. one running a callback, cannot wait: the task is only signalled, and stops
+// before its next callback. A callback already running on another thread may then still
+// complete after the handle is released.
+fn stop_task(stop: Option<oneshot::Sender<()>>, task: Option<tokio::task::JoinHandle<()>>) {
+    if let Some(stop) = stop {
+        let _ = stop.send(());
+    }
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return;
+    }
+    if let Some(task) = task {
+        let _ = RUNTIME.block_on(task);
+    }
+}
+
+// Unsubscribes and releases the handle. No callback runs once this returns, see
+// `stop_task` for calls made from a callback. Returns an `FFI_OK` result without data.
+#[no_mangle]
+pub extern "C" fn ws_unsubscribe_ffi(handle: *mut WsSubscriptionHandle) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if handle.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let mut handle = unsafe { Box::from_raw(handle) };
+        stop_task(handle.stop.take(), handle.task.take());
+
+        FfiResult::ok()
+    })
+}
+
+// Sends a GET request and returns the response body as text.
+#[no_mangle]
+pub extern "C" fn api_call_ffi(
+    api_url: *const c_char,
+    optional_headers: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| block_on_call(prepare_api_call(api_url, optional_headers)))
+}
+
+// `api_call_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn api_call_async_ffi(
+    api_url: *const c_char,
+    optional_headers: *const c_char,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_api_call(api_url, optional_headers);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
+
+fn prepare_api_call(api_url: *const c_char, optional_headers: *const c_char) -> FfiCall {
+    if api_url.is_null() {
+        return invalid_call("Null pointer passed");
+    }
+
+    let api_url = unsafe { CStr::from_ptr(api_url).to_string_lossy().to_string() };
+    let optional_headers = unsafe {
+        if optional_headers.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(optional_headers).to_string_lossy().to_string())
+        }
+    };
+
+    Box::pin(async move {
+        let result = api_call(&api_url, optional_headers.as_deref()).await;
+
+        SendResult(match result {
+            Ok(response) => FfiResult::text(response),
+            Err(err) => FfiResult::failed(&err),
+        })
+    })
+}
+
+// Sends an HTTP request with any method. `options_json` may be null or hold `method`,
+// `headers`, `query` (values may be arrays to repeat a parameter), and either `json` for a
+// JSON body or `body` for a raw string body. Returns `{"status", "headers", "body"}` for
+// every response, including error statuses. The call only fails when no response was
+// received, the error kind being `invalid_request` or `transport`.
+#[no_mangle]
+pub extern "C" fn api_request_ffi(
+    api_url: *const c_char,
+    options_json: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| block_on_call(prepare_api_request(api_url, options_json)))
+}
+
+// `api_request_ffi` without blocking, see `spawn_call`.
+#[no_mangle]
+pub extern "C" fn api_request_async_ffi(
+    api_url: *const c_char,
+    options_json: *const c_char,
+    callback: FfiCompletionCallback,
+    user_data: *mut c_void,
+    out_request: *mut *mut AsyncRequest,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let call = prepare_api_request(api_url, options_json);
+        spawn_call(call, callback, user_data, out_request)
+    })
+}
+
+fn prepare_api_request(api_url: *const c_char, options_json: *const c_char) -> FfiCall {
+    if api_url.is_null() {
+        return invalid_call("Null pointer passed");
+    }
+
+    let api_url = unsafe { CStr::from_ptr(api_url).to_string_lossy().to_string() };
+    let options_json = if options_json.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(options_json).to_string_lossy().to_string() })
+    };
+
+    Box::pin(async move {
+        SendResult(match api_request_call(&api_url, options_json.as_deref()).await {
+            Ok(response) => FfiResult::json(&response.to_json()),
+            Err(err) => FfiResult::error(err.to_json()),
+        })
+    })
+}
+
+// Opens an iterator over the pages of a paginated API. `options_json` is as in
+// `api_request_ffi`, `pagination_json` describes the strategy: `{"type": "page", ...}`,
+// `"offset"`, `"cursor"` or `"link"` (see `Pagination::from_json`). `max_pages` of 0 means
+// no limit. Stores the iterator to release with `free_api_page_iterator` in `out_pages`
+// and returns an `FFI_OK` result without data, or an error result with the
+// `invalid_request` kind when the arguments are invalid.
+#[no_mangle]
+pub extern "C" fn api_paginate_ffi(
+    api_url: *const c_char,
+    options_json: *const c_char,
+    pagination_json: *const c_char,
+    max_pages: usize,
+    out_pages: *mut *mut ApiPageStream,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if api_url.is_null() || pagination_json.is_null() || out_pages.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let api_url = unsafe { CStr::from_ptr(api_url).to_string_lossy().to_string() };
+        let options_json = if options_json.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(options_json).to_string_lossy().to_string() })
+        };
+        let pagination_json = unsafe { CStr::from_ptr(pagination_json).to_string_lossy().to_string() };
+        let max_pages = if max_pages == 0 { None } else { Some(max_pages) };
+
+        match api_paginate(&api_url, options_json.as_deref(), &pagination_json, max_pages) {
+            Ok(pages) => {
+                unsafe { *out_pages = Box::into_raw(Box::new(pages)) };
+                FfiResult::ok()
+            }
+            Err(err) => FfiResult::error(err.to_json()),
+        }
+    })
+}
+
+// Requests the next page and returns it as `{"page", "url", "status", "headers", "body"}`.
+// The status is `FFI_END` once there are no more pages. An error status fails with kind
+// `http`, and the iterator ends after an error.
+#[no_mangle]
+pub extern "C" fn api_page_next_ffi(pages: *mut ApiPageStream) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if pages.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let pages = unsafe { &mut *pages };
+        block_on_call(async {
+            SendResult(match pages.next().await {
+                Some(Ok(page)) => FfiResult::json(&page.to_json()),
+                Some(Err(err)) => FfiResult::error(err.to_json()),
+                None => FfiResult::end(),
+            })
+        })
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_api_page_iterator(pages: *mut ApiPageStream) {
+    catch_panic((), || {
+        if pages.is_null() {
+            return;
+        }
+
+        unsafe {
+            let _ = Box::from_raw(pages);
+        }
+    })
+}
+
+// Called with the JSON of every event (`is_error` = 0): `{"id", "event", "data", "retry"}`
+// for SSE, the parsed line for JSON lines. Called with a `{"kind", "message",
+// "reconnect_in_ms"}` error (`is_error` = 2) each time an SSE connection is lost and about
+// to be reopened, and once with a `{"kind", "message"}` error (`is_error` = 1) when the
+// stream fails. The string is only valid during the call.
+pub type ApiStreamCallback =
+    Option<extern "C" fn(user_data: *mut c_void, json: *const c_char, is_error: i32)>;
+
+pub struct ApiStreamHandle {
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+// Opens a streaming HTTP request, `format` being `sse` or `jsonl`, and delivers its events
+// to `callback` from a runtime thread. SSE streams reconnect with `Last-Event-ID` when the
+// connection drops. `options_json` is as in `api_request_ffi`. Stores the handle to pass
+// to `api_stream_close_ffi` in `out_handle` and returns an `FFI_OK` result without data,
+// or an error result with the `invalid_request` kind when the arguments are invalid.
+#[no_mangle]
+pub extern "C" fn api_stream_ffi(
+    api_url: *const c_char,
+    options_json: *const c_char,
+    format: *const c_char,
+    callback: ApiStreamCallback,
+    user_data: *mut c_void,
+    out_handle: *mut *mut ApiStreamHandle,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        let Some(callback) = callback else {
+            return FfiResult::invalid_argument("Null callback passed");
+        };
+        if api_url.is_null() || format.is_null() || out_handle.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let api_url = unsafe { CStr::from_ptr(api_url).to_string_lossy().to_string() };
+        let options_json = if options_json.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(options_json).to_string_lossy().to_string() })
+        };
+        let format = unsafe { CStr::from_ptr(format).to_string_lossy().to_string() };
+
+        let mut events = match api_stream(&api_url, options_json.as_deref(), &format) {
+            Ok(events) => events,
+            Err(err) => return FfiResult::error(err.to_json()),
+        };
+
+        let (stop, mut stop_rx) = oneshot::channel::<()>();
+        let user_data = UserData(user_data);
+
+        let task = RUNTIME.spawn(async move {
+            let user_data = user_data;
+
+            loop {
+                // Stopping comes first, no callback runs once it was asked for.
+                let item = tokio::select! {
+                    biased;
+                    _ = &mut stop_rx => return,
+                    item = events.next() => item,
+                };
+
+                let (text, is_error) = match item {
+                    Some(Ok(event @ StreamEvent::Reconnecting { .. })) => (event.to_json().to_string(), 2),
+                    Some(Ok(event)) => (event.to_json().to_string(), 0),
+                    Some(Err(err)) => (err.to_json().to_string(), 1),
+                    None => return,
+                };
+                let text = CString::new(text).unwrap_or_default();
+                callback(user_data.0, text.as_ptr(), is_error);
+
+                if is_error == 1 {
+                    return;
+                }
+            }
+        });
+
+        let handle = Box::new(ApiStreamHandle {
+            stop: Some(stop),
+            task: Some(task),
+        });
+        unsafe { *out_handle = Box::into_raw(handle) };
+        FfiResult::ok()
+    })
+}
+
+// Closes the stream and releases the handle. No callback runs once this returns, see
+// `stop_task` for calls made from a callback. Returns an `FFI_OK` result without data.
+#[no_mangle]
+pub extern "C" fn api_stream_close_ffi(handle: *mut ApiStreamHandle) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if handle.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let mut handle = unsafe { Box::from_raw(handle) };
+        stop_task(handle.stop.take(), handle.task.take());
+
+        FfiResult::ok()
+    })
+}
+
+fn sink_service_ffi(endpoint_url: *const c_char, command: SinkCommand) -> *mut FfiResult {
+    if endpoint_url.is_null() {
+        return FfiResult::invalid_argument("Null pointer passed");
+    }
+
+    let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+
+    block_on_call(async move {
+        SendResult(match sink_service_call(endpoint_url, command).await {
+            Ok(value) => FfiResult::json(&value),
+            Err(err) => FfiResult::failed(&err),
+        })
+    })
+}
+
+// Deploys a package to the provider's sink service. `parameters` is an optional JSON object
+// of string values.
+#[no_mangle]
+pub extern "C" fn sink_deploy_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    parameters: *const c_char,
+    development_mode: bool,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if package_file.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+        let parameters = unsafe {
+            if parameters.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(parameters).to_string_lossy().to_string())
+            }
+        };
+
+        sink_service_ffi(
+            endpoint_url,
+            SinkCommand::Deploy {
+                package_file,
+                parameters,
+                development_mode,
+            },
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn sink_update_ffi(
+    endpoint_url: *const c_char,
+    deployment_id: *const c_char,
+    package_file: *const c_char,
+    reset: bool,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if deployment_id.is_null() || package_file.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let deployment_id = unsafe { CStr::from_ptr(deployment_id).to_string_lossy().to_string() };
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+
+        sink_service_ffi(
+            endpoint_url,
+            SinkCommand::Update {
+                deployment_id,
+                package_file,
+                reset,
+            },
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn sink_list_ffi(endpoint_url: *const c_char) -> *mut FfiResult {
+    catch_panic_result(|| sink_service_ffi(endpoint_url, SinkCommand::List))
+}
+
+// Issues a command taking only a deployment id: "info", "pause", "stop", "resume" or "remove".
+#[no_mangle]
+pub extern "C" fn sink_deployment_ffi(
+    endpoint_url: *const c_char,
+    action: *const c_char,
+    deployment_id: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if action.is_null() || deployment_id.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let action = unsafe { CStr::from_ptr(action).to_string_lossy().to_string() };
+        let deployment_id = unsafe { CStr::from_ptr(deployment_id).to_string_lossy().to_string() };
+
+        let command = match action.as_str() {
+            "info" => SinkCommand::Info(deployment_id),
+            "pause" => SinkCommand::Pause(deployment_id),
+            "stop" => SinkCommand::Stop(deployment_id),
+            "resume" => SinkCommand::Resume(deployment_id),
+            "remove" => SinkCommand::Remove(deployment_id),
+            other => {
+                return FfiResult::invalid_argument(&format!("unknown sink action '{}'", other))
+            }
+        };
+
+        sink_service_ffi(endpoint_url, command)
+    })
+}
+
+// Polls a deployment until it reaches one of `statuses`, comma separated status names such
+// as "RUNNING,PAUSED". Fails early when the deployment reports FAILING, or once `timeout_ms`
+// elapsed.
+#[no_mangle]
+pub extern "C" fn sink_wait_for_status_ffi(
+    endpoint_url: *const c_char,
+    deployment_id: *const c_char,
+    statuses: *const c_char,
+    poll_interval_ms: u64,
+    timeout_ms: u64,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if deployment_id.is_null() || statuses.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let deployment_id = unsafe { CStr::from_ptr(deployment_id).to_string_lossy().to_string() };
+        let statuses = unsafe { CStr::from_ptr(statuses).to_string_lossy() };
+        let statuses = match parse_statuses(&statuses) {
+            Ok(statuses) => statuses,
+            Err(err) => return FfiResult::invalid_argument(&err.to_string()),
+        };
+
+        sink_service_ffi(
+            endpoint_url,
+            SinkCommand::WaitForStatus {
+                deployment_id,
+                statuses,
+                poll_interval: std::time::Duration::from_millis(poll_interval_ms),
+                timeout: std::time::Duration::from_millis(timeout_ms),
+            },
+        )
+    })
+}
+
+// Streams a module outputting `EntityChanges` into a new entity store, stored in
+// `out_store` on success. The store is queried with `entity_store_get_ffi` /
+// `entity_store_list_ffi` and released with `free_entity_store`. Returns an `FFI_OK` result
+// without data, or the error that stopped the stream.
+#[no_mangle]
+pub extern "C" fn substreams_entities_call_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+    out_store: *mut *mut EntityStore,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() || out_store.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+        let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
+        let range = unsafe {
+            if range.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(range).to_string_lossy().to_string())
+            }
+        };
+
+        block_on_call(async move {
+            SendResult(match substreams_entities_call(endpoint_url, &package_file, &module_name, range).await {
+                Ok(store) => {
+                    unsafe { *out_store = Box::into_raw(Box::new(store)) };
+                    FfiResult::ok()
+                }
+                Err(err) => FfiResult::failed(&err),
+            })
+        })
+    })
+}
+
+// Streams a store module over `range` and returns the state it reached as a JSON object of
+// its keys, values decoded according to the module's value type and update policy.
+#[no_mangle]
+pub extern "C" fn substreams_store_call_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+        let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
+        let range = unsafe {
+            if range.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(range).to_string_lossy().to_string())
+            }
+        };
+
+        block_on_call(Box::pin(async move {
+            let result = substreams_store_call(endpoint_url, &package_file, &module_name, range)
+                .await
+                .and_then(|tracker| tracker.to_json());
+
+            SendResult(match result {
+                Ok(state) => FfiResult::json(&state),
+                Err(err) => FfiResult::failed(&err),
+            })
+        }))
+    })
+}
+
+// Stores an empty entity store, fed by the host with `entity_store_apply_ffi`, in
+// `out_store`. Returns an `FFI_OK` result without data.
+#[no_mangle]
+pub extern "C" fn entity_store_new_ffi(out_store: *mut *mut EntityStore) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if out_store.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        unsafe { *out_store = Box::into_raw(Box::new(EntityStore::new())) };
+        FfiResult::ok()
+    })
+}
+
+// Applies an encoded `EntityChanges` message produced at `block_num`. Returns an `FFI_OK`
+// result without data, or the error when the message cannot be decoded or applied.
+#[no_mangle]
+pub extern "C" fn entity_store_apply_ffi(
+    store: *mut EntityStore,
+    block_num: u64,
+    data: *const u8,
+    length: usize,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if store.is_null() || (data.is_null() && length > 0) {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let bytes = if length == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(data, length) }
+        };
+        let store = unsafe { &mut *store };
+
+        match decode_entity_changes(bytes).and_then(|changes| store.apply(block_num, &changes)) {
+            Ok(()) => FfiResult::ok(),
+            Err(err) => FfiResult::failed(&err),
+        }
+    })
+}
+
+// Reverts every change applied after `block_num`, the last valid block of an undo signal.
+// Returns an `FFI_OK` result without data.
+#[no_mangle]
+pub extern "C" fn entity_store_revert_ffi(store: *mut EntityStore, block_num: u64) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if store.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        unsafe { &mut *store }.revert_to(block_num);
+        FfiResult::ok()
+    })
+}
+
+// Forgets the history up to `block_num` included, the final block height of the stream.
+// Those blocks can no longer be reverted and their history no longer takes memory. Returns
+// an `FFI_OK` result without data.
+#[no_mangle]
+pub extern "C" fn entity_store_finalize_ffi(store: *mut EntityStore, block_num: u64) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if store.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        unsafe { &mut *store }.finalize(block_num);
+        FfiResult::ok()
+    })
+}
+
+// Returns the entity as a JSON object, or `null` when it does not exist.
+#[no_mangle]
+pub extern "C" fn entity_store_get_ffi(
+    store: *const EntityStore,
+    entity_type: *const c_char,
+    id: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if store.is_null() || entity_type.is_null() || id.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let entity_type = unsafe { CStr::from_ptr(entity_type).to_string_lossy().to_string() };
+        let id = unsafe { CStr::from_ptr(id).to_string_lossy().to_string() };
+        let store = unsafe { &*store };
+
+        FfiResult::json(&store.get_json(&entity_type, &id))
+    })
+}
+
+// Returns every entity of `entity_type` as a JSON array.
+#[no_mangle]
+pub extern "C" fn entity_store_list_ffi(
+    store: *const EntityStore,
+    entity_type: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if store.is_null() || entity_type.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let entity_type = unsafe { CStr::from_ptr(entity_type).to_string_lossy().to_string() };
+        let store = unsafe { &*store };
+
+        FfiResult::json(&store.list_json(&entity_type))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_entity_store(store: *mut EntityStore) {
+    catch_panic((), || {
+        if store.is_null() {
+            return;
+        }
+
+        unsafe {
+            let _ = Box::from_raw(store);
+        }
+    })
+}
+
+// Lands a module's `EntityChanges` into a SQLite database, resuming from the cursor stored
+// in it. Returns the last cursor as text, empty when no block was processed.
+#[no_mangle]
+pub extern "C" fn substreams_sqlite_call_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+    database_path: *const c_char,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() || database_path.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+        let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
+        let database_path = unsafe { CStr::from_ptr(database_path).to_string_lossy().to_string() };
+        let range = unsafe {
+            if range.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(range).to_string_lossy().to_string())
+            }
+        };
+
+        block_on_call(async move {
+            let result =
+                substreams_sqlite_call(endpoint_url, &package_file, &module_name, range, &database_path)
+                    .await;
+
+            SendResult(match result {
+                Ok(cursor) => FfiResult::text(cursor.unwrap_or_default()),
+                Err(err) => FfiResult::failed(&err),
+            })
+        })
+    })
+}
+
+// Writes a module's decoded outputs to rolling files in `output_dir`. `format` is one of
+// `jsonl`, `csv` or `parquet`. Returns the last cursor as text, as
+// `substreams_sqlite_call_ffi` does.
+#[no_mangle]
+pub extern "C" fn substreams_file_sink_call_ffi(
+    endpoint_url: *const c_char,
+    package_file: *const c_char,
+    module_name: *const c_char,
+    range: *const c_char,
+    output_dir: *const c_char,
+    format: *const c_char,
+    blocks_per_file: u64,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if endpoint_url.is_null()
+            || package_file.is_null()
+            || module_name.is_null()
+            || output_dir.is_null()
+            || format.is_null()
+        {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+        let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+        let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
+        let output_dir = unsafe { CStr::from_ptr(output_dir).to_string_lossy().to_string() };
+        let format = unsafe { CStr::from_ptr(format).to_string_lossy().to_string() };
+        let range = unsafe {
+            if range.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(range).to_string_lossy().to_string())
+            }
+        };
+
+        let format: FileFormat = match format.parse() {
+            Ok(format) => format,
+            Err(err) => return FfiResult::invalid_argument(&format!("{:#}", err)),
+        };
+
+        block_on_call(async move {
+            let result = substreams_file_sink_call(
+                endpoint_url,
+                &package_file,
+                &module_name,
+                range,
+                &output_dir,
+                format,
+                blocks_per_file,
+            )
+            .await;
+
+            SendResult(match result {
+                Ok(cursor) => FfiResult::text(cursor.unwrap_or_default()),
+                Err(err) => FfiResult::failed(&err),
+            })
+        })
+    })
+}
+
+// Starts a local mock Substreams server, so that hosts can test their Substreams calls
+// without a provider. Every connection replays the next session of `sessions_json`, a JSON
+// array of arrays of events (see `MockEvent::from_json`). Stores the server in `out_server`
+// and returns an `FFI_OK` result without data, or an `FFI_INVALID_ARGUMENT` one when the
+// script is invalid. The server stops when released with `free_mock_substreams_server`.
+#[no_mangle]
+pub extern "C" fn mock_substreams_server_start_ffi(
+    sessions_json: *const c_char,
+    out_server: *mut *mut MockSubstreamsServer,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if sessions_json.is_null() || out_server.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let sessions_json = unsafe { CStr::from_ptr(sessions_json).to_string_lossy().to_string() };
+        let sessions = serde_json::from_str(&sessions_json)
+            .map_err(anyhow::Error::from)
+            .and_then(|sessions| mock_sessions_from_json(&sessions));
+        let sessions = match sessions {
+            Ok(sessions) => sessions,
+            Err(err) => return FfiResult::invalid_argument(&format!("{:#}", err)),
+        };
+
+        block_on_call(async move {
+            SendResult(match MockSubstreamsServer::start(sessions).await {
+                Ok(server) => {
+                    unsafe { *out_server = Box::into_raw(Box::new(server)) };
+                    FfiResult::ok()
+                }
+                Err(err) => FfiResult::failed(&err),
+            })
+        })
+    })
+}
+
+// Returns the `http://` URL of the server as text, or an `FFI_ERROR` result when the
+// server has stopped with an error.
+#[no_mangle]
+pub extern "C" fn mock_substreams_server_url_ffi(
+    server: *const MockSubstreamsServer,
+) -> *mut FfiResult {
+    catch_panic_result(|| {
+        if server.is_null() {
+            return FfiResult::invalid_argument("Null pointer passed");
+        }
+
+        let server = unsafe { &*server };
+        match server.failure() {
+            Some(failure) => FfiResult::failed(&anyhow::anyhow!("Mock gRPC server failed: {}", failure)),
+            None => FfiResult::text(server.url()),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn free_mock_substreams_server(server: *mut MockSubstreamsServer) {
+    catch_panic((), || {
+        if server.is_null() {
+            return;
+        }
+
+        unsafe {
+            let _ = Box::from_raw(server);
+        }
+    })
+}
+
+// #[no_mangle]
+// pub extern "C" fn substreams_call_ffi(
+//     endpoint_url: *const c_char,
+//     package_file: *const c_char,
+//     module_name: *const c_char,
+//     range: *const c_char,
+// ) -> *mut c_char {
+//     if endpoint_url.is_null() || package_file.is_null() || module_name.is_null() {
+//         return CString::new("Null pointer passed").unwrap().into_raw();
+//     }
+
+//     let endpoint_url = unsafe { CStr::from_ptr(endpoint_url).to_string_lossy().to_string() };
+//     let package_file = unsafe { CStr::from_ptr(package_file).to_string_lossy().to_string() };
+//     let module_name = unsafe { CStr::from_ptr(module_name).to_string_lossy().to_string() };
+//     let range = unsafe {
+//         if range.is_null() {
+//             None
+//         } else {
+//             Some(CStr::from_ptr(range).to_string_lossy().to_string())
+//         }
+//     };
+
+//     let result = RUNTIME.block_on(substreams_call(endpoint_url, &package_file, &module_name, range));
+
+//     match result {
+//         Ok(debug_values) => {
+//             let output = format!("{:?}", debug_values);
+//             CString::new(output).unwrap().into_raw()
+//         }
+//         Err(err) => CString::new(format!("Error: {}", err)).unwrap().into_raw(),
+//     }
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(result: *mut FfiResult) -> (i32, String, serde_json::Value) {
+        let result_ref = unsafe { &*result };
+        let text = if result_ref.data.is_null() {
+            String::new()
+        } else {
+            let data = result_ref.data as *const u8;
+            let bytes = unsafe { std::slice::from_raw_parts(data, result_ref.length) };
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+        let error = if result_ref.error.is_null() {
+            serde_json::Value::Null
+        } else {
+            let error = unsafe { CStr::from_ptr(result_ref.error).to_string_lossy() };
+            serde_json::from_str(&error).unwrap()
+        };
+        let status = result_ref.status;
+        free_ffi_result(result);
+        (status, text, error)
+    }
+
+    #[test]
+    fn panics_return_the_default() {
+        let result = catch_panic(-1, || -> i32 { panic!("boom") });
+        assert_eq!(result, -1);
+        assert_eq!(catch_panic(0, || 7), 7);
+        let (status, _, error) = read(last_panic_ffi());
+        assert_eq!(status, FFI_PANIC);
+        assert_eq!(error["message"], "boom");
+        assert_eq!(read(last_panic_ffi()).0, FFI_OK);
+
+        let (status, text, error) = read(catch_panic_result(|| panic!("boom")));
+        assert_eq!(status, FFI_PANIC);
+        assert_eq!(text, "");
+        assert_eq!(error, serde_json::json!({"kind": "panic", "message": "boom"}));
+    }
+
+    #[test]
+    fn results_own_text_and_errors() {
+        let result = FfiResult::json(&serde_json::json!({"a": 1}));
+        assert_eq!(unsafe { &*result }.length, 7);
+        assert_eq!(read(result), (FFI_OK, "{\"a\":1}".to_string(), serde_json::Value::Null));
+
+        let null = std::ptr::null();
+        let (status, text, error) = read(rpc_call_ffi(null, null, null, 1));
+        assert_eq!(status, FFI_INVALID_ARGUMENT);
+        assert_eq!(text, "");
+        assert_eq!(error["kind"], "invalid_argument");
+
+        // A NUL inside the text does not cut it, and one still follows it.
+        let result = FfiResult::text("cursor\0rest".to_string());
+        let result_ref = unsafe { &*result };
+        assert_eq!(result_ref.length, 11);
+        assert_eq!(unsafe { *(result_ref.data as *const u8).add(11) }, 0);
+        let (_, text, _) = read(result);
+        assert_eq!(text, "cursor\0rest");
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        let bindings = cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", crate_dir))
+            .generate()
+            .unwrap();
+        let mut generated = Vec::new();
+        bindings.write(&mut generated);
+
+        let header = std::fs::read(format!("{}/include/unifiedstreams.h", crate_dir)).unwrap();
+        assert!(
+            generated == header,
+            "include/unifiedstreams.h is out of date, run `cargo run --example generate_header`"
+        );
+    }
+
+    #[test]
+    fn invalid_cache_configurations_are_reported() {
+        let config = CString::new("{\"backend\": \"redis\"}").unwrap();
+        let (status, _, error) = read(response_cache_configure_ffi(config.as_ptr()));
+        assert_eq!(status, FFI_INVALID_ARGUMENT);
+        assert_eq!(error["message"], "unknown cache backend 'redis'");
+
+        let (status, text, error) = read(FfiResult::ok());
+        assert_eq!((status, text, error), (FFI_OK, String::new(), serde_json::Value::Null));
+    }
+
+    #[test]
+    fn byte_arrays_round_trip() {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(b"block");
+        let result = FfiResult::arrays(vec![bytes, Vec::new()]);
+
+        let result_ref = unsafe { &*result };
+        assert_eq!(result_ref.length, 2);
+        let arrays = unsafe {
+            std::slice::from_raw_parts(result_ref.data as *const FfiByteArray, result_ref.length)
+        };
+        let data = unsafe { std::slice::from_raw_parts(arrays[0].data, arrays[0].length) };
+        assert_eq!(data, b"block");
+        assert_eq!(arrays[1].length, 0);
+        free_ffi_result(result);
+    }
+
+    #[test]
+    fn sessions_stream_blocks_from_the_mock_server() {
+        use crate::pb::sf::substreams::v1::{module, Module, Modules, Package};
+
+        let package = Package {
+            modules: Some(Modules {
+                modules: vec![Module {
+                    name: "map_events".to_string(),
+                    initial_block: 1,
+                    kind: Some(module::Kind::KindMap(module::KindMap {
+                        output_type: "proto:mock.Events".to_string(),
+                    })),
+                    ..Default::default()
+                }],
+                binaries: Vec::new(),
+            }),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("unifiedstreams-{}.spkg", std::process::id()));
+        std::fs::write(&path, package.encode_to_vec()).unwrap();
+
+        let script = CString::new(
+            r#"[[{"type": "data", "module": "map_events", "block_num": 1, "output": "0x0102"},
+                 {"type": "undo", "last_valid_block": 0}]]"#,
+        )
+        .unwrap();
+        let mut server = std::ptr::null_mut();
+        assert_eq!(read(mock_substreams_server_start_ffi(script.as_ptr(), &mut server)).0, FFI_OK);
+        assert!(!server.is_null());
+        let (_, url, _) = read(mock_substreams_server_url_ffi(server));
+
+        let url = CString::new(url).unwrap();
+        let package_file = CString::new(path.display().to_string()).unwrap();
+        let module = CString::new("map_events").unwrap();
+        let token = CString::new("test").unwrap();
+        let null = std::ptr::null();
+        let mut session = std::ptr::null_mut();
+        let opened = substreams_session_open_ffi(
+            url.as_ptr(),
+            package_file.as_ptr(),
+            module.as_ptr(),
+            null,
+            null,
+            token.as_ptr(),
+            &mut session,
+        );
+        assert_eq!(read(opened).0, FFI_OK);
+
+        let (status, data, _) = read(substreams_session_next_ffi(session));
+        assert_eq!(status, FFI_OK);
+        let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(data["type"], "data");
+        assert_eq!(data["block_num"], 1);
+        assert_eq!(data["cursor"], "cursor-1");
+        assert_eq!(data["output"], "0x0102");
+
+        let (_, undo, _) = read(substreams_session_next_ffi(session));
+        assert!(undo.contains("\"last_valid_block\":0"));
+        assert_eq!(read(substreams_session_next_ffi(session)).0, FFI_END);
+
+        free_substreams_session(session);
+        free_mock_substreams_server(server);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut session = std::ptr::null_mut();
+        let opened = substreams_session_open_ffi(null, null, null, null, null, null, &mut session);
+        assert_eq!(read(opened).0, FFI_INVALID_ARGUMENT);
+        assert!(session.is_null());
+    }
+
+    #[test]
+    fn handle_calls_report_their_errors() {
+        let script = CString::new(r#"[[{"type": "teleport"}]]"#).unwrap();
+        let mut server = std::ptr::null_mut();
+        let (status, _, error) = read(mock_substreams_server_start_ffi(script.as_ptr(), &mut server));
+        assert_eq!(status, FFI_INVALID_ARGUMENT);
+        assert!(error["message"].as_str().unwrap().contains("teleport"));
+        assert!(server.is_null());
+
+        let url = CString::new("ws://127.0.0.1:1").unwrap();
+        let kind = CString::new("syncing").unwrap();
+        let mut subscription = std::ptr::null_mut();
+        let (status, _, error) = read(ws_subscribe_ffi(
+            url.as_ptr(),
+            kind.as_ptr(),
+            std::ptr::null(),
+            Some(ignore_event),
+            std::ptr::null_mut(),
+            &mut subscription,
+        ));
+        assert_eq!(status, FFI_INVALID_ARGUMENT);
+        assert_eq!(error["message"], "unknown subscription kind 'syncing'");
+
+        let format = CString::new("xml").unwrap();
+        let mut stream = std::ptr::null_mut();
+        let (status, _, error) = read(api_stream_ffi(
+            url.as_ptr(),
+            std::ptr::null(),
+            format.as_ptr(),
+            Some(ignore_event),
+            std::ptr::null_mut(),
+            &mut stream,
+        ));
+        assert_eq!(status, FFI_ERROR);
+        assert_eq!(error["kind"], "invalid_request");
+        assert!(stream.is_null());
+
+        let config = CString::new("{\"timeout_ms\": \"soon\"}").unwrap();
+        assert_eq!(read(http_client_configure_ffi(config.as_ptr())).0, FFI_INVALID_ARGUMENT);
+
+        let config = CString::new("{\"max_consecutive_failures\": 0}").unwrap();
+        let (status, _, error) = read(endpoint_pool_configure_ffi(config.as_ptr()));
+        assert_eq!(status, FFI_INVALID_ARGUMENT);
+        assert!(error["message"].as_str().unwrap().contains("max_consecutive_failures"));
+    }
+
+    #[test]
+    fn entity_stores_are_fed_through_results() {
+        use crate::pb::sf::substreams::entity::v1::{entity_change::Operation, EntityChange, EntityChanges};
+        use prost::Message;
+
+        assert_eq!(read(entity_store_new_ffi(std::ptr::null_mut())).0, FFI_INVALID_ARGUMENT);
+        let mut store = std::ptr::null_mut();
+        assert_eq!(read(entity_store_new_ffi(&mut store)).0, FFI_OK);
+
+        let changes = EntityChanges {
+            entity_changes: vec![EntityChange {
+                entity: "Token".to_string(),
+                id: "a".to_string(),
+                operation: Operation::Create as i32,
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec();
+        assert_eq!(read(entity_store_apply_ffi(store, 2, changes.as_ptr(), changes.len())).0, FFI_OK);
+
+        let garbage = [0xff_u8; 3];
+        let (status, _, error) = read(entity_store_apply_ffi(store, 3, garbage.as_ptr(), garbage.len()));
+        assert_eq!(status, FFI_ERROR);
+        assert!(error["message"].as_str().unwrap().contains("decode entity changes"));
+
+        let entity_type = CString::new("Token").unwrap();
+        let id = CString::new("a").unwrap();
+        assert_ne!(read(entity_store_get_ffi(store, entity_type.as_ptr(), id.as_ptr())).1, "null");
+
+        assert_eq!(read(entity_store_revert_ffi(store, 1)).0, FFI_OK);
+        assert_eq!(read(entity_store_get_ffi(store, entity_type.as_ptr(), id.as_ptr())).1, "null");
+        assert_eq!(read(entity_store_finalize_ffi(store, 1)).0, FFI_OK);
+        assert_eq!(read(entity_store_finalize_ffi(std::ptr::null_mut(), 1)).0, FFI_INVALID_ARGUMENT);
+
+        free_entity_store(store);
+    }
+
+    extern "C" fn ignore_event(_: *mut c_void, _: *const c_char, _: i32) {}
+
+    #[test]
+    fn page_iterators_report_invalid_arguments() {
+        let null = std::ptr::null();
+        let mut pages = std::ptr::null_mut();
+        let (status, _, _) = read(api_paginate_ffi(null, null, null, 0, &mut pages));
+        assert_eq!(status, FFI_INVALID_ARGUMENT);
+        assert!(pages.is_null());
+
+        let url = CString::new("http://127.0.0.1:1/").unwrap();
+        let pagination = CString::new(r#"{"type": "unknown"}"#).unwrap();
+        let (status, _, error) = read(api_paginate_ffi(url.as_ptr(), null, pagination.as_ptr(), 0, &mut pages));
+        assert_eq!(status, FFI_ERROR);
+        assert_eq!(error["kind"], "invalid_request");
+        assert!(pages.is_null());
+    }
+
+    // `user_data` is a boxed sender, owned by the callback since it runs once.
+    extern "C" fn send_result(user_data: *mut c_void, result: *mut FfiResult) {
+        let sender = unsafe { Box::from_raw(user_data as *mut std::sync::mpsc::Sender<usize>) };
+        sender.send(result as usize).unwrap();
+    }
+
+    fn sender_data(sender: &std::sync::mpsc::Sender<usize>) -> *mut c_void {
+        Box::into_raw(Box::new(sender.clone())) as *mut c_void
+    }
+
+    fn receive(receiver: &std::sync::mpsc::Receiver<usize>) -> (i32, String, serde_json::Value) {
+        let result = receiver.recv_timeout(std::time::Duration::from_secs(10)).unwrap();
+        read(result as *mut FfiResult)
+    }
+
+    #[test]
+    fn async_calls_complete_and_cancel() {
+        use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+        // Answers the first request, holds the second one open without answering.
+        let server = RUNTIME.block_on(MockHttpServer::sequence(vec![
+            MockHttpResponse::new("200 OK", &[], "ok"),
+            MockHttpResponse::Hang,
+        ]));
+        let url = CString::new(server.url()).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel::<usize>();
+        let null = std::ptr::null();
+
+        let mut request = std::ptr::null_mut();
+        let spawned = api_call_async_ffi(url.as_ptr(), null, Some(send_result), sender_data(&sender), &mut request);
+        assert_eq!(read(spawned).0, FFI_OK);
+        assert!(!request.is_null());
+        assert_eq!(receive(&receiver), (FFI_OK, "ok".to_string(), serde_json::Value::Null));
+        free_async_request(request);
+
+        let spawned = api_call_async_ffi(url.as_ptr(), null, Some(send_result), sender_data(&sender), &mut request);
+        assert_eq!(read(spawned).0, FFI_OK);
+        assert_eq!(read(async_request_cancel_ffi(request)).0, FFI_OK);
+        let (status, _, error) = receive(&receiver);
+        assert_eq!(status, FFI_CANCELLED);
+        assert_eq!(error["kind"], "cancelled");
+        free_async_request(request);
+
+        // Invalid arguments of the call itself are reported to the callback.
+        let spawned = rpc_call_async_ffi(null, null, null, 1, Some(send_result), sender_data(&sender), &mut request);
+        assert_eq!(read(spawned).0, FFI_OK);
+        assert_eq!(receive(&receiver).0, FFI_INVALID_ARGUMENT);
+        free_async_request(request);
+
+        let mut unspawned = std::ptr::null_mut();
+        let spawned = api_call_async_ffi(url.as_ptr(), null, None, std::ptr::null_mut(), &mut unspawned);
+        assert_eq!(read(spawned).0, FFI_INVALID_ARGUMENT);
+        assert!(unspawned.is_null());
+        assert_eq!(read(async_request_cancel_ffi(std::ptr::null_mut())).0, FFI_INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn blocking_calls_fail_on_runtime_threads() {
+        let url = CString::new("http://127.0.0.1:1/").unwrap();
+        let pagination = CString::new(r#"{"type": "link"}"#).unwrap();
+        let mut pages = std::ptr::null_mut();
+        let opened = api_paginate_ffi(url.as_ptr(), std::ptr::null(), pagination.as_ptr(), 0, &mut pages);
+        assert_eq!(read(opened).0, FFI_OK);
+        let results = RUNTIME.block_on(async {
+            [
+                api_call_ffi(url.as_ptr(), std::ptr::null()) as usize,
+                eth_block_number_ffi(url.as_ptr()) as usize,
+                api_page_next_ffi(pages) as usize,
+            ]
+        });
+        free_api_page_iterator(pages);
+
+        for result in results {
+            let (status, _, error) = read(result as *mut FfiResult);
+            assert_eq!(status, FFI_ERROR);
+            assert_eq!(error["kind"], "runtime_thread");
+        }
+    }
+
+    #[test]
+    fn handles_close_from_runtime_threads() {
+        let url = CString::new("http://127.0.0.1:1/").unwrap();
+        let format = CString::new("sse").unwrap();
+        let mut stream = std::ptr::null_mut();
+        let (status, _, _) = read(api_stream_ffi(
+            url.as_ptr(),
+            std::ptr::null(),
+            format.as_ptr(),
+            Some(ignore_event),
+            std::ptr::null_mut(),
+            &mut stream,
+        ));
+        assert_eq!(status, FFI_OK);
+
+        // Blocking on the runtime there would panic, closing only signals the task.
+        let closed = RUNTIME.block_on(async { api_stream_close_ffi(stream) });
+        assert_eq!(read(closed).0, FFI_OK);
+        assert_eq!(read(api_stream_close_ffi(std::ptr::null_mut())).0, FFI_INVALID_ARGUMENT);
+
+        let url = CString::new("ws://127.0.0.1:1").unwrap();
+        let kind = CString::new("newHeads").unwrap();
+        let mut subscription = std::ptr::null_mut();
+        let (status, _, _) = read(ws_subscribe_ffi(
+            url.as_ptr(),
+            kind.as_ptr(),
+            std::ptr::null(),
+            Some(ignore_event),
+            std::ptr::null_mut(),
+            &mut subscription,
+        ));
+        assert_eq!(status, FFI_OK);
+
+        let closed = RUNTIME.block_on(async { ws_unsubscribe_ffi(subscription) });
+        assert_eq!(read(closed).0, FFI_OK);
+        assert_eq!(read(ws_unsubscribe_ffi(std::ptr::null_mut())).0, FFI_INVALID_ARGUMENT);
+    }
+}
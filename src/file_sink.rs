@@ -0,0 +1,912 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Error};
+use parquet::{
+    basic::{LogicalType, Repetition, Type as PhysicalType},
+    data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type as SchemaType,
+};
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, FieldDescriptor, Kind, MessageDescriptor, SerializeOptions,
+    Value as ReflectValue,
+};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::pb::sf::substreams::v1::{module::Kind as ModuleKind, Clock, Package};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+// Nested messages deeper than this are written as a single JSON column, which also stops
+// recursive message types from expanding forever.
+const MAX_FLATTEN_DEPTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+impl FileFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Jsonl => "jsonl",
+            FileFormat::Csv => "csv",
+            FileFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for FileFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "json" => Ok(FileFormat::Jsonl),
+            "csv" => Ok(FileFormat::Csv),
+            "parquet" => Ok(FileFormat::Parquet),
+            x => Err(anyhow!("unknown file format '{}'", x)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Int64,
+    UInt64,
+    Double,
+    Bool,
+    String,
+}
+
+// A flattened column: nested message fields are joined with `.`, e.g. `transfer.amount`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+impl Column {
+    fn new(name: &str, column_type: ColumnType) -> Self {
+        Column {
+            name: name.to_string(),
+            column_type,
+        }
+    }
+}
+
+// Decodes module outputs into JSON rows using the protobuf descriptors shipped in the
+// package. Outputs wrapping a single repeated message field (e.g. `Events { repeated Event
+// events = 1; }`) are written as one row per element.
+pub struct OutputDecoder {
+    message: MessageDescriptor,
+    rows_field: Option<FieldDescriptor>,
+}
+
+impl OutputDecoder {
+    pub fn from_package(package: &Package, module_name: &str) -> Result<Self, Error> {
+        let module = package
+            .modules
+            .as_ref()
+            .and_then(|modules| modules.modules.iter().find(|m| m.name == module_name))
+            .ok_or_else(|| anyhow!("module '{}' not found in package", module_name))?;
+
+        let output_type = match &module.kind {
+            Some(ModuleKind::KindMap(map)) => map.output_type.trim_start_matches("proto:"),
+            _ => return Err(anyhow!("module '{}' is not a map module", module_name)),
+        };
+
+        let mut pool = DescriptorPool::new();
+        pool.add_file_descriptor_protos(package.proto_files.iter().cloned())
+            .context("load package protobuf descriptors")?;
+
+        Self::new(&pool, output_type)
+    }
+
+    pub fn new(pool: &DescriptorPool, type_name: &str) -> Result<Self, Error> {
+        let message = pool
+            .get_message_by_name(type_name)
+            .ok_or_else(|| anyhow!("message type '{}' not found in package", type_name))?;
+
+        let fields: Vec<FieldDescriptor> = message.fields().collect();
+        let rows_field = match fields.as_slice() {
+            [field] if field.is_list() && matches!(field.kind(), Kind::Message(_)) => {
+                Some(field.clone())
+            }
+            _ => None,
+        };
+
+        Ok(OutputDecoder {
+            message,
+            rows_field,
+        })
+    }
+
+    fn row_message(&self) -> MessageDescriptor {
+        match self.rows_field.as_ref().map(FieldDescriptor::kind) {
+            Some(Kind::Message(message)) => message,
+            _ => self.message.clone(),
+        }
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        let mut columns = Vec::new();
+        flatten_columns(&self.row_message(), "", 0, &mut columns);
+        columns
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<JsonValue>, Error> {
+        let message = DynamicMessage::decode(self.message.clone(), bytes)
+            .context(format!("decode output as {}", self.message.full_name()))?;
+
+        let Some(rows_field) = &self.rows_field else {
+            return Ok(vec![message_to_json(&message)?]);
+        };
+
+        match message.get_field(rows_field).as_ref() {
+            ReflectValue::List(values) => values
+                .iter()
+                .filter_map(ReflectValue::as_message)
+                .map(message_to_json)
+                .collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+fn message_to_json(message: &DynamicMessage) -> Result<JsonValue, Error> {
+    let options = SerializeOptions::new()
+        .use_proto_field_name(true)
+        .skip_default_fields(false)
+        .stringify_64_bit_integers(false);
+
+    Ok(message.serialize_with_options(serde_json::value::Serializer, &options)?)
+}
+
+fn flatten_columns(
+    message: &MessageDescriptor,
+    prefix: &str,
+    depth: usize,
+    columns: &mut Vec<Column>,
+) {
+    for field in message.fields() {
+        let name = format!("{}{}", prefix, field.name());
+
+        let column_type = if field.is_list() || field.is_map() {
+            ColumnType::String
+        } else {
+            match field.kind() {
+                Kind::Int32 | Kind::Int64 | Kind::Sint32 | Kind::Sint64 => ColumnType::Int64,
+                Kind::Sfixed32 | Kind::Sfixed64 | Kind::Uint32 | Kind::Fixed32 => ColumnType::Int64,
+                Kind::Uint64 | Kind::Fixed64 => ColumnType::UInt64,
+                Kind::Double | Kind::Float => ColumnType::Double,
+                Kind::Bool => ColumnType::Bool,
+                // Well-known types have a scalar JSON representation (timestamps, wrappers).
+                Kind::Message(nested)
+                    if !nested.full_name().starts_with("google.protobuf.")
+                        && depth < MAX_FLATTEN_DEPTH =>
+                {
+                    flatten_columns(&nested, &format!("{}.", name), depth + 1, columns);
+                    continue;
+                }
+                _ => ColumnType::String,
+            }
+        };
+
+        columns.push(Column { name, column_type });
+    }
+}
+
+// Resolves a flattened column name against a decoded row.
+fn lookup<'a>(row: &'a JsonValue, name: &str) -> Option<&'a JsonValue> {
+    if let Some(value) = row.get(name) {
+        return Some(value);
+    }
+
+    let (head, rest) = name.split_once('.')?;
+    lookup(row.get(head)?, rest)
+}
+
+fn csv_cell(value: Option<&JsonValue>) -> String {
+    let text = match value {
+        None | Some(JsonValue::Null) => return String::new(),
+        Some(JsonValue::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    };
+
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+struct BlockRows {
+    block_num: u64,
+    cursor: String,
+    rows: Vec<JsonValue>,
+}
+
+// What has been written to the output directory. It is rewritten after every file, so its
+// cursor always matches the last block present in the files and is used to resume.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub module: String,
+    pub format: String,
+    pub start_block: Option<u64>,
+    pub stop_block: Option<u64>,
+    pub cursor: Option<String>,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestFile {
+    pub path: String,
+    pub start_block: u64,
+    pub stop_block: u64,
+    pub rows: usize,
+}
+
+impl Manifest {
+    pub fn read(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(path).context(format!("read manifest '{}'", path.display()))?;
+        let value: JsonValue = serde_json::from_slice(&content)
+            .context(format!("parse manifest '{}'", path.display()))?;
+
+        let files = value["files"]
+            .as_array()
+            .map(|files| {
+                files
+                    .iter()
+                    .map(|file| ManifestFile {
+                        path: file["path"].as_str().unwrap_or_default().to_string(),
+                        start_block: file["start_block"].as_u64().unwrap_or_default(),
+                        stop_block: file["stop_block"].as_u64().unwrap_or_default(),
+                        rows: file["rows"].as_u64().unwrap_or_default() as usize,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Some(Manifest {
+            module: value["module"].as_str().unwrap_or_default().to_string(),
+            format: value["format"].as_str().unwrap_or_default().to_string(),
+            start_block: value["start_block"].as_u64(),
+            stop_block: value["stop_block"].as_u64(),
+            cursor: value["cursor"].as_str().map(str::to_string),
+            files,
+        }))
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        json!({
+            "module": self.module,
+            "format": self.format,
+            "start_block": self.start_block,
+            "stop_block": self.stop_block,
+            "cursor": self.cursor,
+            "files": self.files.iter().map(|file| json!({
+                "path": file.path,
+                "start_block": file.start_block,
+                "stop_block": file.stop_block,
+                "rows": file.rows,
+            })).collect::<Vec<_>>(),
+        })
+    }
+
+    // Written to a temporary file first so a crash never leaves a truncated manifest.
+    fn write(&self, path: &Path) -> Result<(), Error> {
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.to_json())?)
+            .context(format!("write manifest '{}'", tmp.display()))?;
+        fs::rename(&tmp, path).context(format!("write manifest '{}'", path.display()))?;
+        Ok(())
+    }
+}
+
+// Writes decoded module outputs to rolling files in `directory`, one file per range of
+// `blocks_per_file` blocks. Blocks are held in memory until they are final, so undo
+// signals never have to touch a written file. Every row carries the `block_num`,
+// `block_id` and `timestamp` of its block.
+pub struct FileSink {
+    directory: PathBuf,
+    format: FileFormat,
+    decoder: OutputDecoder,
+    columns: Vec<Column>,
+    blocks_per_file: u64,
+    manifest: Manifest,
+    pending: Vec<BlockRows>,
+    // Highest final block height seen, pending blocks above it can still be undone.
+    final_block_height: u64,
+}
+
+impl FileSink {
+    pub fn open<P: AsRef<Path>>(
+        directory: P,
+        format: FileFormat,
+        module_name: &str,
+        decoder: OutputDecoder,
+        blocks_per_file: u64,
+    ) -> Result<Self, Error> {
+        if blocks_per_file == 0 {
+            return Err(anyhow!("blocks per file must be greater than 0"));
+        }
+
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)
+            .context(format!("create output directory '{}'", directory.display()))?;
+
+        let manifest = match Manifest::read(&directory.join(MANIFEST_FILE))? {
+            Some(manifest) if manifest.format != format.extension() => {
+                return Err(anyhow!(
+                    "output directory '{}' already holds {} files",
+                    directory.display(),
+                    manifest.format
+                ))
+            }
+            Some(manifest) if manifest.module != module_name => {
+                return Err(anyhow!(
+                    "output directory '{}' already holds the outputs of module '{}'",
+                    directory.display(),
+                    manifest.module
+                ))
+            }
+            Some(manifest) => manifest,
+            None => Manifest {
+                module: module_name.to_string(),
+                format: format.extension().to_string(),
+                ..Default::default()
+            },
+        };
+
+        let mut columns = vec![
+            Column::new("block_num", ColumnType::UInt64),
+            Column::new("block_id", ColumnType::String),
+            Column::new("timestamp", ColumnType::String),
+        ];
+        columns.extend(decoder.columns());
+
+        Ok(FileSink {
+            directory,
+            format,
+            decoder,
+            columns,
+            blocks_per_file,
+            manifest,
+            pending: Vec::new(),
+            final_block_height: 0,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    // Cursor of the last block written to disk, the stream must resume from it.
+    pub fn cursor(&self) -> Option<String> {
+        self.manifest.cursor.clone()
+    }
+
+    pub fn write_block(
+        &mut self,
+        clock: &Clock,
+        output: Option<&[u8]>,
+        cursor: &str,
+        final_block_height: u64,
+    ) -> Result<(), Error> {
+        let timestamp = clock
+            .timestamp
+            .as_ref()
+            .and_then(|t| chrono::DateTime::from_timestamp(t.seconds, t.nanos as u32))
+            .map(|t| t.to_rfc3339());
+
+        let rows = match output {
+            Some(bytes) => self.decoder.decode(bytes)?,
+            None => Vec::new(),
+        };
+
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                let mut object = match row {
+                    JsonValue::Object(object) => object,
+                    value => Map::from_iter([("value".to_string(), value)]),
+                };
+                object.insert("block_num".to_string(), json!(clock.number));
+                object.insert("block_id".to_string(), json!(clock.id));
+                object.insert("timestamp".to_string(), json!(timestamp));
+                JsonValue::Object(object)
+            })
+            .collect();
+
+        self.pending.push(BlockRows {
+            block_num: clock.number,
+            cursor: cursor.to_string(),
+            rows,
+        });
+
+        self.final_block_height = self.final_block_height.max(final_block_height);
+        self.flush_final(self.final_block_height)
+    }
+
+    // Drops the pending blocks after `last_valid_block`.
+    pub fn undo(&mut self, last_valid_block: u64) {
+        self.pending
+            .retain(|block| block.block_num <= last_valid_block);
+    }
+
+    // Writes the pending final blocks, including a partial last file, and drops the others:
+    // they could still be undone, the stream resumes from the last final block to get them
+    // again. Called once the stream ends.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let final_block_height = self.final_block_height;
+        self.pending
+            .retain(|block| block.block_num <= final_block_height);
+
+        while !self.pending.is_empty() {
+            self.flush_file()?;
+        }
+        Ok(())
+    }
+
+    fn flush_final(&mut self, final_block_height: u64) -> Result<(), Error> {
+        while let Some(first) = self.pending.first() {
+            let file_end = (first.block_num / self.blocks_per_file + 1) * self.blocks_per_file;
+            if file_end > final_block_height + 1 {
+                break;
+            }
+            self.flush_file()?;
+        }
+        Ok(())
+    }
+
+    // Writes the pending blocks belonging to the same file range as the first one.
+    fn flush_file(&mut self) -> Result<(), Error> {
+        let Some(first) = self.pending.first() else {
+            return Ok(());
+        };
+        let file_end = (first.block_num / self.blocks_per_file + 1) * self.blocks_per_file;
+        let count = self
+            .pending
+            .iter()
+            .take_while(|block| block.block_num < file_end)
+            .count();
+        let blocks: Vec<BlockRows> = self.pending.drain(..count).collect();
+
+        let start_block = blocks.first().map(|b| b.block_num).unwrap_or_default();
+        let stop_block = blocks.last().map(|b| b.block_num).unwrap_or_default();
+        let rows: Vec<&JsonValue> = blocks.iter().flat_map(|block| &block.rows).collect();
+
+        if !rows.is_empty() {
+            let name = format!(
+                "{:010}-{:010}.{}",
+                start_block,
+                stop_block,
+                self.format.extension()
+            );
+            let path = self.directory.join(&name);
+
+            match self.format {
+                FileFormat::Jsonl => write_jsonl(&path, &rows)?,
+                FileFormat::Csv => write_csv(&path, &self.columns, &rows)?,
+                FileFormat::Parquet => write_parquet(&path, &self.columns, &rows)?,
+            }
+
+            self.manifest.files.push(ManifestFile {
+                path: name,
+                start_block,
+                stop_block,
+                rows: rows.len(),
+            });
+        }
+
+        self.manifest.start_block = self.manifest.start_block.or(Some(start_block));
+        self.manifest.stop_block = Some(stop_block);
+        self.manifest.cursor = blocks.last().map(|b| b.cursor.clone());
+        self.manifest.write(&self.directory.join(MANIFEST_FILE))
+    }
+}
+
+fn write_jsonl(path: &Path, rows: &[&JsonValue]) -> Result<(), Error> {
+    let file = File::create(path).context(format!("create '{}'", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    for row in rows {
+        serde_json::to_writer(&mut writer, row)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_csv(path: &Path, columns: &[Column], rows: &[&JsonValue]) -> Result<(), Error> {
+    let file = File::create(path).context(format!("create '{}'", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    let header: Vec<String> = columns
+        .iter()
+        .map(|column| csv_cell(Some(&json!(column.name))))
+        .collect();
+    writeln!(writer, "{}", header.join(","))?;
+
+    for row in rows {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|column| csv_cell(lookup(row, &column.name)))
+            .collect();
+        writeln!(writer, "{}", cells.join(","))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn parquet_schema(columns: &[Column]) -> Result<SchemaType, Error> {
+    let mut fields = Vec::with_capacity(columns.len());
+    for column in columns {
+        let (physical_type, logical_type) = match column.column_type {
+            ColumnType::Int64 => (PhysicalType::INT64, None),
+            ColumnType::UInt64 => (
+                PhysicalType::INT64,
+                Some(LogicalType::Integer {
+                    bit_width: 64,
+                    is_signed: false,
+                }),
+            ),
+            ColumnType::Double => (PhysicalType::DOUBLE, None),
+            ColumnType::Bool => (PhysicalType::BOOLEAN, None),
+            ColumnType::String => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+        };
+
+        let field = SchemaType::primitive_type_builder(&column.name, physical_type)
+            .with_repetition(Repetition::OPTIONAL)
+            .with_logical_type(logical_type)
+            .build()?;
+        fields.push(Arc::new(field));
+    }
+
+    Ok(SchemaType::group_type_builder("schema")
+        .with_fields(fields)
+        .build()?)
+}
+
+// Numbers may come as JSON strings (e.g. "NaN" or 64-bit values stringified upstream).
+fn as_number<T: FromStr>(value: &JsonValue) -> Option<T> {
+    match value {
+        JsonValue::String(s) => s.parse().ok(),
+        value => value.to_string().parse().ok(),
+    }
+}
+
+fn write_parquet(path: &Path, columns: &[Column], rows: &[&JsonValue]) -> Result<(), Error> {
+    let file = File::create(path).context(format!("create '{}'", path.display()))?;
+    let props = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, Arc::new(parquet_schema(columns)?), props)?;
+
+    let mut row_group = writer.next_row_group()?;
+    let mut columns = columns.iter();
+    while let Some(mut column_writer) = row_group.next_column()? {
+        let column = columns
+            .next()
+            .ok_or_else(|| anyhow!("parquet schema has more columns than expected"))?;
+
+        let values: Vec<Option<&JsonValue>> = rows
+            .iter()
+            .map(|row| lookup(row, &column.name).filter(|v| !v.is_null()))
+            .collect();
+
+        match column.column_type {
+            ColumnType::Int64 | ColumnType::UInt64 => {
+                let (values, levels) = collect_levels(&values, |value| {
+                    if column.column_type == ColumnType::UInt64 {
+                        // Unsigned values are stored in the same 64 bits.
+                        as_number::<u64>(value).map(|v| v as i64)
+                    } else {
+                        as_number::<i64>(value)
+                    }
+                });
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Double => {
+                let (values, levels) = collect_levels(&values, as_number::<f64>);
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Bool => {
+                let (values, levels) = collect_levels(&values, JsonValue::as_bool);
+                column_writer
+                    .typed::<BoolType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::String => {
+                let (values, levels) = collect_levels(&values, |value| {
+                    Some(ByteArray::from(match value {
+                        JsonValue::String(s) => s.as_str().into(),
+                        value => value.to_string().into_bytes(),
+                    }))
+                });
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+        }
+
+        column_writer.close()?;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+// Splits optional values into the present values and their definition levels.
+fn collect_levels<T>(
+    values: &[Option<&JsonValue>],
+    convert: impl Fn(&JsonValue) -> Option<T>,
+) -> (Vec<T>, Vec<i16>) {
+    let mut present = Vec::with_capacity(values.len());
+    let mut levels = Vec::with_capacity(values.len());
+    for value in values {
+        match value.and_then(&convert) {
+            Some(value) => {
+                present.push(value);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    (present, levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use prost::Message;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    };
+
+    use super::*;
+
+    fn field(
+        name: &str,
+        number: i32,
+        label: Label,
+        r#type: Type,
+        type_name: &str,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(label as i32),
+            r#type: Some(r#type as i32),
+            type_name: (!type_name.is_empty()).then(|| type_name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn decoder() -> (DescriptorPool, OutputDecoder) {
+        let file = FileDescriptorProto {
+            name: Some("test.proto".to_string()),
+            package: Some("test".to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![
+                DescriptorProto {
+                    name: Some("Token".to_string()),
+                    field: vec![field("symbol", 1, Label::Optional, Type::String, "")],
+                    ..Default::default()
+                },
+                DescriptorProto {
+                    name: Some("Transfer".to_string()),
+                    field: vec![
+                        field("from", 1, Label::Optional, Type::String, ""),
+                        field("amount", 2, Label::Optional, Type::Uint64, ""),
+                        field("token", 3, Label::Optional, Type::Message, ".test.Token"),
+                    ],
+                    ..Default::default()
+                },
+                DescriptorProto {
+                    name: Some("Transfers".to_string()),
+                    field: vec![field(
+                        "transfers",
+                        1,
+                        Label::Repeated,
+                        Type::Message,
+                        ".test.Transfer",
+                    )],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let mut pool = DescriptorPool::new();
+        pool.add_file_descriptor_protos([file]).unwrap();
+        let decoder = OutputDecoder::new(&pool, "test.Transfers").unwrap();
+        (pool, decoder)
+    }
+
+    fn output(pool: &DescriptorPool, transfers: &[(&str, u64)]) -> Vec<u8> {
+        let transfer = pool.get_message_by_name("test.Transfer").unwrap();
+        let token = pool.get_message_by_name("test.Token").unwrap();
+
+        let mut message = DynamicMessage::new(pool.get_message_by_name("test.Transfers").unwrap());
+        let values = transfers
+            .iter()
+            .map(|(from, amount)| {
+                let mut t = DynamicMessage::new(token.clone());
+                t.set_field_by_name("symbol", ReflectValue::String("AAA".to_string()));
+
+                let mut m = DynamicMessage::new(transfer.clone());
+                m.set_field_by_name("from", ReflectValue::String(from.to_string()));
+                m.set_field_by_name("amount", ReflectValue::U64(*amount));
+                m.set_field_by_name("token", ReflectValue::Message(t));
+                ReflectValue::Message(m)
+            })
+            .collect();
+        message.set_field_by_name("transfers", ReflectValue::List(values));
+        message.encode_to_vec()
+    }
+
+    fn clock(number: u64) -> Clock {
+        Clock {
+            id: format!("block-{}", number),
+            number,
+            timestamp: Some(prost_types::Timestamp {
+                seconds: 1_700_000_000 + number as i64,
+                nanos: 0,
+            }),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("unifiedstreams-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn writes_final_blocks_to_rolling_csv_files() {
+        let (pool, decoder) = decoder();
+        let dir = temp_dir("csv");
+
+        assert_eq!(
+            decoder.columns(),
+            vec![
+                Column::new("from", ColumnType::String),
+                Column::new("amount", ColumnType::UInt64),
+                Column::new("token.symbol", ColumnType::String),
+            ]
+        );
+
+        let mut sink = FileSink::open(&dir, FileFormat::Csv, "map_transfers", decoder, 10).unwrap();
+        sink.write_block(&clock(8), Some(&output(&pool, &[("a,b", 1)])), "c8", 8)
+            .unwrap();
+        sink.write_block(
+            &clock(9),
+            Some(&output(&pool, &[("c", 2), ("d", 3)])),
+            "c9",
+            8,
+        )
+        .unwrap();
+        sink.write_block(
+            &clock(10),
+            Some(&output(&pool, &[("reorged", 4)])),
+            "c10",
+            8,
+        )
+        .unwrap();
+        // Nothing is final past block 8, no file yet.
+        assert!(sink.manifest().files.is_empty());
+
+        sink.undo(9);
+        sink.write_block(&clock(10), Some(&output(&pool, &[("e", 5)])), "c10b", 9)
+            .unwrap();
+        assert_eq!(sink.manifest().files.len(), 1);
+        assert_eq!(sink.cursor(), Some("c9".to_string()));
+
+        // Block 10 is not final yet, it is dropped and the cursor stays on block 9.
+        sink.finish().unwrap();
+
+        let manifest = Manifest::read(&dir.join(MANIFEST_FILE)).unwrap().unwrap();
+        assert_eq!(manifest.start_block, Some(8));
+        assert_eq!(manifest.stop_block, Some(9));
+        assert_eq!(manifest.cursor, Some("c9".to_string()));
+        assert_eq!(
+            manifest
+                .files
+                .iter()
+                .map(|f| (f.path.as_str(), f.rows))
+                .collect::<Vec<_>>(),
+            vec![("0000000008-0000000009.csv", 3)]
+        );
+        assert!(!dir.join("0000000010-0000000010.csv").exists());
+
+        let content = fs::read_to_string(dir.join("0000000008-0000000009.csv")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines[0],
+            "block_num,block_id,timestamp,from,amount,token.symbol"
+        );
+        assert_eq!(
+            lines[1],
+            "8,block-8,2023-11-14T22:13:28+00:00,\"a,b\",1,AAA"
+        );
+        assert_eq!(lines.len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_jsonl_and_parquet_files() {
+        let (pool, decoder) = decoder();
+        let dir = temp_dir("parquet");
+
+        let mut sink =
+            FileSink::open(&dir, FileFormat::Parquet, "map_transfers", decoder, 100).unwrap();
+        sink.write_block(
+            &clock(1),
+            Some(&output(&pool, &[("a", 1), ("b", u64::MAX)])),
+            "c1",
+            1,
+        )
+        .unwrap();
+        sink.write_block(&clock(2), None, "c2", 2).unwrap();
+        sink.finish().unwrap();
+
+        let reader = SerializedFileReader::new(
+            File::open(dir.join("0000000001-0000000002.parquet")).unwrap(),
+        )
+        .unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        assert_eq!(metadata.schema_descr().num_columns(), 6);
+
+        // Reopening a directory holding files of another format or module is refused.
+        let (_, decoder) = self::decoder();
+        assert!(FileSink::open(&dir, FileFormat::Jsonl, "map_transfers", decoder, 100).is_err());
+        let (_, decoder) = self::decoder();
+        let error = FileSink::open(&dir, FileFormat::Parquet, "map_other", decoder, 100)
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .ends_with("already holds the outputs of module 'map_transfers'"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (_, decoder) = self::decoder();
+        let mut sink =
+            FileSink::open(&dir, FileFormat::Jsonl, "map_transfers", decoder, 100).unwrap();
+        sink.write_block(&clock(1), Some(&output(&pool, &[("a", 1)])), "c1", 1)
+            .unwrap();
+        sink.finish().unwrap();
+
+        let content = fs::read_to_string(dir.join("0000000001-0000000001.jsonl")).unwrap();
+        let row: JsonValue = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(row["from"], "a");
+        assert_eq!(row["amount"], 1);
+        assert_eq!(row["token"]["symbol"], "AAA");
+        assert_eq!(row["block_num"], 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
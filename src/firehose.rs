@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use prost_types::Any;
+use tonic::codec::CompressionEncoding;
+
+use crate::pb::sf::firehose::v2::{
+    endpoint_info_client::EndpointInfoClient, fetch_client::FetchClient, single_block_request,
+    stream_client::StreamClient, BlockMetadata, InfoRequest, Request, Response,
+    SingleBlockRequest, SingleBlockResponse,
+};
+use crate::substreams::{EndpointInfo, SubstreamsEndpoint};
+
+// Identifies the block to fetch with `firehose_block`.
+#[derive(Clone, Debug)]
+pub enum BlockReference {
+    // The currently known canonical block at this height.
+    Number(u64),
+    HashAndNumber { num: u64, hash: String },
+    // The block that produced this cursor.
+    Cursor(String),
+}
+
+impl From<BlockReference> for single_block_request::Reference {
+    fn from(reference: BlockReference) -> Self {
+        match reference {
+            BlockReference::Number(num) => {
+                single_block_request::Reference::BlockNumber(single_block_request::BlockNumber {
+                    num,
+                })
+            }
+            BlockReference::HashAndNumber { num, hash } => {
+                single_block_request::Reference::BlockHashAndNumber(
+                    single_block_request::BlockHashAndNumber { num, hash },
+                )
+            }
+            BlockReference::Cursor(cursor) => {
+                single_block_request::Reference::Cursor(single_block_request::Cursor { cursor })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FetchedBlock {
+    pub block: Any,
+    // Only sent by recent Firehose versions, absent otherwise.
+    pub metadata: Option<BlockMetadata>,
+}
+
+impl TryFrom<SingleBlockResponse> for FetchedBlock {
+    type Error = anyhow::Error;
+
+    fn try_from(response: SingleBlockResponse) -> Result<Self, Self::Error> {
+        Ok(FetchedBlock {
+            block: response
+                .block
+                .ok_or_else(|| anyhow!("single block response has no block"))?,
+            metadata: response.metadata,
+        })
+    }
+}
+
+// Firehose providers are reached exactly like Substreams ones (gRPC channel plus an
+// optional bearer token), so a `SubstreamsEndpoint` also calls the Firehose services.
+impl SubstreamsEndpoint {
+    pub async fn firehose_blocks(
+        self: Arc<Self>,
+        request: Request,
+    ) -> Result<tonic::Streaming<Response>, anyhow::Error> {
+        let mut client = StreamClient::with_interceptor(self.channel(), self.auth_interceptor()?)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip)
+            .max_decoding_message_size(10 * 1024 * 1024);
+
+        let response_stream = client.blocks(request).await?;
+        let block_stream = response_stream.into_inner();
+
+        Ok(block_stream)
+    }
+
+    pub async fn firehose_block(
+        &self,
+        reference: BlockReference,
+    ) -> Result<FetchedBlock, anyhow::Error> {
+        let mut client = FetchClient::with_interceptor(self.channel(), self.auth_interceptor()?)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip)
+            .max_decoding_message_size(10 * 1024 * 1024);
+
+        let response = client
+            .block(SingleBlockRequest {
+                reference: Some(reference.into()),
+                transforms: vec![],
+            })
+            .await?;
+
+        response.into_inner().try_into()
+    }
+
+    pub async fn firehose_info(&self) -> Result<EndpointInfo, anyhow::Error> {
+        let mut client =
+            EndpointInfoClient::with_interceptor(self.channel(), self.auth_interceptor()?)
+                .accept_compressed(CompressionEncoding::Gzip)
+                .send_compressed(CompressionEncoding::Gzip);
+
+        let response = client.info(InfoRequest {}).await?;
+
+        Ok(response.into_inner().into())
+    }
+}
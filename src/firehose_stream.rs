@@ -0,0 +1,339 @@
+use anyhow::{anyhow, Error};
+use async_stream::try_stream;
+use futures03::{Stream, StreamExt};
+use prost_types::Any;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::sleep;
+use tokio_retry::strategy::ExponentialBackoff;
+
+use crate::endpoint_pool::SubstreamsEndpointPool;
+use crate::pb::sf::firehose::v2::{BlockMetadata, ForkStep, Request, Response};
+use crate::substreams::SubstreamsEndpoint;
+
+pub struct FirehoseBlock {
+    pub block: Any,
+    pub cursor: String,
+    // Only sent by recent Firehose versions, absent otherwise.
+    pub metadata: Option<BlockMetadata>,
+}
+
+pub enum FirehoseBlockResponse {
+    New(FirehoseBlock),
+    // The block was forked out, everything recorded for it must be reverted.
+    Undo(FirehoseBlock),
+    Final(FirehoseBlock),
+}
+
+pub struct FirehoseStream {
+    stream: Pin<Box<dyn Stream<Item = Result<FirehoseBlockResponse, Error>> + Send>>,
+}
+
+impl FirehoseStream {
+    pub fn new(
+        endpoint: Arc<SubstreamsEndpoint>,
+        cursor: Option<String>,
+        start_block: i64,
+        end_block: u64,
+        final_blocks_only: bool,
+    ) -> Self {
+        Self::with_pool(
+            Arc::new(SubstreamsEndpointPool::single(endpoint)),
+            cursor,
+            start_block,
+            end_block,
+            final_blocks_only,
+        )
+    }
+
+    pub fn with_pool(
+        pool: Arc<SubstreamsEndpointPool>,
+        cursor: Option<String>,
+        start_block: i64,
+        end_block: u64,
+        final_blocks_only: bool,
+    ) -> Self {
+        FirehoseStream {
+            stream: Box::pin(stream_blocks(
+                pool,
+                cursor,
+                start_block,
+                end_block,
+                final_blocks_only,
+            )),
+        }
+    }
+}
+
+// Create the Stream implementation that streams Firehose blocks with auto-reconnection,
+// resuming from the cursor of the last block handed to the consumer.
+fn stream_blocks(
+    pool: Arc<SubstreamsEndpointPool>,
+    cursor: Option<String>,
+    start_block_num: i64,
+    stop_block_num: u64,
+    final_blocks_only: bool,
+) -> impl Stream<Item = Result<FirehoseBlockResponse, Error>> {
+    let mut latest_cursor = cursor.unwrap_or_default();
+    let mut backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
+
+    try_stream! {
+        loop {
+            let endpoint = pool.current();
+
+            let result = endpoint.clone().firehose_blocks(Request {
+                start_block_num,
+                cursor: latest_cursor.clone(),
+                stop_block_num,
+                final_blocks_only,
+                transforms: vec![],
+            }).await;
+
+            match result {
+                Ok(stream) => {
+                    let mut encountered_error = false;
+                    for await response in stream {
+                        match process_firehose_response(response) {
+                            FirehoseProcessedResult::Block(block_response) => {
+                                // Reset backoff because we got a good value from the stream
+                                backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
+                                pool.report_success(&endpoint);
+
+                                let cursor = match &block_response {
+                                    FirehoseBlockResponse::New(block)
+                                    | FirehoseBlockResponse::Undo(block)
+                                    | FirehoseBlockResponse::Final(block) => block.cursor.clone(),
+                                };
+                                yield block_response;
+
+                                latest_cursor = cursor;
+                            },
+                            FirehoseProcessedResult::Invalid(error) => {
+                                return Err(error)?;
+                            },
+                            FirehoseProcessedResult::TonicError(status) => {
+                                // Unauthenticated errors are not retried, we forward the error back to the
+                                // stream consumer which handles it
+                                if status.code() == tonic::Code::Unauthenticated {
+                                    return Err(anyhow::Error::new(status.clone()))?;
+                                }
+
+                                // The error is kept in the pool's health state.
+                                pool.report_failure(&endpoint, status);
+                                encountered_error = true;
+                                break;
+                            },
+                        }
+                    }
+
+                    if !encountered_error {
+                        // Reached the end block.
+                        return
+                    }
+                },
+                Err(e) => {
+                    if pool.report_failure(&endpoint, &e) {
+                        // A different provider is available, try it right away.
+                        continue;
+                    }
+                }
+            }
+
+            // If we reach this point, we must wait a bit before retrying
+            if let Some(duration) = backoff.next() {
+                sleep(duration).await
+            } else {
+                return Err(anyhow!("backoff requested to stop retrying, quitting"))?;
+            }
+        }
+    }
+}
+
+enum FirehoseProcessedResult {
+    // A response breaking the protocol, which ends the stream.
+    Invalid(Error),
+    Block(FirehoseBlockResponse),
+    TonicError(tonic::Status),
+}
+
+fn process_firehose_response(result: Result<Response, tonic::Status>) -> FirehoseProcessedResult {
+    let response = match result {
+        Ok(v) => v,
+        Err(e) => return FirehoseProcessedResult::TonicError(e),
+    };
+
+    let step = response.step();
+    let Some(block) = response.block else {
+        return FirehoseProcessedResult::Invalid(anyhow!("Firehose response without a block"));
+    };
+
+    let block = FirehoseBlock {
+        block,
+        cursor: response.cursor,
+        metadata: response.metadata,
+    };
+
+    match step {
+        ForkStep::StepNew => FirehoseProcessedResult::Block(FirehoseBlockResponse::New(block)),
+        ForkStep::StepUndo => FirehoseProcessedResult::Block(FirehoseBlockResponse::Undo(block)),
+        ForkStep::StepFinal => FirehoseProcessedResult::Block(FirehoseBlockResponse::Final(block)),
+        ForkStep::StepUnset => FirehoseProcessedResult::Invalid(anyhow!(
+            "Firehose block with cursor {} has no fork step",
+            block.cursor
+        )),
+    }
+}
+
+impl Stream for FirehoseStream {
+    type Item = Result<FirehoseBlockResponse, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{mock_cursor, MockFirehoseEvent, MockFirehoseServer};
+
+    async fn connect(server: &MockFirehoseServer, cursor: Option<String>) -> FirehoseStream {
+        let endpoint = Arc::new(SubstreamsEndpoint::new(server.url(), None).await.unwrap());
+        FirehoseStream::new(endpoint, cursor, 1, 0, false)
+    }
+
+    fn describe(response: &FirehoseBlockResponse) -> String {
+        let (step, block) = match response {
+            FirehoseBlockResponse::New(block) => ("new", block),
+            FirehoseBlockResponse::Undo(block) => ("undo", block),
+            FirehoseBlockResponse::Final(block) => ("final", block),
+        };
+        format!("{} {}", step, String::from_utf8_lossy(&block.block.value))
+    }
+
+    #[tokio::test]
+    async fn reconnects_from_last_cursor_after_disconnect() {
+        let server = MockFirehoseServer::start(vec![
+            vec![
+                MockFirehoseEvent::block(1, ForkStep::StepNew),
+                MockFirehoseEvent::block(2, ForkStep::StepNew),
+                MockFirehoseEvent::Disconnect,
+            ],
+            vec![MockFirehoseEvent::block(3, ForkStep::StepNew)],
+        ])
+        .await
+        .unwrap();
+
+        let stream = connect(&server, None).await;
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["new block-1", "new block-2", "new block-3"]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].cursor, "");
+        assert_eq!(requests[1].cursor, mock_cursor(2));
+    }
+
+    #[tokio::test]
+    async fn starts_from_given_cursor() {
+        let server =
+            MockFirehoseServer::start(vec![vec![MockFirehoseEvent::block(11, ForkStep::StepNew)]])
+                .await
+                .unwrap();
+
+        let stream = connect(&server, Some(mock_cursor(10))).await;
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["new block-11"]);
+        assert_eq!(server.requests()[0].cursor, mock_cursor(10));
+    }
+
+    #[tokio::test]
+    async fn undo_resumes_from_the_undone_block_cursor() {
+        let server = MockFirehoseServer::start(vec![
+            vec![
+                MockFirehoseEvent::block(1, ForkStep::StepNew),
+                MockFirehoseEvent::block(2, ForkStep::StepNew),
+                MockFirehoseEvent::block(2, ForkStep::StepUndo),
+                MockFirehoseEvent::Disconnect,
+            ],
+            vec![MockFirehoseEvent::block(2, ForkStep::StepNew)],
+        ])
+        .await
+        .unwrap();
+
+        let stream = connect(&server, None).await;
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(
+            received,
+            vec!["new block-1", "new block-2", "undo block-2", "new block-2"]
+        );
+        assert_eq!(server.requests()[1].cursor, mock_cursor(2));
+    }
+
+    #[tokio::test]
+    async fn forwards_final_blocks_only() {
+        let server =
+            MockFirehoseServer::start(vec![vec![MockFirehoseEvent::block(2, ForkStep::StepFinal)]])
+                .await
+                .unwrap();
+
+        let endpoint = Arc::new(SubstreamsEndpoint::new(server.url(), None).await.unwrap());
+        let stream = FirehoseStream::new(endpoint, None, 1, 0, true);
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["final block-2"]);
+        assert!(server.requests()[0].final_blocks_only);
+    }
+
+    #[tokio::test]
+    async fn unset_steps_end_the_stream_with_an_error() {
+        let server = MockFirehoseServer::start(vec![vec![
+            MockFirehoseEvent::block(1, ForkStep::StepNew),
+            MockFirehoseEvent::block(2, ForkStep::StepUnset),
+            MockFirehoseEvent::block(3, ForkStep::StepNew),
+        ]])
+        .await
+        .unwrap();
+
+        let mut stream = connect(&server, None).await;
+
+        assert_eq!(
+            describe(&stream.next().await.unwrap().unwrap()),
+            "new block-1"
+        );
+        let error = stream.next().await.unwrap().err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Firehose block with cursor {} has no fork step",
+                mock_cursor(2)
+            )
+        );
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_error_is_not_retried() {
+        let server = MockFirehoseServer::start(vec![
+            vec![MockFirehoseEvent::Error(tonic::Status::unauthenticated(
+                "bad token",
+            ))],
+            vec![MockFirehoseEvent::block(1, ForkStep::StepNew)],
+        ])
+        .await
+        .unwrap();
+
+        let mut stream = connect(&server, None).await;
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Error};
+use lazy_static::lazy_static;
+use reqwest::{header::RETRY_AFTER, Client, Request, Response, StatusCode};
+use serde_json::Value;
+use tokio::time::sleep;
+
+lazy_static! {
+    // Shared by every call, including FFI calls, so connections are reused between them.
+    static ref SHARED_CLIENT: RwLock<Arc<HttpClient>> =
+        RwLock::new(Arc::new(HttpClient::new(HttpClientConfig::default()).unwrap()));
+}
+
+pub fn shared_client() -> Arc<HttpClient> {
+    SHARED_CLIENT.read().unwrap().clone()
+}
+
+// Replaces the shared client, calls already running keep the previous one.
+pub fn configure_shared_client(config: HttpClientConfig) -> Result<(), Error> {
+    *SHARED_CLIENT.write().unwrap() = Arc::new(HttpClient::new(config)?);
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+// Overrides for the requests sent to one host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EndpointConfig {
+    pub timeout: Option<Duration>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpClientConfig {
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // A `Retry-After` longer than this is not waited for, the response is returned as is.
+    pub max_retry_after: Duration,
+    // Applied to every host without an endpoint specific limit, `None` is unlimited.
+    pub rate_limit: Option<RateLimit>,
+    // Keyed by host, e.g. `mainnet.infura.io`.
+    pub endpoints: HashMap<String, EndpointConfig>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+            rate_limit: None,
+            endpoints: HashMap::new(),
+        }
+    }
+}
+
+fn millis(value: &Value, name: &str) -> Result<Option<Duration>, Error> {
+    match value.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v
+            .as_u64()
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .ok_or_else(|| anyhow!("'{}' must be a number of milliseconds", name)),
+    }
+}
+
+fn rate_limit(value: &Value) -> Result<Option<RateLimit>, Error> {
+    let Some(requests_per_second) = value.get("requests_per_second") else {
+        return Ok(None);
+    };
+
+    let requests_per_second = requests_per_second
+        .as_f64()
+        .filter(|rps| *rps > 0.0)
+        .ok_or_else(|| anyhow!("'requests_per_second' must be a positive number"))?;
+    let burst = value
+        .get("burst")
+        .and_then(Value::as_u64)
+        .unwrap_or(1)
+        .max(1) as u32;
+
+    Ok(Some(RateLimit {
+        requests_per_second,
+        burst,
+    }))
+}
+
+impl HttpClientConfig {
+    // Parses a JSON configuration, every key is optional:
+    // `{"timeout_ms", "connect_timeout_ms", "max_retries", "initial_backoff_ms",
+    //   "max_backoff_ms", "max_retry_after_ms", "requests_per_second", "burst",
+    //   "endpoints": {"<host>": {"timeout_ms", "requests_per_second", "burst"}}}`
+    pub fn from_json(input: &str) -> Result<Self, Error> {
+        let value: Value = serde_json::from_str(input).context("Invalid JSON for HTTP config")?;
+        if !value.is_object() {
+            return Err(anyhow!("HTTP config must be a JSON object"));
+        }
+
+        let mut config = HttpClientConfig::default();
+        if let Some(timeout) = millis(&value, "timeout_ms")? {
+            config.timeout = timeout;
+        }
+        if let Some(timeout) = millis(&value, "connect_timeout_ms")? {
+            config.connect_timeout = timeout;
+        }
+        if let Some(backoff) = millis(&value, "initial_backoff_ms")? {
+            config.initial_backoff = backoff;
+        }
+        if let Some(backoff) = millis(&value, "max_backoff_ms")? {
+            config.max_backoff = backoff;
+        }
+        if let Some(retry_after) = millis(&value, "max_retry_after_ms")? {
+            config.max_retry_after = retry_after;
+        }
+        if let Some(max_retries) = value.get("max_retries") {
+            config.max_retries = max_retries
+                .as_u64()
+                .ok_or_else(|| anyhow!("'max_retries' must be a number"))?
+                as u32;
+        }
+        config.rate_limit = rate_limit(&value)?;
+
+        if let Some(endpoints) = value.get("endpoints").and_then(Value::as_object) {
+            for (host, endpoint) in endpoints {
+                config.endpoints.insert(
+                    host.clone(),
+                    EndpointConfig {
+                        timeout: millis(endpoint, "timeout_ms")?,
+                        rate_limit: rate_limit(endpoint)?,
+                    },
+                );
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    // Takes a token, returning how long the caller must wait before using it. The token is
+    // taken even when waiting, so concurrent callers queue up instead of all waking at once.
+    fn take(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.requests_per_second).min(self.limit.burst as f64);
+        self.updated_at = now;
+
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.limit.requests_per_second)
+        }
+    }
+}
+
+// Parses a `Retry-After` header, either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+fn is_retryable_status(status: StatusCode, idempotent: bool) -> bool {
+    // A 429 means the request was not processed, it is always safe to send it again.
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (idempotent
+            && matches!(
+                status,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ))
+}
+
+// HTTP client shared by `rpc_call` and `api_call`: timeouts, retries with exponential
+// backoff honoring `Retry-After`, and a token bucket rate limiter per host.
+pub struct HttpClient {
+    client: Client,
+    config: HttpClientConfig,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl HttpClient {
+    pub fn new(config: HttpClientConfig) -> Result<Self, Error> {
+        // The total timeout is applied per request by `send`, so `send_streaming` can do
+        // without it.
+        let client = Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .build()
+            .context("build HTTP client")?;
+
+        Ok(HttpClient {
+            client,
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn config(&self) -> &HttpClientConfig {
+        &self.config
+    }
+
+    // Used to build requests for `send`.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    fn endpoint(&self, host: &str) -> Option<&EndpointConfig> {
+        self.config.endpoints.get(host)
+    }
+
+    async fn acquire(&self, host: &str) {
+        let limit = self
+            .endpoint(host)
+            .and_then(|endpoint| endpoint.rate_limit)
+            .or(self.config.rate_limit);
+        let Some(limit) = limit else {
+            return;
+        };
+
+        let wait = {
+            let mut buckets = self.buckets.lock().unwrap();
+            let now = Instant::now();
+            buckets
+                .entry(host.to_string())
+                .or_insert_with(|| TokenBucket::new(limit, now))
+                .take(now)
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.config
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.config.max_backoff)
+    }
+
+    // Sends `request`, retrying on 429 and, when `idempotent`, on connection errors,
+    // timeouts and 502/503/504. Requests with a streaming body cannot be retried.
+    pub async fn send(
+        &self,
+        mut request: Request,
+        idempotent: bool,
+    ) -> Result<Response, reqwest::Error> {
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        match self.endpoint(&host).and_then(|endpoint| endpoint.timeout) {
+            Some(timeout) => *request.timeout_mut() = Some(timeout),
+            None => {
+                request.timeout_mut().get_or_insert(self.config.timeout);
+            }
+        }
+
+        let mut attempt = 0;
+        loop {
+            self.acquire(&host).await;
+
+            let retry = if attempt < self.config.max_retries {
+                request.try_clone()
+            } else {
+                None
+            };
+            let Some(next) = retry else {
+                return self.client.execute(request).await;
+            };
+
+            let delay = match self.client.execute(next).await {
+                Ok(response) if is_retryable_status(response.status(), idempotent) => {
+                    match retry_after(&response) {
+                        Some(delay) if delay > self.config.max_retry_after => return Ok(response),
+                        Some(delay) => delay,
+                        None => self.backoff(attempt),
+                    }
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if idempotent && (e.is_connect() || e.is_timeout()) => self.backoff(attempt),
+                Err(e) => return Err(e),
+            };
+
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // Sends a request whose response body is read for as long as the server keeps it open,
+    // e.g. Server-Sent Events. Rate limits apply but there is no total timeout and no retry,
+    // the caller reconnects as it sees fit.
+    pub async fn send_streaming(&self, request: Request) -> Result<Response, reqwest::Error> {
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        self.acquire(&host).await;
+        self.client.execute(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    // Answers successive requests with the given status lines and extra headers, the last
+    // one is repeated.
+    async fn serve(responses: Vec<(&str, &[&str])>) -> MockHttpServer {
+        MockHttpServer::sequence(
+            responses
+                .into_iter()
+                .map(|(status, headers)| MockHttpResponse::new(status, headers, "ok"))
+                .collect(),
+        )
+        .await
+    }
+
+    fn client(max_retries: u32) -> HttpClient {
+        HttpClient::new(HttpClientConfig {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_and_unavailable_responses() {
+        let server = serve(vec![
+            ("429 Too Many Requests", &["Retry-After: 0"]),
+            ("503 Service Unavailable", &[]),
+            ("200 OK", &[]),
+        ])
+        .await;
+
+        let client = client(3);
+        let request = client.client().get(server.url()).build().unwrap();
+        let response = client.send(request, true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_non_idempotent_server_errors() {
+        let server = serve(vec![("503 Service Unavailable", &[]), ("200 OK", &[])]).await;
+
+        let client = client(3);
+        let request = client
+            .client()
+            .post(server.url())
+            .body("{}")
+            .build()
+            .unwrap();
+        let response = client.send(request, false).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.hits(), 1);
+
+        // A `Retry-After` past the configured maximum is returned instead of waited for.
+        let server = serve(vec![("429 Too Many Requests", &["Retry-After: 3600"])]).await;
+        let request = client.client().get(server.url()).build().unwrap();
+        let response = client.send(request, true).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn token_bucket_spaces_requests_after_burst() {
+        let millis = |wait: Duration| (wait.as_secs_f64() * 1000.0).round() as u64;
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(
+            RateLimit {
+                requests_per_second: 10.0,
+                burst: 2,
+            },
+            now,
+        );
+
+        assert_eq!(millis(bucket.take(now)), 0);
+        assert_eq!(millis(bucket.take(now)), 0);
+        assert_eq!(millis(bucket.take(now)), 100);
+        assert_eq!(millis(bucket.take(now)), 200);
+        // A second later the bucket is full again.
+        assert_eq!(millis(bucket.take(now + Duration::from_secs(1))), 0);
+    }
+
+    #[test]
+    fn parses_config() {
+        let config = HttpClientConfig::from_json(
+            r#"{"timeout_ms": 5000, "max_retries": 1, "requests_per_second": 20, "burst": 5,
+                "endpoints": {"eth.llamarpc.com": {"timeout_ms": 1000, "requests_per_second": 2}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        assert_eq!(config.max_retries, 1);
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimit {
+                requests_per_second: 20.0,
+                burst: 5
+            })
+        );
+        assert_eq!(
+            config.endpoints["eth.llamarpc.com"].timeout,
+            Some(Duration::from_secs(1))
+        );
+        assert!(HttpClientConfig::from_json(r#"{"timeout_ms": "5s"}"#).is_err());
+    }
+}
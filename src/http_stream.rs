@@ -0,0 +1,483 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use async_stream::try_stream;
+use futures03::Stream;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tokio::time::{sleep, timeout};
+
+use crate::api::{ApiError, ApiRequest};
+use crate::http_client::HttpClient;
+
+// Reconnection delay until the server sets one with `retry:`.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// A stream that cannot be reconnected this many times in a row ends with an error.
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+// A connection receiving nothing for this long is considered dead. SSE servers usually
+// send comment lines to keep idle connections alive.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamFormat {
+    // `text/event-stream`, reconnected with `Last-Event-ID` when the connection drops.
+    Sse,
+    // One JSON value per line, ending with the response.
+    JsonLines,
+}
+
+impl StreamFormat {
+    pub fn parse(format: &str) -> Result<Self, ApiError> {
+        match format {
+            "sse" => Ok(StreamFormat::Sse),
+            "jsonl" | "ndjson" => Ok(StreamFormat::JsonLines),
+            x => Err(ApiError::InvalidRequest(format!(
+                "unknown stream format '{}', expected sse or jsonl",
+                x
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SseEvent {
+    // The last event id seen, as the spec carries it over to following events.
+    pub id: Option<String>,
+    // None for the default `message` type.
+    pub event: Option<String>,
+    pub data: String,
+    pub retry: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    Sse(SseEvent),
+    Json(Value),
+    // An SSE connection was lost, `error` being the `ApiError` JSON. The stream goes on
+    // after `delay`.
+    Reconnecting { error: Value, delay: Duration },
+}
+
+impl StreamEvent {
+    // SSE events as `{"id", "event", "data", "retry"}`, JSON lines as they are, and
+    // reconnections as the error with `reconnect_in_ms` added.
+    pub fn to_json(&self) -> Value {
+        match self {
+            StreamEvent::Sse(event) => json!({
+                "id": event.id,
+                "event": event.event.as_deref().unwrap_or("message"),
+                "data": event.data,
+                "retry": event.retry,
+            }),
+            StreamEvent::Json(value) => value.clone(),
+            StreamEvent::Reconnecting { error, delay } => {
+                let mut error = error.clone();
+                error["reconnect_in_ms"] = json!(delay.as_millis() as u64);
+                error
+            }
+        }
+    }
+}
+
+// Splits bytes into lines, which may arrive in any chunking. Lines end with `\n` or
+// `\r\n`.
+#[derive(Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let mut line: Vec<u8> = self.pending.drain(..=end).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            lines.push(String::from_utf8_lossy(&line).to_string());
+        }
+        lines
+    }
+
+    // The last line when the bytes ended without a line break.
+    fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.pending);
+        (!line.is_empty()).then(|| String::from_utf8_lossy(&line).to_string())
+    }
+}
+
+// Incremental `text/event-stream` parser following the HTML spec: `data` lines are joined
+// with `\n`, an empty line dispatches the event, lines starting with `:` are comments.
+#[derive(Default)]
+pub struct SseParser {
+    lines: LineBuffer,
+    data: Vec<String>,
+    event: Option<String>,
+    retry: Option<u64>,
+    last_event_id: Option<String>,
+    // The last `retry` received, which applies at once, whether an event follows or not.
+    reconnection_time: Option<u64>,
+}
+
+impl SseParser {
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    // The reconnection time in milliseconds set by the server, if any.
+    pub fn reconnection_time(&self) -> Option<u64> {
+        self.reconnection_time
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        for line in self.lines.push(chunk) {
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    events.push(event);
+                }
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_str(), ""),
+            };
+            match field {
+                "data" => self.data.push(value.to_string()),
+                "event" => self.event = Some(value.to_string()),
+                "id" if !value.contains('\0') => {
+                    self.last_event_id = Some(value.to_string()).filter(|id| !id.is_empty())
+                }
+                "retry" => {
+                    if let Ok(retry) = value.parse() {
+                        self.retry = Some(retry);
+                        self.reconnection_time = Some(retry);
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        if self.data.is_empty() {
+            return None;
+        }
+
+        Some(SseEvent {
+            id: self.last_event_id.clone(),
+            event,
+            data: std::mem::take(&mut self.data).join("\n"),
+            retry,
+        })
+    }
+
+    // Drops a partly received event, as the spec does when the connection closes.
+    fn reset(&mut self) {
+        self.lines = LineBuffer::default();
+        self.data.clear();
+        self.event = None;
+        self.retry = None;
+    }
+}
+
+// Events of a streaming HTTP response, in SSE or JSON lines format. SSE streams reconnect
+// whenever the connection drops or goes idle, sending `Last-Event-ID` so the server can
+// resume, and end when the server answers 204. JSON lines streams end with the response.
+pub struct HttpStream {
+    stream: Pin<Box<dyn Stream<Item = Result<StreamEvent, ApiError>> + Send>>,
+}
+
+impl HttpStream {
+    pub fn open(client: Arc<HttpClient>, request: ApiRequest, format: StreamFormat) -> Self {
+        Self::with_idle_timeout(client, request, format, DEFAULT_IDLE_TIMEOUT)
+    }
+
+    pub fn with_idle_timeout(
+        client: Arc<HttpClient>,
+        request: ApiRequest,
+        format: StreamFormat,
+        idle_timeout: Duration,
+    ) -> Self {
+        HttpStream {
+            stream: Box::pin(stream_events(client, request, format, idle_timeout)),
+        }
+    }
+}
+
+impl Stream for HttpStream {
+    type Item = Result<StreamEvent, ApiError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+fn is_reconnectable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// A JSON lines value, `None` for a blank line.
+fn parse_json_line(line: &str) -> Result<Option<Value>, ApiError> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(line)
+        .map(Some)
+        .map_err(|e| ApiError::InvalidResponse(format!("invalid JSON line '{}': {}", line, e)))
+}
+
+fn stream_events(
+    client: Arc<HttpClient>,
+    request: ApiRequest,
+    format: StreamFormat,
+    idle_timeout: Duration,
+) -> impl Stream<Item = Result<StreamEvent, ApiError>> {
+    try_stream! {
+        let mut sse = SseParser::default();
+        let mut lines = LineBuffer::default();
+        let mut retry = DEFAULT_RETRY;
+        let mut failures = 0;
+
+        loop {
+            let mut attempt = request.clone();
+            if format == StreamFormat::Sse {
+                if !attempt.headers.iter().any(|(key, _)| key.eq_ignore_ascii_case("accept")) {
+                    attempt.headers.push(("Accept".to_string(), "text/event-stream".to_string()));
+                }
+                if let Some(id) = sse.last_event_id() {
+                    attempt.headers.push(("Last-Event-ID".to_string(), id.to_string()));
+                }
+            }
+
+            let error = match client.send_streaming(attempt.build(&client)?).await {
+                Ok(response) if format == StreamFormat::Sse && response.status() == StatusCode::NO_CONTENT => {
+                    break;
+                }
+                Ok(response) if response.status().is_success() => {
+                    let mut response = response;
+                    // None when the response ended normally.
+                    let interrupted = loop {
+                        let chunk = match timeout(idle_timeout, response.chunk()).await {
+                            Ok(Ok(Some(chunk))) => chunk.to_vec(),
+                            Ok(Ok(None)) => break None,
+                            Ok(Err(e)) => break Some(ApiError::Transport(format!("{}", e))),
+                            Err(_) => {
+                                break Some(ApiError::Transport(format!("no data received for {:?}", idle_timeout)))
+                            }
+                        };
+
+                        match format {
+                            StreamFormat::Sse => {
+                                let events = sse.push(&chunk);
+                                if let Some(delay) = sse.reconnection_time() {
+                                    retry = Duration::from_millis(delay);
+                                }
+                                for event in events {
+                                    failures = 0;
+                                    yield StreamEvent::Sse(event);
+                                }
+                            }
+                            StreamFormat::JsonLines => {
+                                for line in lines.push(&chunk) {
+                                    if let Some(value) = parse_json_line(&line)? {
+                                        yield StreamEvent::Json(value);
+                                    }
+                                }
+                            }
+                        }
+                    };
+
+                    match (format, interrupted) {
+                        (StreamFormat::JsonLines, None) => {
+                            let last = lines.finish().unwrap_or_default();
+                            if let Some(value) = parse_json_line(&last)? {
+                                yield StreamEvent::Json(value);
+                            }
+                            break;
+                        }
+                        (StreamFormat::JsonLines, Some(error)) => Err(error)?,
+                        (StreamFormat::Sse, error) => {
+                            error.unwrap_or_else(|| ApiError::Transport("connection closed".to_string()))
+                        }
+                    }
+                }
+                Ok(response) if format == StreamFormat::Sse && is_reconnectable_status(response.status()) => {
+                    ApiError::Http {
+                        status: response.status().as_u16(),
+                        body: response.text().await.unwrap_or_default(),
+                    }
+                }
+                Ok(response) => Err(ApiError::Http {
+                    status: response.status().as_u16(),
+                    body: response.text().await.unwrap_or_default(),
+                })?,
+                Err(e) if format == StreamFormat::Sse => ApiError::Transport(format!("{}", e)),
+                Err(e) => Err(ApiError::Transport(format!("{}", e)))?,
+            };
+
+            failures += 1;
+            if failures >= MAX_RECONNECT_ATTEMPTS {
+                Err(ApiError::Transport(format!(
+                    "stream from {} failed {} times in a row: {}",
+                    request.url, failures, error
+                )))?;
+            }
+
+            // Backs off from the server's reconnection time, never below it.
+            let delay = retry
+                .saturating_mul(2u32.saturating_pow(failures - 1))
+                .min(MAX_BACKOFF.max(retry));
+            yield StreamEvent::Reconnecting { error: error.to_json(), delay };
+            sse.reset();
+            sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures03::StreamExt;
+
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    #[test]
+    fn parses_sse_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b": keep-alive\nid: 7\nevent: blo").is_empty());
+        let events = parser.push(b"ck\ndata: {\"n\":\ndata: 1}\nretry: 500\n\r\ndata: x\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    id: Some("7".to_string()),
+                    event: Some("block".to_string()),
+                    data: "{\"n\":\n1}".to_string(),
+                    retry: Some(500),
+                },
+                SseEvent {
+                    id: Some("7".to_string()),
+                    event: None,
+                    data: "x".to_string(),
+                    retry: None,
+                },
+            ]
+        );
+        assert_eq!(parser.last_event_id(), Some("7"));
+    }
+
+    // Serves the raw `responses` in order, one per connection, the last one is repeated.
+    async fn serve(responses: Vec<&str>) -> MockHttpServer {
+        MockHttpServer::sequence(responses.into_iter().map(MockHttpResponse::raw).collect()).await
+    }
+
+    #[tokio::test]
+    async fn resumes_sse_with_last_event_id() {
+        let server = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\nretry: 10\nid: 1\ndata: a\n\nid: 2\ndata: b\n\nid: 3\ndata: partial",
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\nid: 3\ndata: c\n\n",
+            "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+
+        let client = Arc::new(HttpClient::new(HttpClientConfig::default()).unwrap());
+        let stream = HttpStream::open(client, ApiRequest::get(&server.url()), StreamFormat::Sse);
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        let data: Vec<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Sse(event) => Some(event.data.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(data, vec!["a", "b", "c"]);
+
+        // Both dropped connections are reported, with the server's retry delay.
+        let reconnects: Vec<Value> = events
+            .iter()
+            .filter(|event| matches!(event, StreamEvent::Reconnecting { .. }))
+            .map(StreamEvent::to_json)
+            .collect();
+        assert_eq!(reconnects.len(), 2);
+        assert_eq!(reconnects[1]["kind"], json!("transport"));
+        assert_eq!(reconnects[1]["reconnect_in_ms"], json!(10));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("accept"), Some("text/event-stream"));
+        assert_eq!(requests[0].header("last-event-id"), None);
+        assert_eq!(requests[1].header("last-event-id"), Some("3"));
+        assert_eq!(requests[2].header("last-event-id"), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn applies_retry_without_an_event() {
+        let server = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\nretry: 20\n\n",
+            "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+
+        let client = Arc::new(HttpClient::new(HttpClientConfig::default()).unwrap());
+        let stream = HttpStream::open(client, ApiRequest::get(&server.url()), StreamFormat::Sse);
+        let events: Vec<Value> = stream.map(|event| event.unwrap().to_json()).collect().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["reconnect_in_ms"], json!(20));
+    }
+
+    #[tokio::test]
+    async fn reads_chunked_json_lines() {
+        let server = serve(vec![
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n8\r\n{\"n\": 1}\r\n5\r\n\n{\"n\"\r\n6\r\n: 2}\n\n\r\n0\r\n\r\n",
+        ])
+        .await;
+
+        let client = Arc::new(HttpClient::new(HttpClientConfig::default()).unwrap());
+        let stream = HttpStream::open(
+            client,
+            ApiRequest::get(&server.url()),
+            StreamFormat::JsonLines,
+        );
+        let values: Vec<Value> = stream.map(|event| event.unwrap().to_json()).collect().await;
+        assert_eq!(values, vec![json!({"n": 1}), json!({"n": 2})]);
+    }
+
+    #[tokio::test]
+    async fn reads_a_last_json_line_without_line_break() {
+        let server = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 17\r\nConnection: close\r\n\r\n{\"n\": 1}\n{\"n\": 2}",
+        ])
+        .await;
+
+        let client = Arc::new(HttpClient::new(HttpClientConfig::default()).unwrap());
+        let stream = HttpStream::open(
+            client,
+            ApiRequest::get(&server.url()),
+            StreamFormat::JsonLines,
+        );
+        let values: Vec<Value> = stream.map(|event| event.unwrap().to_json()).collect().await;
+        assert_eq!(values, vec![json!({"n": 1}), json!({"n": 2})]);
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Error};
+use reqwest::Response;
+use serde_json::{json, Value};
+
+use crate::http_client::HttpClient;
+
+// Batches larger than this are split into several POSTs, most providers reject or
+// throttle bigger ones.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+// Code used for errors detected on our side, e.g. a batch item left without response.
+const INTERNAL_ERROR: i64 = -32603;
+
+// A JSON-RPC 2.0 error object, as returned by the server in `error`.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("JSON-RPC error {code}: {message}")]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    pub fn new(code: i64, message: &str, data: Option<Value>) -> Self {
+        JsonRpcError {
+            code,
+            message: message.to_string(),
+            data,
+        }
+    }
+
+    // Servers do not always follow the spec, a missing code or message is tolerated.
+    pub fn from_json(error: &Value) -> Self {
+        match error {
+            Value::Object(object) => JsonRpcError {
+                code: object
+                    .get("code")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(INTERNAL_ERROR),
+                message: object
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                data: object.get("data").cloned(),
+            },
+            Value::String(message) => JsonRpcError::new(INTERNAL_ERROR, message, None),
+            error => JsonRpcError::new(INTERNAL_ERROR, "invalid error object", Some(error.clone())),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "code": self.code,
+            "message": self.message,
+            "data": self.data,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RpcError {
+    #[error(transparent)]
+    JsonRpc(#[from] JsonRpcError),
+    // The server answered with a non-2xx status, `body` is what it sent along and `error`
+    // the JSON-RPC error object found in it, if any.
+    #[error("HTTP status {status}: {body}")]
+    Http {
+        status: u16,
+        body: String,
+        error: Option<JsonRpcError>,
+    },
+    // The request could not be built from the caller's input, nothing was sent.
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("invalid JSON-RPC response: {0}")]
+    InvalidResponse(String),
+    #[error("request failed: {0}")]
+    Transport(String),
+    // Not enough providers of a group gave the same answer, or two answers reached the quorum.
+    #[error("no quorum: {agreed} of the {required} required providers agreed")]
+    NoQuorum { required: usize, agreed: usize },
+}
+
+impl RpcError {
+    // The JSON-RPC error object sent by the server, whatever the HTTP status it came with.
+    pub fn json_rpc_error(&self) -> Option<&JsonRpcError> {
+        match self {
+            RpcError::JsonRpc(error) => Some(error),
+            RpcError::Http { error, .. } => error.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Describes the error for hosts, which can branch on `kind`.
+    pub fn to_json(&self) -> Value {
+        match self {
+            RpcError::JsonRpc(error) => json!({
+                "kind": "json_rpc",
+                "code": error.code,
+                "message": error.message,
+                "data": error.data,
+            }),
+            RpcError::Http {
+                status,
+                body,
+                error,
+            } => json!({
+                "kind": "http",
+                "status": status,
+                "message": self.to_string(),
+                "body": body,
+                "error": error.as_ref().map(JsonRpcError::to_json),
+            }),
+            RpcError::InvalidRequest(_) => json!({
+                "kind": "invalid_request",
+                "message": self.to_string(),
+            }),
+            RpcError::InvalidResponse(_) => json!({
+                "kind": "invalid_response",
+                "message": self.to_string(),
+            }),
+            RpcError::Transport(_) => json!({
+                "kind": "transport",
+                "message": self.to_string(),
+            }),
+            RpcError::NoQuorum { required, agreed } => json!({
+                "kind": "no_quorum",
+                "message": self.to_string(),
+                "required": required,
+                "agreed": agreed,
+            }),
+        }
+    }
+}
+
+impl From<reqwest::Error> for RpcError {
+    fn from(err: reqwest::Error) -> Self {
+        RpcError::Transport(format!("{:#}", anyhow::Error::from(err)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonRpcRequest {
+    pub method: String,
+    pub params: Value,
+}
+
+impl JsonRpcRequest {
+    pub fn new(method: &str, params: Value) -> Self {
+        JsonRpcRequest {
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    // Sending a transaction twice is not harmless, every other method only reads or is
+    // rejected the second time, so it can be retried.
+    pub fn is_idempotent(&self) -> bool {
+        !(self.method.starts_with("eth_send") || self.method.starts_with("personal_send"))
+    }
+
+    pub fn to_json(&self, id: u64) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": self.method,
+            "params": self.params,
+            "id": id,
+        })
+    }
+}
+
+// Parses a JSON array of requests, each either `{"method": ..., "params": ...}` or a
+// `[method, params]` pair. Missing params default to an empty array.
+pub fn parse_batch_requests(input: &str) -> Result<Vec<JsonRpcRequest>, Error> {
+    let value: Value = serde_json::from_str(input).context("Invalid JSON for batch requests")?;
+    let items = value
+        .as_array()
+        .ok_or_else(|| anyhow!("batch requests must be a JSON array"))?;
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let (method, params) = match item {
+                Value::Object(object) => (object.get("method"), object.get("params")),
+                Value::Array(pair) => (pair.first(), pair.get(1)),
+                _ => (None, None),
+            };
+
+            let method = method
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("batch request {} has no method", i))?;
+
+            Ok(JsonRpcRequest::new(
+                method,
+                params.cloned().unwrap_or_else(|| json!([])),
+            ))
+        })
+        .collect()
+}
+
+// Extracts the `result` of a single JSON-RPC 2.0 response, or its error object.
+pub fn parse_response(response: &Value) -> Result<Value, RpcError> {
+    let object = response.as_object().ok_or_else(|| {
+        RpcError::InvalidResponse(format!("expected an object, got {}", response))
+    })?;
+
+    match (object.get("error"), object.get("result")) {
+        (Some(error), _) if !error.is_null() => Err(JsonRpcError::from_json(error).into()),
+        (_, Some(result)) => Ok(result.clone()),
+        _ => Err(RpcError::InvalidResponse(format!(
+            "neither result nor error in {}",
+            response
+        ))),
+    }
+}
+
+// Reads the JSON body of a response, non-2xx statuses are errors carrying the body. Some
+// providers answer 4xx/5xx with a JSON-RPC error object, which the error then holds too.
+async fn read_json(response: Response) -> Result<Value, RpcError> {
+    let status = response.status();
+    let body = response.text().await?;
+
+    if !status.is_success() {
+        let error = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|value| value.get("error").filter(|e| e.is_object()).cloned())
+            .map(|error| JsonRpcError::from_json(&error));
+        return Err(RpcError::Http {
+            status: status.as_u16(),
+            body,
+            error,
+        });
+    }
+
+    serde_json::from_str(&body).map_err(|e| RpcError::InvalidResponse(format!("{}: {}", e, body)))
+}
+
+pub async fn send_request(
+    client: &HttpClient,
+    rpc_endpoint: &str,
+    request: &JsonRpcRequest,
+    id: u64,
+) -> Result<Value, RpcError> {
+    let http_request = client
+        .client()
+        .post(rpc_endpoint)
+        .json(&request.to_json(id))
+        .build()?;
+    let response = client.send(http_request, request.is_idempotent()).await?;
+
+    parse_response(&read_json(response).await?)
+}
+
+// Matches the responses of a batch to its requests by id, as servers may answer in any
+// order. `first_id` is the id of the first request of the batch, the following ones are
+// consecutive. Each item is the `result` of its request or its JSON-RPC error.
+pub fn correlate_batch_response(
+    first_id: u64,
+    count: usize,
+    response: &Value,
+) -> Vec<Result<Value, JsonRpcError>> {
+    let responses = match response.as_array() {
+        Some(responses) => responses,
+        // A single object answering a batch is an error for the whole batch, e.g. when the
+        // provider does not support batching.
+        None => {
+            let error = match response.get("error") {
+                Some(error) => JsonRpcError::from_json(error),
+                None => JsonRpcError::new(
+                    INTERNAL_ERROR,
+                    "invalid batch response",
+                    Some(response.clone()),
+                ),
+            };
+            return vec![Err(error); count];
+        }
+    };
+
+    let mut by_id: HashMap<u64, &Value> = HashMap::new();
+    for item in responses {
+        if let Some(id) = item.get("id").and_then(Value::as_u64) {
+            by_id.insert(id, item);
+        }
+    }
+
+    (0..count as u64)
+        .map(|offset| match by_id.get(&(first_id + offset)) {
+            Some(item) => match item.get("error") {
+                Some(error) if !error.is_null() => Err(JsonRpcError::from_json(error)),
+                _ => Ok(item.get("result").cloned().unwrap_or(Value::Null)),
+            },
+            None => Err(JsonRpcError::new(
+                INTERNAL_ERROR,
+                "no response for request in batch",
+                None,
+            )),
+        })
+        .collect()
+}
+
+// Sends `requests` in batches of at most `max_batch_size` and returns one item per request,
+// in the order of `requests`. The requests of a batch that failed as a whole, e.g. on a
+// transport error or an HTTP error status, each get that error as a JSON-RPC error whose
+// `data` describes it, the other batches are kept. Fails only when no batch succeeded.
+pub async fn send_batch(
+    client: &HttpClient,
+    rpc_endpoint: &str,
+    requests: &[JsonRpcRequest],
+    max_batch_size: usize,
+) -> Result<Vec<Result<Value, JsonRpcError>>, RpcError> {
+    let max_batch_size = if max_batch_size == 0 {
+        DEFAULT_MAX_BATCH_SIZE
+    } else {
+        max_batch_size
+    };
+
+    let mut results = Vec::with_capacity(requests.len());
+    let mut last_error = None;
+    let mut any_sent = false;
+    for (chunk_index, chunk) in requests.chunks(max_batch_size).enumerate() {
+        let first_id = (chunk_index * max_batch_size) as u64;
+        match send_chunk(client, rpc_endpoint, first_id, chunk).await {
+            Ok(response) => {
+                any_sent = true;
+                results.extend(correlate_batch_response(first_id, chunk.len(), &response));
+            }
+            Err(err) => {
+                let error =
+                    JsonRpcError::new(INTERNAL_ERROR, &err.to_string(), Some(err.to_json()));
+                results.extend(std::iter::repeat(Err(error)).take(chunk.len()));
+                last_error = Some(err);
+            }
+        }
+    }
+
+    match last_error {
+        Some(err) if !any_sent => Err(err),
+        _ => Ok(results),
+    }
+}
+
+async fn send_chunk(
+    client: &HttpClient,
+    rpc_endpoint: &str,
+    first_id: u64,
+    chunk: &[JsonRpcRequest],
+) -> Result<Value, RpcError> {
+    let body: Vec<Value> = chunk
+        .iter()
+        .enumerate()
+        .map(|(i, request)| request.to_json(first_id + i as u64))
+        .collect();
+
+    let idempotent = chunk.iter().all(JsonRpcRequest::is_idempotent);
+    let http_request = client.client().post(rpc_endpoint).json(&body).build()?;
+    read_json(client.send(http_request, idempotent).await?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    // Answers every HTTP request with `status` and `body`, returns the URL to call.
+    async fn serve_once(status: &str, body: &str) -> String {
+        serve_each(vec![(status, body.to_string())]).await
+    }
+
+    // Answers HTTP requests with each `(status, body)` in order, the last one is repeated.
+    // Returns the URL to call.
+    async fn serve_each(answers: Vec<(&str, String)>) -> String {
+        let responses = answers
+            .into_iter()
+            .map(|(status, body)| {
+                MockHttpResponse::new(status, &["Content-Type: application/json"], body)
+            })
+            .collect();
+        MockHttpServer::sequence(responses).await.url()
+    }
+
+    #[test]
+    fn parses_objects_and_pairs() {
+        let requests = parse_batch_requests(
+            r#"[{"method": "eth_blockNumber"}, ["eth_getBalance", ["0xabc", "latest"]]]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            requests,
+            vec![
+                JsonRpcRequest::new("eth_blockNumber", json!([])),
+                JsonRpcRequest::new("eth_getBalance", json!(["0xabc", "latest"])),
+            ]
+        );
+        assert!(parse_batch_requests(r#"[{"params": []}]"#).is_err());
+        assert!(parse_batch_requests(r#"{"method": "eth_blockNumber"}"#).is_err());
+    }
+
+    #[test]
+    fn correlates_out_of_order_responses() {
+        let response = json!([
+            {"jsonrpc": "2.0", "id": 12, "error": {"code": -32000, "message": "execution reverted"}},
+            {"jsonrpc": "2.0", "id": 10, "result": "0x1"},
+        ]);
+
+        let results = correlate_batch_response(10, 3, &response);
+        assert_eq!(results[0], Ok(json!("0x1")));
+        assert_eq!(results[1].as_ref().unwrap_err().code, INTERNAL_ERROR);
+        assert_eq!(
+            results[2],
+            Err(JsonRpcError::new(-32000, "execution reverted", None))
+        );
+
+        let results = correlate_batch_response(
+            0,
+            2,
+            &json!({"jsonrpc": "2.0", "id": null, "error": {"code": -32600, "message": "batch not supported"}}),
+        );
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap_err().code, -32600);
+    }
+
+    #[test]
+    fn parses_response_envelope() {
+        assert_eq!(
+            parse_response(&json!({"jsonrpc": "2.0", "id": 1, "result": "0x10"})).unwrap(),
+            json!("0x10")
+        );
+
+        let err = parse_response(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": {"code": 3, "message": "execution reverted", "data": "0x08c379a0"},
+        }))
+        .unwrap_err();
+        assert!(matches!(
+            &err,
+            RpcError::JsonRpc(JsonRpcError { code: 3, data: Some(data), .. }) if data == "0x08c379a0"
+        ));
+        assert_eq!(err.to_json()["kind"], "json_rpc");
+
+        assert!(matches!(
+            parse_response(&json!({"jsonrpc": "2.0", "id": 1})),
+            Err(RpcError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn non_success_status_is_an_error_with_body() {
+        // Retries are covered by the HTTP client tests, the error is wanted right away.
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        let request = JsonRpcRequest::new("eth_blockNumber", json!([]));
+
+        let url = serve_once("429 Too Many Requests", "rate limited").await;
+        match send_request(&client, &url, &request, 1).await {
+            Err(RpcError::Http {
+                status,
+                body,
+                error,
+            }) => {
+                assert_eq!(status, 429);
+                assert_eq!(body, "rate limited");
+                assert_eq!(error, None);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let url = serve_once(
+            "500 Internal Server Error",
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"header not found"}}"#,
+        )
+        .await;
+        let err = send_request(&client, &url, &request, 1).await.unwrap_err();
+        assert!(matches!(err, RpcError::Http { status: 500, .. }));
+        assert_eq!(err.json_rpc_error().unwrap().code, -32000);
+        assert_eq!(err.to_json()["error"]["message"], "header not found");
+    }
+
+    #[tokio::test]
+    async fn failed_batch_chunks_keep_the_other_results() {
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        let requests = vec![JsonRpcRequest::new("eth_blockNumber", json!([])); 3];
+
+        let url = serve_each(vec![
+            (
+                "200 OK",
+                r#"[{"jsonrpc":"2.0","id":0,"result":"0x1"},{"jsonrpc":"2.0","id":1,"result":"0x2"}]"#
+                    .to_string(),
+            ),
+            ("502 Bad Gateway", "upstream down".to_string()),
+        ])
+        .await;
+        let results = send_batch(&client, &url, &requests, 2).await.unwrap();
+
+        assert_eq!(results[0], Ok(json!("0x1")));
+        assert_eq!(results[1], Ok(json!("0x2")));
+        let error = results[2].as_ref().unwrap_err();
+        assert_eq!(error.code, INTERNAL_ERROR);
+        assert_eq!(error.data.as_ref().unwrap()["status"], 502);
+
+        let url = serve_once("502 Bad Gateway", "upstream down").await;
+        assert!(matches!(
+            send_batch(&client, &url, &requests[..1], 2).await,
+            Err(RpcError::Http { status: 502, .. })
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{format_err, Context, Error};
+use async_stream::try_stream;
 // use chrono::DateTime;
-use futures03::StreamExt;
+use futures03::{Stream, StreamExt};
 use lazy_static::lazy_static;
 use pb::sf::substreams::rpc::v2::{BlockScopedData, BlockUndoSignal};
 use pb::sf::substreams::v1::Package;
@@ -8,15 +9,53 @@ use regex::Regex;
 use semver::Version;
 
 use prost::Message;
-use std::{env,  sync::Arc};
+use std::{env, path::PathBuf, sync::Arc};
 use substreams::SubstreamsEndpoint;
+use api::{ApiError, ApiRequest, ApiResponse};
+use endpoint_pool::{shared_pool_config, SubstreamsEndpointPool};
+use entity_store::{decode_entity_changes, EntityStore};
+use file_sink::{FileFormat, FileSink, OutputDecoder};
+use firehose::{BlockReference, FetchedBlock};
+use http_client::shared_client;
+use http_stream::{HttpStream, StreamFormat};
+use json_rpc::{
+    parse_batch_requests, send_batch, JsonRpcError, JsonRpcRequest, RpcError,
+};
+use pagination::{ApiPageStream, Pagination};
+use recording::{RECORD_PATH_ENV, REPLAY_SCHEME};
+use response_cache::{cached_api_request, cached_rpc_request, shared_cache};
+use rpc_provider_group::{shared_group, RpcGroupResponse};
+use sink_service::{parse_parameters, SinkCommand, SinkServiceClient};
+use sqlite_sink::SqliteSink;
+use store_state::StoreStateTracker;
+use firehose_stream::{FirehoseBlock, FirehoseBlockResponse, FirehoseStream};
 use substreams_stream::{BlockResponse, SubstreamsStream};
 
+pub mod api;
+pub mod endpoint_pool;
+pub mod entity_store;
+pub mod eth;
+pub mod file_sink;
+pub mod firehose;
+pub mod firehose_stream;
+pub mod http_client;
+pub mod http_stream;
+pub mod json_rpc;
+#[cfg(test)]
+mod mock_http_server;
+pub mod mock_server;
+pub mod pagination;
 pub mod pb;
+pub mod recording;
+pub mod response_cache;
+pub mod rpc_provider_group;
+pub mod sink_service;
+pub mod sqlite_sink;
+pub mod store_state;
 pub mod substreams;
 pub mod substreams_stream;
+pub mod websocket;
 
-use reqwest::Client;
 use serde_json::{json, Value};
 // use std::error::Error;
 
@@ -30,41 +69,38 @@ lazy_static! {
 const REGISTRY_URL: &str = "https://spkg.io";
 
 // Refactored into lib.rs
+//
+// `endpoint_url` may list several providers separated by commas, in which case they are
+// grouped in a `SubstreamsEndpointPool` and the stream fails over between them, following
+// the configuration set with `configure_shared_pool`.
 pub async fn substreams_call(
     endpoint_url: String,
     package_file: &str,
     module_name: &str,
     range: Option<String>,
 ) -> Result<Vec<Vec<u8>>, Error> {
-    let endpoint_url = if endpoint_url.starts_with("http") {
-        endpoint_url
-    } else {
-        format!("https://{}", endpoint_url)
-    };
-
-    let token = env::var("SUBSTREAMS_API_TOKEN")
-        .context("The environment variable SUBSTREAMS_API_TOKEN is not set")?;
-
-    let package = read_package(package_file).await?;
-    let block_range = read_block_range(&package, module_name, range.as_deref())?;
-    let endpoint = Arc::new(SubstreamsEndpoint::new(&endpoint_url, Some(token)).await?);
     let cursor: Option<String> = load_persisted_cursor()?;
-    let mut stream = SubstreamsStream::new(
-        endpoint,
-        cursor,
-        package.modules,
-        module_name.to_string(),
-        block_range.0,
-        block_range.1,
-    );
+    let stream =
+        open_substreams_stream(&endpoint_url, package_file, module_name, range, cursor, None)
+            .await?;
 
-    let mut results = Vec::new();
+    collect_module_outputs(stream).await
+}
+
+// Collects the non-empty outputs of a stream, dropping those of blocks undone by a fork.
+async fn collect_module_outputs(
+    mut stream: impl Stream<Item = Result<BlockResponse, Error>> + Unpin,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let mut results: Vec<(u64, Vec<u8>)> = Vec::new();
 
     while let Some(result) = stream.next().await {
         match result {
             Ok(BlockResponse::New(data)) => {
                 match process_block_scoped_data(&data) {
-                    Ok(decoded_data) => results.push(decoded_data),
+                    Ok(decoded_data) => {
+                        let block_num = data.clock.as_ref().map(|c| c.number).unwrap_or_default();
+                        results.push((block_num, decoded_data));
+                    }
                     Err(err) => {
                         if err.to_string() == "Empty block data" {
                             // Skip empty blocks
@@ -77,7 +113,7 @@ pub async fn substreams_call(
                 persist_cursor(data.cursor)?;
             }
             Ok(BlockResponse::Undo(undo_signal)) => {
-                process_block_undo_signal(&undo_signal)?;
+                process_block_undo_signal(&undo_signal, &mut results)?;
                 persist_cursor(undo_signal.last_valid_cursor)?;
             }
             Err(err) => {
@@ -86,15 +122,516 @@ pub async fn substreams_call(
         }
     }
 
-    Ok(results)
+    Ok(results.into_iter().map(|(_, output)| output).collect())
+}
+
+// Streams the responses of a module one at a time, starting from `cursor` when given. The
+// stream is only opened when first polled, so reading the package or reaching the provider
+// fails through the stream. Without a `token`, `SUBSTREAMS_API_TOKEN` is used.
+pub fn substreams_session(
+    endpoint_url: String,
+    package_file: String,
+    module_name: String,
+    range: Option<String>,
+    cursor: Option<String>,
+    token: Option<String>,
+) -> impl Stream<Item = Result<BlockResponse, Error>> + Send {
+    try_stream! {
+        let mut stream = open_substreams_stream(
+            &endpoint_url,
+            &package_file,
+            &module_name,
+            range,
+            cursor,
+            token,
+        )
+        .await?;
+
+        while let Some(response) = stream.next().await {
+            yield response?;
+        }
+    }
+}
+
+// Streams a module outputting `sf.substreams.entity.v1.EntityChanges` into an in-memory
+// `EntityStore`, reverting the store on undo signals.
+pub async fn substreams_entities_call(
+    endpoint_url: String,
+    package_file: &str,
+    module_name: &str,
+    range: Option<String>,
+) -> Result<EntityStore, Error> {
+    let stream =
+        open_substreams_stream(&endpoint_url, package_file, module_name, range, None, None)
+            .await?;
+
+    collect_entities(stream).await
+}
+
+async fn collect_entities(
+    mut stream: impl Stream<Item = Result<BlockResponse, Error>> + Unpin,
+) -> Result<EntityStore, Error> {
+    let mut store = EntityStore::new();
+
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(BlockResponse::New(data)) => {
+                let block_num = data.clock.as_ref().map(|c| c.number).unwrap_or_default();
+                if let Some(output) = data.output.as_ref().and_then(|o| o.map_output.as_ref()) {
+                    store.apply(block_num, &decode_entity_changes(&output.value)?)?;
+                }
+                store.finalize(data.final_block_height);
+            }
+            Ok(BlockResponse::Undo(undo_signal)) => {
+                let last_valid_block = undo_signal
+                    .last_valid_block
+                    .as_ref()
+                    .map(|b| b.number)
+                    .ok_or_else(|| format_err!("undo signal without a last valid block"))?;
+                store.revert_to(last_valid_block);
+            }
+            Err(err) => {
+                return Err(anyhow::anyhow!("Stream terminated with error: {:?}", err));
+            }
+        }
+    }
+
+    Ok(store)
+}
+
+// Streams a store module and reconstructs its key/value state from the
+// `sf.substreams.v1.StoreDeltas` it outputs, undoing the deltas of forked out blocks.
+pub async fn substreams_store_call(
+    endpoint_url: String,
+    package_file: &str,
+    module_name: &str,
+    range: Option<String>,
+) -> Result<StoreStateTracker, Error> {
+    let package = read_package(package_file).await?;
+    let module = package
+        .modules
+        .as_ref()
+        .and_then(|modules| modules.modules.iter().find(|m| m.name == module_name))
+        .ok_or_else(|| format_err!("module '{}' not found in package", module_name))?;
+    let tracker = StoreStateTracker::for_module(module)?;
+
+    let stream =
+        open_package_stream(&endpoint_url, package, module_name, range, None, None).await?;
+
+    track_store_state(stream, tracker).await
+}
+
+async fn track_store_state(
+    mut stream: impl Stream<Item = Result<BlockResponse, Error>> + Unpin,
+    mut tracker: StoreStateTracker,
+) -> Result<StoreStateTracker, Error> {
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(BlockResponse::New(data)) => {
+                let block_num = data.clock.as_ref().map(|c| c.number).unwrap_or_default();
+                if let Some(output) = data.output.as_ref().and_then(|o| o.map_output.as_ref()) {
+                    tracker.apply_encoded(block_num, &output.value)?;
+                }
+                tracker.finalize(data.final_block_height);
+            }
+            Ok(BlockResponse::Undo(undo_signal)) => {
+                let last_valid_block = undo_signal
+                    .last_valid_block
+                    .as_ref()
+                    .map(|b| b.number)
+                    .ok_or_else(|| format_err!("undo signal without a last valid block"))?;
+                tracker.undo_to(last_valid_block);
+            }
+            Err(err) => {
+                return Err(anyhow::anyhow!("Stream terminated with error: {:?}", err));
+            }
+        }
+    }
+
+    Ok(tracker)
+}
+
+// Lands the `EntityChanges` of a module into the SQLite database at `database_path`. The
+// cursor is stored in the same database, so calling it again resumes where it stopped.
+// Returns the cursor of the last processed block.
+pub async fn substreams_sqlite_call(
+    endpoint_url: String,
+    package_file: &str,
+    module_name: &str,
+    range: Option<String>,
+    database_path: &str,
+) -> Result<Option<String>, Error> {
+    let mut sink = SqliteSink::open(database_path)?;
+    let cursor = sink.load_cursor()?;
+
+    let stream =
+        open_substreams_stream(&endpoint_url, package_file, module_name, range, cursor, None)
+            .await?;
+
+    sink_entities(stream, &mut sink).await?;
+    sink.load_cursor()
+}
+
+async fn sink_entities(
+    mut stream: impl Stream<Item = Result<BlockResponse, Error>> + Unpin,
+    sink: &mut SqliteSink,
+) -> Result<(), Error> {
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(BlockResponse::New(data)) => {
+                let block_num = data.clock.as_ref().map(|c| c.number).unwrap_or_default();
+                let changes = match data.output.as_ref().and_then(|o| o.map_output.as_ref()) {
+                    Some(output) => decode_entity_changes(&output.value)?,
+                    None => Default::default(),
+                };
+                sink.apply_block(block_num, data.final_block_height, &changes, &data.cursor)?;
+            }
+            Ok(BlockResponse::Undo(undo_signal)) => {
+                let last_valid_block = undo_signal
+                    .last_valid_block
+                    .as_ref()
+                    .map(|b| b.number)
+                    .ok_or_else(|| format_err!("undo signal without a last valid block"))?;
+                sink.undo(last_valid_block, &undo_signal.last_valid_cursor)?;
+            }
+            Err(err) => {
+                return Err(anyhow::anyhow!("Stream terminated with error: {:?}", err));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Writes the decoded outputs of a module to rolling JSONL, CSV or Parquet files in
+// `output_dir`, `blocks_per_file` blocks per file. The manifest in the directory holds the
+// cursor of the last written block, so calling it again resumes where it stopped. Returns
+// the cursor of the last written block.
+pub async fn substreams_file_sink_call(
+    endpoint_url: String,
+    package_file: &str,
+    module_name: &str,
+    range: Option<String>,
+    output_dir: &str,
+    format: FileFormat,
+    blocks_per_file: u64,
+) -> Result<Option<String>, Error> {
+    let package = read_package(package_file).await?;
+    let decoder = OutputDecoder::from_package(&package, module_name)?;
+    let mut sink = FileSink::open(output_dir, format, module_name, decoder, blocks_per_file)?;
+
+    let stream =
+        open_package_stream(&endpoint_url, package, module_name, range, sink.cursor(), None)
+            .await?;
+
+    write_files(stream, &mut sink).await?;
+    Ok(sink.cursor())
+}
+
+async fn write_files(
+    mut stream: impl Stream<Item = Result<BlockResponse, Error>> + Unpin,
+    sink: &mut FileSink,
+) -> Result<(), Error> {
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(BlockResponse::New(data)) => {
+                let clock = data.clock.clone().unwrap_or_default();
+                let output = data
+                    .output
+                    .as_ref()
+                    .and_then(|o| o.map_output.as_ref())
+                    .map(|o| o.value.as_slice());
+                sink.write_block(&clock, output, &data.cursor, data.final_block_height)?;
+            }
+            Ok(BlockResponse::Undo(undo_signal)) => {
+                let last_valid_block = undo_signal
+                    .last_valid_block
+                    .as_ref()
+                    .map(|b| b.number)
+                    .ok_or_else(|| format_err!("undo signal without a last valid block"))?;
+                sink.undo(last_valid_block);
+            }
+            Err(err) => {
+                return Err(anyhow::anyhow!("Stream terminated with error: {:?}", err));
+            }
+        }
+    }
+
+    sink.finish()
+}
+
+async fn open_substreams_stream(
+    endpoint_url: &str,
+    package_file: &str,
+    module_name: &str,
+    range: Option<String>,
+    cursor: Option<String>,
+    token: Option<String>,
+) -> Result<SubstreamsStream, Error> {
+    let package = read_package(package_file).await?;
+    open_package_stream(endpoint_url, package, module_name, range, cursor, token).await
+}
+
+async fn open_package_stream(
+    endpoint_url: &str,
+    package: Package,
+    module_name: &str,
+    range: Option<String>,
+    cursor: Option<String>,
+    token: Option<String>,
+) -> Result<SubstreamsStream, Error> {
+    let endpoint_urls = parse_endpoint_urls(endpoint_url);
+
+    // Replaying a recorded session (`replay://<file>`) needs no credentials.
+    let token = if token.is_some() {
+        token
+    } else if endpoint_urls.iter().all(|url| url.starts_with(REPLAY_SCHEME)) {
+        env::var("SUBSTREAMS_API_TOKEN").ok()
+    } else {
+        Some(
+            env::var("SUBSTREAMS_API_TOKEN")
+                .context("The environment variable SUBSTREAMS_API_TOKEN is not set")?,
+        )
+    };
+    let record_path = env::var(RECORD_PATH_ENV).ok().map(PathBuf::from);
+
+    let block_range = read_block_range(&package, module_name, range.as_deref())?;
+
+    let mut endpoints = Vec::with_capacity(endpoint_urls.len());
+    for url in &endpoint_urls {
+        let mut endpoint = SubstreamsEndpoint::new(url, token.clone()).await?;
+        if let Some(path) = &record_path {
+            endpoint = endpoint.with_recording(path.clone())?;
+        }
+        endpoints.push(Arc::new(endpoint));
+    }
+
+    let pool = SubstreamsEndpointPool::new(endpoints)?.with_config(&shared_pool_config());
+    if pool.len() > 1 {
+        pool.health_check().await;
+    }
+
+    // Validate the request against what the provider actually serves before opening the
+    // stream. Providers not implementing the Info endpoint are streamed from as before.
+    if let Ok(info) = pool.current().info().await {
+        info.validate(&package.network, block_range.0)?;
+    }
+
+    Ok(SubstreamsStream::with_pool(
+        Arc::new(pool),
+        cursor,
+        package.modules,
+        module_name.to_string(),
+        block_range.0,
+        block_range.1,
+    ))
+}
+
+// Streams raw chain blocks from a Firehose provider. Each returned item is the chain
+// specific block payload (the `value` of the block `Any`, e.g. `sf.ethereum.type.v1.Block`).
+pub async fn firehose_call(
+    endpoint_url: String,
+    range: Option<String>,
+    final_blocks_only: bool,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let endpoint_urls = parse_endpoint_urls(&endpoint_url);
+
+    let token = env::var("FIREHOSE_API_TOKEN")
+        .or_else(|_| env::var("SUBSTREAMS_API_TOKEN"))
+        .context("Neither FIREHOSE_API_TOKEN nor SUBSTREAMS_API_TOKEN environment variable is set")?;
+
+    let pool = SubstreamsEndpointPool::from_urls(&endpoint_urls, Some(token))
+        .await?
+        .with_config(&shared_pool_config());
+    if pool.len() > 1 {
+        pool.health_check().await;
+    }
+
+    let block_range = match pool.current().firehose_info().await {
+        Ok(info) => {
+            let block_range = parse_block_range(range.as_deref(), info.first_streamable_block_num)?;
+            info.validate("", block_range.0)?;
+            block_range
+        }
+        // Providers not implementing the Info endpoint are streamed from without validation.
+        Err(_) => parse_block_range(range.as_deref(), 0)?,
+    };
+
+    let stream = FirehoseStream::with_pool(
+        Arc::new(pool),
+        None,
+        block_range.0,
+        block_range.1,
+        final_blocks_only,
+    );
+
+    collect_firehose_blocks(stream, final_blocks_only).await
+}
+
+// Collects the payloads of the blocks of a stream. Only final blocks are kept when
+// `final_blocks_only`, otherwise new blocks are, minus those undone by a fork.
+async fn collect_firehose_blocks(
+    mut stream: impl Stream<Item = Result<FirehoseBlockResponse, Error>> + Unpin,
+    final_blocks_only: bool,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let mut results: Vec<FirehoseBlock> = Vec::new();
+
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(FirehoseBlockResponse::New(block)) if !final_blocks_only => {
+                results.push(block);
+            }
+            Ok(FirehoseBlockResponse::Final(block)) if final_blocks_only => {
+                results.push(block);
+            }
+            Ok(FirehoseBlockResponse::Undo(undone)) if !final_blocks_only => {
+                // Firehose sends one undo per forked out block, newest first, so the undone
+                // block must be the last one kept.
+                match results.last() {
+                    Some(last) if is_same_block(last, &undone) => {
+                        results.pop();
+                    }
+                    Some(_) => {
+                        return Err(anyhow::anyhow!(
+                            "undo received for {} while the last block kept is {}",
+                            describe_firehose_block(&undone),
+                            describe_firehose_block(results.last().unwrap())
+                        ));
+                    }
+                    None => {
+                        return Err(anyhow::anyhow!("undo received for a block never received"));
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                return Err(anyhow::anyhow!("Stream terminated with error: {:?}", err));
+            }
+        }
+    }
+
+    Ok(results.into_iter().map(|block| block.block.value).collect())
+}
+
+// Blocks are matched on their number and id, or on their payload when a provider sends
+// no metadata.
+fn is_same_block(a: &FirehoseBlock, b: &FirehoseBlock) -> bool {
+    match (&a.metadata, &b.metadata) {
+        (Some(a), Some(b)) => a.num == b.num && a.id == b.id,
+        _ => a.block == b.block,
+    }
 }
 
+fn describe_firehose_block(block: &FirehoseBlock) -> String {
+    match &block.metadata {
+        Some(metadata) => format!("block {} ({})", metadata.num, metadata.id),
+        None => format!("block with cursor {}", block.cursor),
+    }
+}
+
+// Fetches a single block from a Firehose provider without opening a stream.
+pub async fn firehose_fetch_block(
+    endpoint_url: String,
+    reference: BlockReference,
+) -> Result<FetchedBlock, Error> {
+    let token = env::var("FIREHOSE_API_TOKEN")
+        .or_else(|_| env::var("SUBSTREAMS_API_TOKEN"))
+        .context("Neither FIREHOSE_API_TOKEN nor SUBSTREAMS_API_TOKEN environment variable is set")?;
+
+    fetch_block_from(&parse_endpoint_urls(&endpoint_url), Some(token), reference).await
+}
+
+// Asks each provider in turn for the block, returning the first answer. The error of the
+// last provider is returned when none of them could serve it.
+async fn fetch_block_from(
+    endpoint_urls: &[String],
+    token: Option<String>,
+    reference: BlockReference,
+) -> Result<FetchedBlock, Error> {
+    let mut last_error = format_err!("no endpoint provided");
+
+    for endpoint_url in endpoint_urls {
+        let endpoint = SubstreamsEndpoint::new(endpoint_url, token.clone()).await?;
+        match endpoint.firehose_block(reference.clone()).await {
+            Ok(block) => return Ok(block),
+            Err(err) => last_error = err.context(format!("fetching block from {}", endpoint_url)),
+        }
+    }
+
+    Err(last_error)
+}
+
+// Runs a sink service command against a provider and returns the outcome as JSON.
+pub async fn sink_service_call(endpoint_url: String, command: SinkCommand) -> Result<Value, Error> {
+    let endpoint_urls = parse_endpoint_urls(&endpoint_url);
+    let endpoint_url = endpoint_urls
+        .first()
+        .ok_or_else(|| format_err!("no endpoint provided"))?;
+
+    let token = env::var("SUBSTREAMS_API_TOKEN")
+        .context("The environment variable SUBSTREAMS_API_TOKEN is not set")?;
+
+    let endpoint = Arc::new(SubstreamsEndpoint::new(endpoint_url, Some(token)).await?);
+    let client = SinkServiceClient::new(endpoint);
+
+    let value = match command {
+        SinkCommand::Deploy {
+            package_file,
+            parameters,
+            development_mode,
+        } => {
+            let package = read_package(&package_file).await?;
+            let parameters = parse_parameters(parameters.as_deref())?;
+            client
+                .deploy(package, parameters, development_mode)
+                .await?
+                .to_json()
+        }
+        SinkCommand::Update {
+            deployment_id,
+            package_file,
+            reset,
+        } => {
+            let package = read_package(&package_file).await?;
+            client.update(&deployment_id, package, reset).await?.to_json()
+        }
+        SinkCommand::Info(deployment_id) => client.info(&deployment_id).await?.to_json(),
+        SinkCommand::List => Value::Array(
+            client
+                .list()
+                .await?
+                .iter()
+                .map(|deployment| deployment.to_json())
+                .collect(),
+        ),
+        SinkCommand::Pause(deployment_id) => client.pause(&deployment_id).await?.to_json(),
+        SinkCommand::Stop(deployment_id) => client.stop(&deployment_id).await?.to_json(),
+        SinkCommand::Resume(deployment_id) => client.resume(&deployment_id).await?.to_json(),
+        SinkCommand::Remove(deployment_id) => json!({
+            "previous_status": client.remove(&deployment_id).await?.as_str_name(),
+        }),
+        SinkCommand::WaitForStatus {
+            deployment_id,
+            statuses,
+            poll_interval,
+            timeout,
+        } => client
+            .wait_for_status(&deployment_id, &statuses, poll_interval, timeout)
+            .await?
+            .to_json(),
+    };
+
+    Ok(value)
+}
+
+// Sends a JSON-RPC 2.0 request and returns its `result`. JSON-RPC error objects, non-2xx
+// HTTP statuses and malformed responses are returned as a typed `RpcError`.
 pub async fn rpc_call(
     rpc_endpoint: &str,
     method: &str,
     params_input: &str,
     id: i32,
-) -> Result<Value, anyhow::Error> {
+) -> Result<Value, RpcError> {
     // Ensure the endpoint starts with HTTP
     let rpc_endpoint = if rpc_endpoint.starts_with("http") {
         rpc_endpoint.to_string()
@@ -104,47 +641,64 @@ pub async fn rpc_call(
 
     // Parse the parameters JSON
     let params: Value = serde_json::from_str(params_input)
-        .context("Invalid JSON for parameters")?;
-
-    // Build the JSON-RPC request body
-    let request_body = json!({
-        "jsonrpc": "2.0",
-        "method": method,
-        "params": params,
-        "id": id
-    });
-
-    // Create an HTTP client
-    let client = Client::new();
-
-    // Send the POST request
-    let response = client
-        .post(&rpc_endpoint)
-        .json(&request_body)
-        .send()
-        .await
-        .context("Failed to send RPC call")?;
+        .map_err(|e| RpcError::InvalidRequest(format!("invalid JSON for parameters: {}", e)))?;
 
-    // Parse and return the response
-    let response_json: Value = response
-        .json()
-        .await
-        .context("Failed to parse RPC response")?;
+    let request = JsonRpcRequest::new(method, params);
+    let id = u64::try_from(id)
+        .map_err(|_| RpcError::InvalidRequest(format!("id must not be negative, got {}", id)))?;
+
+    cached_rpc_request(
+        &shared_client(),
+        shared_cache().as_deref(),
+        &rpc_endpoint,
+        &request,
+        id,
+    )
+    .await
+}
+
+// Sends a JSON-RPC request to a group of comma separated providers according to
+// `strategy`: `fallback`, `round_robin` or `quorum:<agree>/<providers>`. The response tells
+// which provider answered and how every provider tried did.
+pub async fn rpc_group_call(
+    rpc_endpoints: &str,
+    strategy: &str,
+    method: &str,
+    params_input: &str,
+) -> Result<RpcGroupResponse, RpcError> {
+    let group = shared_group(rpc_endpoints, strategy)
+        .map_err(|e| RpcError::InvalidRequest(format!("{:#}", e)))?;
+
+    let params: Value = serde_json::from_str(params_input)
+        .map_err(|e| RpcError::InvalidRequest(format!("invalid JSON for parameters: {}", e)))?;
+
+    group.call(&JsonRpcRequest::new(method, params)).await
+}
+
+// Sends a JSON array of `{"method", "params"}` requests as JSON-RPC batches of at most
+// `max_batch_size` requests (0 for the default). Returns one item per request, in order:
+// its `result`, or its JSON-RPC error.
+pub async fn rpc_batch_call(
+    rpc_endpoint: &str,
+    requests_input: &str,
+    max_batch_size: usize,
+) -> Result<Vec<Result<Value, JsonRpcError>>, RpcError> {
+    let rpc_endpoint = if rpc_endpoint.starts_with("http") {
+        rpc_endpoint.to_string()
+    } else {
+        format!("https://{}", rpc_endpoint)
+    };
+
+    let requests = parse_batch_requests(requests_input)
+        .map_err(|e| RpcError::InvalidRequest(format!("{:#}", e)))?;
 
-    Ok(response_json)
+    send_batch(&shared_client(), &rpc_endpoint, &requests, max_batch_size).await
 }
 
 pub async fn api_call(
     api_url: &str,
     optional_headers: Option<&str>,
 ) -> Result<String, anyhow::Error> {
-    // Ensure the API URL starts with HTTP
-    let api_url = if api_url.starts_with("http") {
-        api_url.to_string()
-    } else {
-        format!("https://{}", api_url)
-    };
-
     // Parse optional headers JSON
     let headers: Value = match optional_headers {
         Some(headers_str) => serde_json::from_str(headers_str)
@@ -152,35 +706,75 @@ pub async fn api_call(
         None => json!({}),
     };
 
-    // Create HTTP client
-    let client = Client::new();
-
-    // Create request with optional headers
-    let mut request = client.get(&api_url);
-    if let Some(headers_map) = headers.as_object() {
-        for (key, value) in headers_map {
-            if let Some(header_value) = value.as_str() {
-                request = request.header(key, header_value);
-            }
-        }
-    }
-
-    // Send the GET request
-    let response = request
-        .send()
+    let request = ApiRequest::from_json(api_url, &json!({ "headers": headers }))?;
+    let response = cached_api_request(&shared_client(), shared_cache().as_deref(), &request)
         .await
         .context("Failed to send API call")?;
 
-    // Parse and return the response text
-    let response_text = response
-        .text()
-        .await
-        .context("Failed to read API response")?;
+    Ok(response.text())
+}
 
-    Ok(response_text)
+// Sends an HTTP request described by `options` (see `ApiRequest::from_json`: method,
+// headers, query parameters and a JSON or raw body) and returns the status, headers and
+// body of the response, whatever the status.
+pub async fn api_request_call(
+    api_url: &str,
+    options: Option<&str>,
+) -> Result<ApiResponse, ApiError> {
+    let options: Value = match options {
+        Some(options) => serde_json::from_str(options)
+            .map_err(|e| ApiError::InvalidRequest(format!("invalid JSON for options: {}", e)))?,
+        None => Value::Null,
+    };
+
+    let request = ApiRequest::from_json(api_url, &options)?;
+    cached_api_request(&shared_client(), shared_cache().as_deref(), &request).await
 }
 
 
+// Opens a stream over the pages of a paginated API. `options` are those of
+// `api_request_call`, `pagination` is read by `Pagination::from_json`. Pages are requested
+// as the stream is polled, up to `max_pages` when set.
+pub fn api_paginate(
+    api_url: &str,
+    options: Option<&str>,
+    pagination: &str,
+    max_pages: Option<usize>,
+) -> Result<ApiPageStream, ApiError> {
+    let options: Value = match options {
+        Some(options) => serde_json::from_str(options)
+            .map_err(|e| ApiError::InvalidRequest(format!("invalid JSON for options: {}", e)))?,
+        None => Value::Null,
+    };
+    let pagination: Value = serde_json::from_str(pagination)
+        .map_err(|e| ApiError::InvalidRequest(format!("invalid JSON for pagination: {}", e)))?;
+
+    let request = ApiRequest::from_json(api_url, &options)?;
+    Ok(ApiPageStream::new(
+        shared_client(),
+        request,
+        Pagination::from_json(&pagination)?,
+        max_pages,
+    ))
+}
+
+// Opens a streaming HTTP request, `format` being `sse` for Server-Sent Events or `jsonl`
+// for newline delimited JSON. `options` are those of `api_request_call`.
+pub fn api_stream(
+    api_url: &str,
+    options: Option<&str>,
+    format: &str,
+) -> Result<HttpStream, ApiError> {
+    let options: Value = match options {
+        Some(options) => serde_json::from_str(options)
+            .map_err(|e| ApiError::InvalidRequest(format!("invalid JSON for options: {}", e)))?,
+        None => Value::Null,
+    };
+
+    let request = ApiRequest::from_json(api_url, &options)?;
+    Ok(HttpStream::open(shared_client(), request, StreamFormat::parse(format)?))
+}
+
 fn process_block_scoped_data(
     data: &BlockScopedData,
 ) -> Result<Vec<u8>, Error> {
@@ -195,14 +789,21 @@ fn process_block_scoped_data(
 }
 
 
-fn process_block_undo_signal(_undo_signal: &BlockUndoSignal) -> Result<(), anyhow::Error> {
+fn process_block_undo_signal(
+    undo_signal: &BlockUndoSignal,
+    results: &mut Vec<(u64, Vec<u8>)>,
+) -> Result<(), anyhow::Error> {
     // `BlockUndoSignal` must be treated as "delete every data that has been recorded after
-    // block height specified by block in BlockUndoSignal". In the example above, this means
-    // you must delete changes done by `Block #7b` and `Block #6b`. The exact details depends
-    // on your own logic. If for example all your added record contain a block number, a
-    // simple way is to do `delete all records where block_num > 5` which is the block num
-    // received in the `BlockUndoSignal` (this is true for append only records, so when only `INSERT` are allowed).
-    unimplemented!("you must implement some kind of block undo handling, or request only final blocks (tweak substreams_stream.rs)")
+    // block height specified by block in BlockUndoSignal". Every output is recorded with its
+    // block number, so this is `delete all records where block_num > last_valid_block`.
+    let last_valid_block = undo_signal
+        .last_valid_block
+        .as_ref()
+        .map(|block| block.number)
+        .ok_or_else(|| format_err!("block undo signal without a last valid block"))?;
+
+    results.retain(|(block_num, _)| *block_num <= last_valid_block);
+    Ok(())
 }
 
 fn persist_cursor(_cursor: String) -> Result<(), anyhow::Error> {
@@ -238,6 +839,12 @@ fn read_block_range(
         .find(|m| m.name == module_name)
         .ok_or_else(|| format_err!("module '{}' not found in package", module_name))?;
 
+    parse_block_range(range, module.initial_block)
+}
+
+// Parses a `<start>:<stop>` range where both sides are optional and may be relative
+// (`+N`): a relative start is added to `initial_block`, a relative stop to the start.
+fn parse_block_range(range: Option<&str>, initial_block: u64) -> Result<(i64, u64), anyhow::Error> {
     let input: String = range.unwrap_or("").to_string();
 
     let (prefix, suffix) = match input.split_once(":") {
@@ -246,14 +853,14 @@ fn read_block_range(
     };
 
     let start: i64 = match prefix.as_str() {
-        "" => module.initial_block as i64,
+        "" => initial_block as i64,
         x if x.starts_with("+") => {
             let block_count = x
                 .trim_start_matches("+")
                 .parse::<u64>()
                 .context("argument <start> is not a valid integer")?;
 
-            (module.initial_block + block_count) as i64
+            (initial_block + block_count) as i64
         }
         x => x
             .parse::<i64>()
@@ -279,6 +886,22 @@ fn read_block_range(
     Ok((start, stop))
 }
 
+// Splits a comma separated list of endpoints, defaulting each to https.
+fn parse_endpoint_urls(endpoint_url: &str) -> Vec<String> {
+    endpoint_url
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            if url.starts_with("http") || url.starts_with(REPLAY_SCHEME) {
+                url.to_string()
+            } else {
+                format!("https://{}", url)
+            }
+        })
+        .collect()
+}
+
 
 async fn read_package(input: &str) -> Result<Package, anyhow::Error> {
     let mut mutable_input = input.to_string();
@@ -347,3 +970,332 @@ fn parse_standard_package_and_version(input: &str) -> Result<(String, String), E
 fn is_valid_version(version: &str) -> bool {
     Version::parse(version).is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{
+        MockEvent, MockFirehoseEvent, MockFirehoseServer, MockSubstreamsServer,
+    };
+
+    const MODULE: &str = "map_events";
+
+    async fn connect(server: &MockSubstreamsServer) -> SubstreamsStream {
+        let endpoint = Arc::new(SubstreamsEndpoint::new(server.url(), None).await.unwrap());
+        SubstreamsStream::new(endpoint, None, None, MODULE.to_string(), 1, 0)
+    }
+
+    fn package(network: &str) -> Package {
+        use pb::sf::substreams::v1::{module, Module, Modules};
+
+        Package {
+            network: network.to_string(),
+            modules: Some(Modules {
+                modules: vec![Module {
+                    name: MODULE.to_string(),
+                    initial_block: 100,
+                    kind: Some(module::Kind::KindMap(module::KindMap {
+                        output_type: "proto:mock.Events".to_string(),
+                    })),
+                    ..Default::default()
+                }],
+                binaries: Vec::new(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    async fn open(server: &MockSubstreamsServer, network: &str, range: &str) -> Result<(), Error> {
+        let token = Some("test".to_string());
+        let range = Some(range.to_string());
+        open_package_stream(&server.url(), package(network), MODULE, range, None, token)
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn streams_are_validated_against_endpoint_info() {
+        let server = MockSubstreamsServer::start(vec![])
+            .await
+            .unwrap()
+            .with_info(pb::sf::firehose::v2::InfoResponse {
+                chain_name: "mainnet".to_string(),
+                chain_name_aliases: vec!["ethereum".to_string()],
+                first_streamable_block_num: 100,
+                ..Default::default()
+            });
+
+        assert!(open(&server, "ethereum", "").await.is_ok());
+        assert!(open(&server, "", "-10:").await.is_ok());
+
+        let err = open(&server, "polygon", "").await.unwrap_err();
+        assert!(err.to_string().contains("not served"), "{}", err);
+        let err = open(&server, "mainnet", "50:").await.unwrap_err();
+        assert!(err.to_string().contains("first streamable block"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn streams_open_without_endpoint_info() {
+        let server = MockSubstreamsServer::start(vec![]).await.unwrap();
+
+        assert!(open(&server, "polygon", "50:").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn module_outputs_drop_undone_blocks() {
+        let server = MockSubstreamsServer::start(vec![vec![
+            MockEvent::data(MODULE, 1, vec![1]),
+            MockEvent::data(MODULE, 2, vec![]),
+            MockEvent::data(MODULE, 3, vec![3]),
+            MockEvent::undo(1),
+            MockEvent::data(MODULE, 2, vec![22]),
+        ]])
+        .await
+        .unwrap();
+
+        let outputs = collect_module_outputs(connect(&server).await).await.unwrap();
+
+        assert_eq!(outputs, vec![vec![1], vec![22]]);
+    }
+
+    async fn firehose_blocks(
+        events: Vec<MockFirehoseEvent>,
+        final_blocks_only: bool,
+    ) -> Result<Vec<String>, Error> {
+        let server = MockFirehoseServer::start(vec![events]).await.unwrap();
+        let endpoint = Arc::new(SubstreamsEndpoint::new(server.url(), None).await.unwrap());
+        let stream = FirehoseStream::new(endpoint, None, 1, 0, final_blocks_only);
+
+        let blocks = collect_firehose_blocks(stream, final_blocks_only).await?;
+        Ok(blocks
+            .into_iter()
+            .map(|block| String::from_utf8(block).unwrap())
+            .collect())
+    }
+
+    fn undo_without_block() -> MockEvent {
+        use pb::sf::substreams::rpc::v2::response::Message;
+
+        MockEvent::Response(Box::new(pb::sf::substreams::rpc::v2::Response {
+            message: Some(Message::BlockUndoSignal(BlockUndoSignal {
+                last_valid_block: None,
+                last_valid_cursor: String::new(),
+            })),
+        }))
+    }
+
+    #[tokio::test]
+    async fn entities_fail_on_undo_signals_without_a_block() {
+        let server = MockSubstreamsServer::start(vec![vec![undo_without_block()]])
+            .await
+            .unwrap();
+
+        let result = collect_entities(connect(&server).await).await;
+
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "undo signal without a last valid block"
+        );
+    }
+
+    #[tokio::test]
+    async fn sqlite_sink_fails_on_undo_signals_without_a_block() {
+        let server = MockSubstreamsServer::start(vec![vec![undo_without_block()]])
+            .await
+            .unwrap();
+        let mut sink = SqliteSink::open_in_memory().unwrap();
+
+        let result = sink_entities(connect(&server).await, &mut sink).await;
+
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "undo signal without a last valid block"
+        );
+        assert_eq!(sink.load_cursor().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn file_sink_fails_on_undo_signals_without_a_block() {
+        use prost_types::{DescriptorProto, FileDescriptorProto};
+
+        let mut package = package("mainnet");
+        package.proto_files = vec![FileDescriptorProto {
+            name: Some("mock.proto".to_string()),
+            package: Some("mock".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Events".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let decoder = OutputDecoder::from_package(&package, MODULE).unwrap();
+        let dir = std::env::temp_dir().join(format!("unifiedstreams-undo-{}", std::process::id()));
+        let mut sink = FileSink::open(&dir, FileFormat::Jsonl, MODULE, decoder, 10).unwrap();
+        let server = MockSubstreamsServer::start(vec![vec![undo_without_block()]])
+            .await
+            .unwrap();
+
+        let result = write_files(connect(&server).await, &mut sink).await;
+
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "undo signal without a last valid block"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn store_state_follows_deltas_and_undos() {
+        use pb::sf::substreams::v1::{
+            module::kind_store::UpdatePolicy, store_delta::Operation, StoreDelta, StoreDeltas,
+        };
+        use store_state::{StoreValue, StoreValueType};
+
+        let deltas = |deltas: Vec<(Operation, &str, &str, &str)>| {
+            StoreDeltas {
+                store_deltas: deltas
+                    .into_iter()
+                    .enumerate()
+                    .map(|(ordinal, (operation, key, old, new))| StoreDelta {
+                        operation: operation as i32,
+                        ordinal: ordinal as u64,
+                        key: key.to_string(),
+                        old_value: old.as_bytes().to_vec(),
+                        new_value: new.as_bytes().to_vec(),
+                    })
+                    .collect(),
+            }
+            .encode_to_vec()
+        };
+
+        let server = MockSubstreamsServer::start(vec![vec![
+            MockEvent::data(MODULE, 1, deltas(vec![(Operation::Create, "a", "", "1")])),
+            MockEvent::data(
+                MODULE,
+                2,
+                deltas(vec![
+                    (Operation::Update, "a", "1", "2"),
+                    (Operation::Create, "b", "", "5"),
+                ]),
+            )
+            .with_final_block_height(1),
+            MockEvent::undo(1),
+            MockEvent::data(MODULE, 2, deltas(vec![(Operation::Update, "a", "1", "3")])),
+        ]])
+        .await
+        .unwrap();
+
+        let tracker = StoreStateTracker::new(StoreValueType::Int64, UpdatePolicy::Set);
+        let tracker = track_store_state(connect(&server).await, tracker)
+            .await
+            .unwrap();
+
+        assert_eq!(tracker.get("a").unwrap(), Some(StoreValue::Int64(3)));
+        assert_eq!(tracker.get("b").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rpc_call_rejects_negative_ids() {
+        let err = rpc_call("http://127.0.0.1:9", "eth_blockNumber", "[]", -1)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, RpcError::InvalidRequest(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn firehose_blocks_are_collected_once() {
+        use pb::sf::firehose::v2::ForkStep::{StepFinal, StepNew};
+
+        let events = vec![
+            MockFirehoseEvent::block(1, StepNew),
+            MockFirehoseEvent::block(1, StepFinal),
+            MockFirehoseEvent::block(2, StepNew),
+        ];
+
+        let blocks = firehose_blocks(events.clone(), false).await.unwrap();
+        assert_eq!(blocks, vec!["block-1", "block-2"]);
+
+        let blocks = firehose_blocks(events, true).await.unwrap();
+        assert_eq!(blocks, vec!["block-1"]);
+    }
+
+    #[tokio::test]
+    async fn firehose_undo_drops_blocks_without_metadata() {
+        use pb::sf::firehose::v2::ForkStep::{StepNew, StepUndo};
+
+        let blocks = firehose_blocks(
+            vec![
+                MockFirehoseEvent::block(1, StepNew).without_metadata(),
+                MockFirehoseEvent::block(2, StepNew).without_metadata(),
+                MockFirehoseEvent::block(3, StepNew).without_metadata(),
+                MockFirehoseEvent::block(3, StepUndo).without_metadata(),
+                MockFirehoseEvent::block(2, StepUndo).without_metadata(),
+                MockFirehoseEvent::block(2, StepNew).without_metadata(),
+            ],
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(blocks, vec!["block-1", "block-2"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_block_tries_each_endpoint() {
+        let server = MockFirehoseServer::start(vec![]).await.unwrap();
+        let closed_url = crate::mock_http_server::closed_url().await;
+
+        let urls = vec![closed_url.clone(), server.url()];
+        let block = fetch_block_from(&urls, None, BlockReference::Number(7))
+            .await
+            .unwrap();
+
+        assert_eq!(block.block.value, b"block-7");
+        assert_eq!(server.fetched().len(), 1);
+
+        let err = fetch_block_from(&[closed_url], None, BlockReference::Number(7))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("fetching block from"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn firehose_undo_of_an_unknown_block_fails() {
+        use pb::sf::firehose::v2::ForkStep::StepUndo;
+
+        let result = firehose_blocks(vec![MockFirehoseEvent::block(1, StepUndo)], false).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn firehose_undo_of_another_block_fails() {
+        use pb::sf::firehose::v2::ForkStep::{StepNew, StepUndo};
+
+        let result = firehose_blocks(
+            vec![
+                MockFirehoseEvent::block(1, StepNew),
+                MockFirehoseEvent::block(2, StepNew),
+                MockFirehoseEvent::block(1, StepUndo),
+            ],
+            false,
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "undo received for block 1 (block-1) while the last block kept is block 2 (block-2)"
+        );
+
+        let result = firehose_blocks(
+            vec![
+                MockFirehoseEvent::block(1, StepNew).without_metadata(),
+                MockFirehoseEvent::block(2, StepNew).without_metadata(),
+                MockFirehoseEvent::block(1, StepUndo).without_metadata(),
+            ],
+            false,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+}
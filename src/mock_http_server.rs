@@ -0,0 +1,225 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::sleep,
+};
+
+// A request received by a `MockHttpServer`.
+#[derive(Clone, Debug)]
+pub(crate) struct MockHttpRequest {
+    // The request line and headers, as received.
+    pub(crate) head: String,
+    pub(crate) body: Vec<u8>,
+}
+
+impl MockHttpRequest {
+    // The request target, e.g. `/items?page=2`.
+    pub(crate) fn target(&self) -> &str {
+        self.head.split(' ').nth(1).unwrap_or_default()
+    }
+
+    // The first `name` header, matched case-insensitively.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    pub(crate) fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+
+    // The request as received, head and body.
+    pub(crate) fn raw(&self) -> Vec<u8> {
+        let mut raw = format!("{}\r\n\r\n", self.head).into_bytes();
+        raw.extend_from_slice(&self.body);
+        raw
+    }
+}
+
+// What a `MockHttpServer` answers before closing the connection.
+#[derive(Clone, Debug)]
+pub(crate) enum MockHttpResponse {
+    Raw(Vec<u8>),
+    // Keeps the connection open without answering.
+    Hang,
+}
+
+impl MockHttpResponse {
+    // `status` is the status line after the version, e.g. `200 OK`, and `headers` whole
+    // header lines. `Content-Length` and `Connection: close` are added.
+    pub(crate) fn new(status: &str, headers: &[&str], body: impl AsRef<[u8]>) -> Self {
+        let body = body.as_ref();
+        let mut response = format!("HTTP/1.1 {}\r\n", status);
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        ));
+
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+        MockHttpResponse::Raw(response)
+    }
+
+    pub(crate) fn json(status: &str, body: &Value) -> Self {
+        Self::new(
+            status,
+            &["Content-Type: application/json"],
+            body.to_string(),
+        )
+    }
+
+    // A response written exactly as given, e.g. a stream cut in the middle of an event.
+    pub(crate) fn raw(response: &str) -> Self {
+        MockHttpResponse::Raw(response.as_bytes().to_vec())
+    }
+}
+
+type Respond = dyn Fn(&MockHttpRequest, usize) -> MockHttpResponse + Send + Sync;
+
+// A local HTTP/1.1 server speaking over raw TCP, so tests control every byte a client
+// receives. Each connection carries one request. Every received request is recorded.
+pub(crate) struct MockHttpServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<MockHttpRequest>>>,
+}
+
+impl MockHttpServer {
+    // Answers every request with `respond`, called with the request and the number of
+    // requests received before it.
+    pub(crate) async fn start(
+        respond: impl Fn(&MockHttpRequest, usize) -> MockHttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond: Arc<Respond> = Arc::new(respond);
+
+        let received = requests.clone();
+        let addr = serve_connections(move |socket, _| {
+            let received = received.clone();
+            let respond = respond.clone();
+            answer(socket, received, respond)
+        })
+        .await;
+
+        MockHttpServer { addr, requests }
+    }
+
+    // Answers successive requests with `responses`, the last one is repeated.
+    pub(crate) async fn sequence(responses: Vec<MockHttpResponse>) -> Self {
+        Self::start(move |_, n| responses[n.min(responses.len() - 1)].clone()).await
+    }
+
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub(crate) fn requests(&self) -> Vec<MockHttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub(crate) fn hits(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+async fn answer(
+    mut socket: TcpStream,
+    received: Arc<Mutex<Vec<MockHttpRequest>>>,
+    respond: Arc<Respond>,
+) {
+    let Some(request) = read_request(&mut socket).await else {
+        return;
+    };
+
+    let response = {
+        let mut received = received.lock().unwrap();
+        let response = respond(&request, received.len());
+        received.push(request);
+        response
+    };
+
+    match response {
+        MockHttpResponse::Raw(response) => {
+            let _ = socket.write_all(&response).await;
+        }
+        MockHttpResponse::Hang => {
+            sleep(Duration::from_secs(60)).await;
+        }
+    }
+}
+
+// Reads the head, then the body announced by `Content-Length`.
+async fn read_request(socket: &mut TcpStream) -> Option<MockHttpRequest> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let n = socket.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        received.extend_from_slice(&buf[..n]);
+
+        let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let head = String::from_utf8_lossy(&received[..end]).to_string();
+        let request = MockHttpRequest {
+            head,
+            body: Vec::new(),
+        };
+        let length: usize = request
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0);
+
+        let body = &received[end + 4..];
+        if body.len() >= length {
+            return Some(MockHttpRequest {
+                body: body[..length].to_vec(),
+                ..request
+            });
+        }
+    }
+}
+
+// Accepts connections on a local port, handing each one to `handle` with the number of
+// connections accepted before it. Returns the address listened on.
+pub(crate) async fn serve_connections<F, Fut>(handle: F) -> SocketAddr
+where
+    F: Fn(TcpStream, usize) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        for n in 0.. {
+            let Ok((socket, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(handle(socket, n));
+        }
+    });
+
+    addr
+}
+
+// A local `http://` address nothing listens on.
+pub(crate) async fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
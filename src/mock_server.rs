@@ -0,0 +1,654 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{format_err, Error};
+use async_stream::stream;
+use prost_types::Any;
+use serde_json::Value;
+use tokio::{net::TcpListener, sync::oneshot, time::sleep};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{
+        http, BoxFuture, BoxStream, CompressionEncoding, EnabledCompressionEncodings, Service,
+    },
+    server::{Grpc, NamedService, ServerStreamingService, UnaryService},
+    transport::{server::Router, Server},
+    Status,
+};
+
+use crate::eth::decode_data;
+use crate::pb::sf::firehose::v2::{
+    single_block_request, BlockMetadata, ForkStep, InfoRequest, InfoResponse,
+    Request as FirehoseRequest, Response as FirehoseResponse, SingleBlockRequest,
+    SingleBlockResponse,
+};
+use crate::pb::sf::substreams::rpc::v2::{
+    response::Message, BlockScopedData, BlockUndoSignal, Error as ModuleError, MapModuleOutput,
+    ModulesProgress, Request, Response, SessionInit,
+};
+use crate::pb::sf::substreams::v1::{BlockRef, Clock};
+
+// One step of a scripted session replayed by `MockSubstreamsServer`.
+#[derive(Clone, Debug)]
+pub enum MockEvent {
+    Response(Box<Response>),
+    // Ends the session with this gRPC status.
+    Error(Status),
+    // Drops the session mid-stream, as a provider going away would.
+    Disconnect,
+    Delay(Duration),
+}
+
+impl MockEvent {
+    pub fn session(trace_id: &str) -> Self {
+        MockEvent::Response(Box::new(Response {
+            message: Some(Message::Session(SessionInit {
+                trace_id: trace_id.to_string(),
+                max_parallel_workers: 1,
+                ..Default::default()
+            })),
+        }))
+    }
+
+    pub fn progress() -> Self {
+        MockEvent::Response(Box::new(Response {
+            message: Some(Message::Progress(ModulesProgress::default())),
+        }))
+    }
+
+    // A block of output for `module`, its cursor is `cursor-<block_num>`.
+    pub fn data(module: &str, block_num: u64, output: Vec<u8>) -> Self {
+        MockEvent::Response(Box::new(Response {
+            message: Some(Message::BlockScopedData(BlockScopedData {
+                output: Some(MapModuleOutput {
+                    name: module.to_string(),
+                    map_output: Some(Any {
+                        type_url: format!("type.googleapis.com/mock.{}", module),
+                        value: output,
+                    }),
+                    debug_info: None,
+                }),
+                clock: Some(Clock {
+                    id: format!("block-{}", block_num),
+                    number: block_num,
+                    timestamp: None,
+                }),
+                cursor: mock_cursor(block_num),
+                final_block_height: block_num,
+                ..Default::default()
+            })),
+        }))
+    }
+
+    // The same data event with a final block height below its block, which can then be
+    // undone. `data` events are final as soon as they are sent.
+    pub fn with_final_block_height(mut self, final_block_height: u64) -> Self {
+        if let MockEvent::Response(response) = &mut self {
+            if let Some(Message::BlockScopedData(data)) = &mut response.message {
+                data.final_block_height = final_block_height;
+            }
+        }
+        self
+    }
+
+    // Undo everything after `last_valid_block`, whose cursor is `cursor-<last_valid_block>`.
+    pub fn undo(last_valid_block: u64) -> Self {
+        MockEvent::Response(Box::new(Response {
+            message: Some(Message::BlockUndoSignal(BlockUndoSignal {
+                last_valid_block: Some(BlockRef {
+                    id: format!("block-{}", last_valid_block),
+                    number: last_valid_block,
+                }),
+                last_valid_cursor: mock_cursor(last_valid_block),
+            })),
+        }))
+    }
+
+    pub fn fatal(module: &str, reason: &str) -> Self {
+        MockEvent::Response(Box::new(Response {
+            message: Some(Message::FatalError(ModuleError {
+                module: module.to_string(),
+                reason: reason.to_string(),
+                ..Default::default()
+            })),
+        }))
+    }
+}
+
+impl MockEvent {
+    // Reads `{"type": ...}` with the arguments of the constructor of that name: `session`
+    // (`trace_id`), `progress`, `data` (`module`, `block_num`, `0x` hex `output`), `undo`
+    // (`last_valid_block`), `fatal` (`module`, `reason`), `disconnect` and `delay` (`ms`).
+    pub fn from_json(value: &Value) -> Result<Self, Error> {
+        let string = |key: &str| value[key].as_str().unwrap_or_default().to_string();
+        let number = |key: &str| {
+            value[key]
+                .as_u64()
+                .ok_or_else(|| format_err!("mock event {} needs a numeric '{}'", value, key))
+        };
+
+        match value["type"].as_str().unwrap_or_default() {
+            "session" => Ok(MockEvent::session(&string("trace_id"))),
+            "progress" => Ok(MockEvent::progress()),
+            "data" => {
+                let output = match &value["output"] {
+                    Value::Null => Vec::new(),
+                    output => decode_data(output)
+                        .map_err(|_| format_err!("mock event output {} is not 0x hex", output))?,
+                };
+                Ok(MockEvent::data(
+                    &string("module"),
+                    number("block_num")?,
+                    output,
+                ))
+            }
+            "undo" => Ok(MockEvent::undo(number("last_valid_block")?)),
+            "fatal" => Ok(MockEvent::fatal(&string("module"), &string("reason"))),
+            "disconnect" => Ok(MockEvent::Disconnect),
+            "delay" => Ok(MockEvent::Delay(Duration::from_millis(number("ms")?))),
+            other => Err(format_err!("unknown mock event type '{}'", other)),
+        }
+    }
+}
+
+// Reads the sessions of `MockSubstreamsServer::start` from a JSON array of arrays of events.
+pub fn mock_sessions_from_json(value: &Value) -> Result<Vec<Vec<MockEvent>>, Error> {
+    let sessions = value
+        .as_array()
+        .ok_or_else(|| format_err!("mock sessions must be a JSON array"))?;
+
+    sessions
+        .iter()
+        .map(|session| {
+            session
+                .as_array()
+                .ok_or_else(|| format_err!("a mock session must be a JSON array of events"))?
+                .iter()
+                .map(MockEvent::from_json)
+                .collect()
+        })
+        .collect()
+}
+
+pub fn mock_cursor(block_num: u64) -> String {
+    format!("cursor-{}", block_num)
+}
+
+#[derive(Default)]
+struct MockState {
+    sessions: Mutex<VecDeque<Vec<MockEvent>>>,
+    requests: Mutex<Vec<Request>>,
+    info: Mutex<Option<InfoResponse>>,
+}
+
+// A local gRPC server implementing the `sf.substreams.rpc.v2.Stream` and `EndpointInfo`
+// services. Every `Blocks` call replays the next scripted session, so a test can script
+// what happens on the first connection, on the reconnection, and so on.
+pub struct MockSubstreamsServer {
+    server: MockGrpcServer,
+    state: Arc<MockState>,
+}
+
+impl MockSubstreamsServer {
+    pub async fn start(sessions: Vec<Vec<MockEvent>>) -> Result<Self, Error> {
+        let state = Arc::new(MockState {
+            sessions: Mutex::new(sessions.into()),
+            ..Default::default()
+        });
+
+        let blocks_state = state.clone();
+        let blocks = move |request: Request| {
+            blocks_state.requests.lock().unwrap().push(request);
+            let session = blocks_state.sessions.lock().unwrap().pop_front();
+            let session =
+                session.ok_or_else(|| Status::failed_precondition("no scripted session left"))?;
+
+            Ok(replay(session.into_iter().map(|event| match event {
+                MockEvent::Response(response) => ScriptStep::Message(*response),
+                MockEvent::Error(status) => ScriptStep::Error(status),
+                MockEvent::Disconnect => ScriptStep::Disconnect,
+                MockEvent::Delay(duration) => ScriptStep::Delay(duration),
+            })))
+        };
+
+        let info_state = state.clone();
+        let info = move |_: InfoRequest| {
+            let info = info_state.info.lock().unwrap().clone();
+            info.ok_or_else(|| Status::unimplemented("mock server has no endpoint info"))
+        };
+
+        let router = Server::builder()
+            .add_service(
+                MockService::<SubstreamsStreamName>::new().server_streaming("Blocks", blocks),
+            )
+            .add_service(MockService::<SubstreamsInfoName>::new().unary("Info", info));
+
+        Ok(MockSubstreamsServer {
+            server: MockGrpcServer::start(router).await?,
+            state,
+        })
+    }
+
+    pub fn with_info(self, info: InfoResponse) -> Self {
+        *self.state.info.lock().unwrap() = Some(info);
+        self
+    }
+
+    pub fn url(&self) -> String {
+        self.server.url()
+    }
+
+    // The error the server stopped with, if it failed while running.
+    pub fn failure(&self) -> Option<String> {
+        self.server.failure()
+    }
+
+    // Requests received so far, one per `Blocks` call.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state.requests.lock().unwrap().clone()
+    }
+}
+
+// One step of a scripted session replayed by `MockFirehoseServer`.
+#[derive(Clone, Debug)]
+pub enum MockFirehoseEvent {
+    Response(Box<FirehoseResponse>),
+    // Ends the session with this gRPC status.
+    Error(Status),
+    // Drops the session mid-stream, as a provider going away would.
+    Disconnect,
+}
+
+impl MockFirehoseEvent {
+    // Block `num` at `step`, its payload is `block-<num>` and its cursor `cursor-<num>`.
+    pub fn block(num: u64, step: ForkStep) -> Self {
+        MockFirehoseEvent::Response(Box::new(FirehoseResponse {
+            block: Some(mock_block(num)),
+            step: step as i32,
+            cursor: mock_cursor(num),
+            metadata: Some(BlockMetadata {
+                num,
+                id: format!("block-{}", num),
+                parent_num: num.saturating_sub(1),
+                parent_id: format!("block-{}", num.saturating_sub(1)),
+                ..Default::default()
+            }),
+        }))
+    }
+
+    // The same event without block metadata, as older Firehose versions send it.
+    pub fn without_metadata(self) -> Self {
+        match self {
+            MockFirehoseEvent::Response(mut response) => {
+                response.metadata = None;
+                MockFirehoseEvent::Response(response)
+            }
+            event => event,
+        }
+    }
+}
+
+// The payload of mock block `num`.
+pub fn mock_block(num: u64) -> Any {
+    Any {
+        type_url: "type.googleapis.com/mock.Block".to_string(),
+        value: format!("block-{}", num).into_bytes(),
+    }
+}
+
+#[derive(Default)]
+struct MockFirehoseState {
+    sessions: Mutex<VecDeque<Vec<MockFirehoseEvent>>>,
+    requests: Mutex<Vec<FirehoseRequest>>,
+    fetched: Mutex<Vec<SingleBlockRequest>>,
+    info: Mutex<Option<InfoResponse>>,
+}
+
+// A local gRPC server implementing the `sf.firehose.v2.Stream`, `Fetch` and `EndpointInfo`
+// services. `Blocks` calls replay scripted sessions as `MockSubstreamsServer` does, `Block`
+// calls answer `mock_block` for the requested number.
+pub struct MockFirehoseServer {
+    server: MockGrpcServer,
+    state: Arc<MockFirehoseState>,
+}
+
+impl MockFirehoseServer {
+    pub async fn start(sessions: Vec<Vec<MockFirehoseEvent>>) -> Result<Self, Error> {
+        let state = Arc::new(MockFirehoseState {
+            sessions: Mutex::new(sessions.into()),
+            ..Default::default()
+        });
+
+        let blocks_state = state.clone();
+        let blocks = move |request: FirehoseRequest| {
+            blocks_state.requests.lock().unwrap().push(request);
+            let session = blocks_state.sessions.lock().unwrap().pop_front();
+            let session =
+                session.ok_or_else(|| Status::failed_precondition("no scripted session left"))?;
+
+            Ok(replay(session.into_iter().map(|event| match event {
+                MockFirehoseEvent::Response(response) => ScriptStep::Message(*response),
+                MockFirehoseEvent::Error(status) => ScriptStep::Error(status),
+                MockFirehoseEvent::Disconnect => ScriptStep::Disconnect,
+            })))
+        };
+
+        let fetch_state = state.clone();
+        let fetch = move |request: SingleBlockRequest| {
+            fetch_state.fetched.lock().unwrap().push(request.clone());
+            let num = match request.reference {
+                Some(single_block_request::Reference::BlockNumber(block)) => block.num,
+                Some(single_block_request::Reference::BlockHashAndNumber(block)) => block.num,
+                _ => return Err(Status::invalid_argument("mock server fetches by number")),
+            };
+
+            Ok(SingleBlockResponse {
+                block: Some(mock_block(num)),
+                metadata: None,
+            })
+        };
+
+        let info_state = state.clone();
+        let info = move |_: InfoRequest| {
+            let info = info_state.info.lock().unwrap().clone();
+            info.ok_or_else(|| Status::unimplemented("mock server has no endpoint info"))
+        };
+
+        let router = Server::builder()
+            .add_service(
+                MockService::<FirehoseStreamName>::new().server_streaming("Blocks", blocks),
+            )
+            .add_service(MockService::<FirehoseFetchName>::new().unary("Block", fetch))
+            .add_service(MockService::<FirehoseInfoName>::new().unary("Info", info));
+
+        Ok(MockFirehoseServer {
+            server: MockGrpcServer::start(router).await?,
+            state,
+        })
+    }
+
+    pub fn with_info(self, info: InfoResponse) -> Self {
+        *self.state.info.lock().unwrap() = Some(info);
+        self
+    }
+
+    pub fn url(&self) -> String {
+        self.server.url()
+    }
+
+    // The error the server stopped with, if it failed while running.
+    pub fn failure(&self) -> Option<String> {
+        self.server.failure()
+    }
+
+    // Requests received so far, one per `Blocks` call.
+    pub fn requests(&self) -> Vec<FirehoseRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    // Requests received so far, one per `Block` call.
+    pub fn fetched(&self) -> Vec<SingleBlockRequest> {
+        self.state.fetched.lock().unwrap().clone()
+    }
+}
+
+// A local gRPC server, shut down when dropped.
+pub(crate) struct MockGrpcServer {
+    addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl MockGrpcServer {
+    pub(crate) async fn start(router: Router) -> Result<Self, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let failure = Arc::new(Mutex::new(None));
+
+        let server_failure = failure.clone();
+        tokio::spawn(async move {
+            let result = router
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                    let _ = shutdown_rx.await;
+                })
+                .await;
+
+            if let Err(e) = result {
+                *server_failure.lock().unwrap() = Some(format!("{:#}", e));
+            }
+        });
+
+        Ok(MockGrpcServer {
+            addr,
+            shutdown: Some(shutdown),
+            failure,
+        })
+    }
+
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    // The error the server stopped with, if it failed while running.
+    pub(crate) fn failure(&self) -> Option<String> {
+        self.failure.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockGrpcServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+enum ScriptStep<T> {
+    Message(T),
+    Error(Status),
+    Disconnect,
+    Delay(Duration),
+}
+
+fn replay<T: Send + 'static>(
+    steps: impl Iterator<Item = ScriptStep<T>> + Send + 'static,
+) -> BoxStream<T> {
+    Box::pin(stream! {
+        for step in steps {
+            match step {
+                ScriptStep::Message(message) => yield Ok(message),
+                ScriptStep::Error(status) => {
+                    yield Err(status);
+                    return;
+                }
+                ScriptStep::Disconnect => {
+                    yield Err(Status::unavailable("mock server disconnected"));
+                    return;
+                }
+                ScriptStep::Delay(duration) => sleep(duration).await,
+            }
+        }
+    })
+}
+
+// Names the gRPC service answered by a `MockService`, as `NamedService` does for generated
+// servers.
+pub(crate) trait MockServiceName: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+struct SubstreamsStreamName;
+impl MockServiceName for SubstreamsStreamName {
+    const NAME: &'static str = "sf.substreams.rpc.v2.Stream";
+}
+
+struct SubstreamsInfoName;
+impl MockServiceName for SubstreamsInfoName {
+    const NAME: &'static str = "sf.substreams.rpc.v2.EndpointInfo";
+}
+
+struct FirehoseStreamName;
+impl MockServiceName for FirehoseStreamName {
+    const NAME: &'static str = "sf.firehose.v2.Stream";
+}
+
+struct FirehoseFetchName;
+impl MockServiceName for FirehoseFetchName {
+    const NAME: &'static str = "sf.firehose.v2.Fetch";
+}
+
+struct FirehoseInfoName;
+impl MockServiceName for FirehoseInfoName {
+    const NAME: &'static str = "sf.firehose.v2.EndpointInfo";
+}
+
+type MockMethod = Arc<
+    dyn Fn(http::Request<BoxBody>) -> BoxFuture<http::Response<BoxBody>, Infallible> + Send + Sync,
+>;
+
+// A gRPC service whose methods are answered by closures, so mock servers need no generated
+// server code. Methods not given answer `Unimplemented`.
+pub(crate) struct MockService<N> {
+    methods: HashMap<&'static str, MockMethod>,
+    name: PhantomData<N>,
+}
+
+impl<N> Clone for MockService<N> {
+    fn clone(&self) -> Self {
+        MockService {
+            methods: self.methods.clone(),
+            name: PhantomData,
+        }
+    }
+}
+
+impl<N: MockServiceName> MockService<N> {
+    pub(crate) fn new() -> Self {
+        MockService {
+            methods: HashMap::new(),
+            name: PhantomData,
+        }
+    }
+
+    pub(crate) fn unary<Req, Resp>(
+        mut self,
+        method: &'static str,
+        handler: impl Fn(Req) -> Result<Resp, Status> + Send + Sync + 'static,
+    ) -> Self
+    where
+        Req: prost::Message + Default + Send + 'static,
+        Resp: prost::Message + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let method_handler: MockMethod = Arc::new(move |request| {
+            let handler = UnaryHandler(handler.clone(), PhantomData);
+            Box::pin(async move {
+                let mut grpc = Grpc::new(ProstCodec::<Resp, Req>::default())
+                    .apply_compression_config(compression_encodings(), compression_encodings());
+                Ok(grpc.unary(handler, request).await)
+            })
+        });
+        self.methods.insert(method, method_handler);
+        self
+    }
+
+    pub(crate) fn server_streaming<Req, Resp>(
+        mut self,
+        method: &'static str,
+        handler: impl Fn(Req) -> Result<BoxStream<Resp>, Status> + Send + Sync + 'static,
+    ) -> Self
+    where
+        Req: prost::Message + Default + Send + 'static,
+        Resp: prost::Message + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let method_handler: MockMethod = Arc::new(move |request| {
+            let handler = StreamingHandler(handler.clone(), PhantomData);
+            Box::pin(async move {
+                let mut grpc = Grpc::new(ProstCodec::<Resp, Req>::default())
+                    .apply_compression_config(compression_encodings(), compression_encodings());
+                Ok(grpc.server_streaming(handler, request).await)
+            })
+        });
+        self.methods.insert(method, method_handler);
+        self
+    }
+}
+
+impl<N: MockServiceName> NamedService for MockService<N> {
+    const NAME: &'static str = N::NAME;
+}
+
+impl<N: MockServiceName> Service<http::Request<BoxBody>> for MockService<N> {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<BoxBody>) -> Self::Future {
+        let path = req.uri().path().to_string();
+        let method = path.rsplit('/').next().unwrap_or_default();
+
+        match self.methods.get(method) {
+            Some(method) => method(req),
+            None => {
+                let status = Status::unimplemented(format!("mock server does not serve {}", path));
+                Box::pin(async move { Ok(status.into_http()) })
+            }
+        }
+    }
+}
+
+// Adapts the closure answering a unary method to the service tonic calls.
+struct UnaryHandler<F, Resp>(Arc<F>, PhantomData<fn() -> Resp>);
+
+impl<F, Req, Resp> UnaryService<Req> for UnaryHandler<F, Resp>
+where
+    F: Fn(Req) -> Result<Resp, Status>,
+    Resp: Send + 'static,
+{
+    type Response = Resp;
+    type Future = BoxFuture<tonic::Response<Resp>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let result = (self.0)(request.into_inner()).map(tonic::Response::new);
+        Box::pin(async move { result })
+    }
+}
+
+// Adapts the closure answering a server streaming method to the service tonic calls.
+struct StreamingHandler<F, Resp>(Arc<F>, PhantomData<fn() -> Resp>);
+
+impl<F, Req, Resp> ServerStreamingService<Req> for StreamingHandler<F, Resp>
+where
+    F: Fn(Req) -> Result<BoxStream<Resp>, Status>,
+    Resp: Send + 'static,
+{
+    type Response = Resp;
+    type ResponseStream = BoxStream<Resp>;
+    type Future = BoxFuture<tonic::Response<BoxStream<Resp>>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let result = (self.0)(request.into_inner()).map(tonic::Response::new);
+        Box::pin(async move { result })
+    }
+}
+
+fn compression_encodings() -> EnabledCompressionEncodings {
+    let mut encodings = EnabledCompressionEncodings::default();
+    encodings.enable(CompressionEncoding::Gzip);
+    encodings
+}
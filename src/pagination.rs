@@ -0,0 +1,395 @@
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+};
+
+use async_stream::try_stream;
+use futures03::Stream;
+use reqwest::Url;
+use serde_json::{json, Value};
+
+use crate::api::{send_api_request, ApiError, ApiRequest, ApiResponse};
+use crate::http_client::HttpClient;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pagination {
+    // Sends the page number in `param`, starting at `start`. Stops on an empty page or one
+    // with fewer than `size` items.
+    Page {
+        param: String,
+        start: u64,
+        size: Option<(String, u64)>,
+        items: Option<String>,
+    },
+    // Sends the number of items already received in `param`, and `limit` in `limit_param`.
+    // Stops on an empty page or one with fewer than `limit` items.
+    Offset {
+        param: String,
+        limit: Option<(String, u64)>,
+        items: Option<String>,
+    },
+    // Reads the next cursor at the JSON pointer `next` of each page and sends it in `param`.
+    // Stops when there is no cursor, or the same one comes back.
+    Cursor {
+        param: String,
+        next: String,
+    },
+    // Follows the `rel="next"` URL of the `Link` header. Stops when there is none, or it
+    // points to a page already received.
+    LinkHeader,
+}
+
+impl Pagination {
+    // `{"type": "page", "param": "page", "start": 1, "size_param": "per_page", "size": 100,
+    // "items": "/data"}`, `{"type": "offset", "param": "offset", "limit_param": "limit",
+    // "limit": 100, "items": "/results"}`, `{"type": "cursor", "param": "cursor", "next":
+    // "/meta/next_cursor"}` or `{"type": "link"}`. `items` is the JSON pointer of the page
+    // items, the whole body by default.
+    pub fn from_json(value: &Value) -> Result<Self, ApiError> {
+        let string = |key: &str, default: &str| value[key].as_str().unwrap_or(default).to_string();
+        let items = value["items"].as_str().map(str::to_string);
+        let sized = |param_key: &str, value_key: &str, default_param: &str| {
+            value[value_key]
+                .as_u64()
+                .map(|size| (string(param_key, default_param), size))
+        };
+
+        match value["type"].as_str() {
+            Some("page") => Ok(Pagination::Page {
+                param: string("param", "page"),
+                start: value["start"].as_u64().unwrap_or(1),
+                size: sized("size_param", "size", "per_page"),
+                items,
+            }),
+            Some("offset") => Ok(Pagination::Offset {
+                param: string("param", "offset"),
+                limit: sized("limit_param", "limit", "limit"),
+                items,
+            }),
+            Some("cursor") => match value["next"].as_str() {
+                Some(next) => Ok(Pagination::Cursor {
+                    param: string("param", "cursor"),
+                    next: next.to_string(),
+                }),
+                None => Err(ApiError::InvalidRequest(
+                    "cursor pagination requires the JSON pointer 'next'".to_string(),
+                )),
+            },
+            Some("link") => Ok(Pagination::LinkHeader),
+            other => Err(ApiError::InvalidRequest(format!(
+                "unknown pagination type {:?}, expected page, offset, cursor or link",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiPage {
+    // Starts at 0.
+    pub index: usize,
+    pub url: String,
+    pub response: ApiResponse,
+}
+
+impl ApiPage {
+    pub fn to_json(&self) -> Value {
+        let mut page = self.response.to_json();
+        page["page"] = json!(self.index);
+        page["url"] = json!(self.url);
+        page
+    }
+}
+
+// Sets `name` in the query, replacing any previous value in place.
+fn set_query(request: &mut ApiRequest, name: &str, value: String) {
+    match request.query.iter_mut().find(|(key, _)| key == name) {
+        Some((_, existing)) => *existing = value,
+        None => request.query.push((name.to_string(), value)),
+    }
+}
+
+fn parse_body(response: &ApiResponse) -> Result<Value, ApiError> {
+    serde_json::from_slice(&response.body)
+        .map_err(|e| ApiError::InvalidResponse(format!("page is not JSON: {}", e)))
+}
+
+fn count_items(response: &ApiResponse, items: &Option<String>) -> Result<u64, ApiError> {
+    let body = parse_body(response)?;
+    let items = match items {
+        Some(pointer) => body.pointer(pointer),
+        None => Some(&body),
+    };
+    match items {
+        Some(Value::Array(items)) => Ok(items.len() as u64),
+        Some(Value::Null) | None => Ok(0),
+        Some(_) => Err(ApiError::InvalidResponse(
+            "page items are not a JSON array".to_string(),
+        )),
+    }
+}
+
+// The `rel="next"` target of a `Link` header, resolved against the page URL.
+fn next_link(response: &ApiResponse, url: &str) -> Option<String> {
+    let header = response.header("link")?;
+    header.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        let is_next = params.split(';').any(|param| {
+            let param = param.trim().replace(' ', "");
+            param == "rel=\"next\"" || param == "rel=next"
+        });
+        if !is_next {
+            return None;
+        }
+
+        let target = target.trim().trim_start_matches('<').trim_end_matches('>');
+        Url::parse(url)
+            .and_then(|base| base.join(target))
+            .ok()
+            .map(String::from)
+    })
+}
+
+// Pages of a paginated API, requested one after the other as the stream is polled and
+// ending after `max_pages` pages when set. A page answered with an error status ends the
+// stream with `ApiError::Http`.
+pub struct ApiPageStream {
+    stream: Pin<Box<dyn Stream<Item = Result<ApiPage, ApiError>> + Send>>,
+}
+
+impl ApiPageStream {
+    pub fn new(
+        client: Arc<HttpClient>,
+        request: ApiRequest,
+        pagination: Pagination,
+        max_pages: Option<usize>,
+    ) -> Self {
+        ApiPageStream {
+            stream: Box::pin(stream_pages(client, request, pagination, max_pages)),
+        }
+    }
+}
+
+impl Stream for ApiPageStream {
+    type Item = Result<ApiPage, ApiError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+fn stream_pages(
+    client: Arc<HttpClient>,
+    mut request: ApiRequest,
+    pagination: Pagination,
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<ApiPage, ApiError>> {
+    try_stream! {
+        let mut received = 0;
+        let mut cursor: Option<String> = None;
+        let mut visited: HashSet<String> = HashSet::new();
+
+        match &pagination {
+            Pagination::Page { param, start, size, .. } => {
+                set_query(&mut request, param, start.to_string());
+                if let Some((size_param, size)) = size {
+                    set_query(&mut request, size_param, size.to_string());
+                }
+            }
+            Pagination::Offset { param, limit, .. } => {
+                set_query(&mut request, param, "0".to_string());
+                if let Some((limit_param, limit)) = limit {
+                    set_query(&mut request, limit_param, limit.to_string());
+                }
+            }
+            _ => {}
+        }
+
+        for index in 0.. {
+            if max_pages.is_some_and(|max_pages| index >= max_pages) {
+                break;
+            }
+
+            let url = request.full_url()?.to_string();
+            let response = send_api_request(&client, &request).await?;
+            if !response.is_success() {
+                Err(ApiError::Http {
+                    status: response.status,
+                    body: response.text(),
+                })?;
+            }
+
+            // Works out the next request before handing the page over, None when this was
+            // the last page.
+            let next = match &pagination {
+                Pagination::Page { param, start, size, items } => {
+                    let count = count_items(&response, items)?;
+                    let page_size = size.as_ref().map(|(_, size)| *size).unwrap_or(1);
+                    (count >= page_size && count > 0).then(|| {
+                        set_query(&mut request, param, (start + index as u64 + 1).to_string());
+                    })
+                }
+                Pagination::Offset { param, limit, items } => {
+                    let count = count_items(&response, items)?;
+                    received += count;
+                    let page_size = limit.as_ref().map(|(_, limit)| *limit).unwrap_or(1);
+                    (count >= page_size && count > 0).then(|| {
+                        set_query(&mut request, param, received.to_string());
+                    })
+                }
+                Pagination::Cursor { param, next } => {
+                    let body = parse_body(&response)?;
+                    let next_cursor = body.pointer(next).and_then(|cursor| match cursor {
+                        Value::String(cursor) if !cursor.is_empty() => Some(cursor.clone()),
+                        Value::Number(cursor) => Some(cursor.to_string()),
+                        _ => None,
+                    });
+                    match next_cursor {
+                        Some(next_cursor) if cursor.as_ref() != Some(&next_cursor) => {
+                            set_query(&mut request, param, next_cursor.clone());
+                            cursor = Some(next_cursor);
+                            Some(())
+                        }
+                        _ => None,
+                    }
+                }
+                Pagination::LinkHeader => {
+                    visited.insert(url.clone());
+                    next_link(&response, &request.url)
+                        .filter(|next| !visited.contains(next))
+                        .map(|next| {
+                            // The next URL carries the whole query already.
+                            request.url = next;
+                            request.query.clear();
+                        })
+                }
+            };
+
+            yield ApiPage { index, url, response };
+
+            if next.is_none() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures03::StreamExt;
+
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    // Answers every request with `respond(base URL, request target)` as (extra header lines,
+    // body).
+    async fn serve(respond: fn(&str, &str) -> (String, String)) -> String {
+        let server = MockHttpServer::start(move |request, _| {
+            let base = format!("http://{}", request.header("host").unwrap());
+            let (headers, body) = respond(&base, request.target());
+            let mut headers: Vec<&str> = headers.lines().collect();
+            headers.push("Content-Type: application/json");
+            MockHttpResponse::new("200 OK", &headers, body)
+        })
+        .await;
+        server.url()
+    }
+
+    async fn collect(url: String, pagination: Value, max_pages: Option<usize>) -> Vec<ApiPage> {
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        let stream = ApiPageStream::new(
+            Arc::new(client),
+            ApiRequest::get(&url),
+            Pagination::from_json(&pagination).unwrap(),
+            max_pages,
+        );
+        stream.map(|page| page.unwrap()).collect().await
+    }
+
+    #[tokio::test]
+    async fn paginates_by_page_cursor_and_link() {
+        // Three pages of two, three, then one item.
+        let url = serve(|_, target| {
+            let body = match target {
+                "/items?page=1&per_page=2" => "{\"data\": [1, 2]}",
+                "/items?page=2&per_page=2" => "{\"data\": [3, 4]}",
+                _ => "{\"data\": [5]}",
+            };
+            (String::new(), body.to_string())
+        })
+        .await;
+        let pages = collect(
+            format!("{}/items", url),
+            json!({"type": "page", "size": 2, "items": "/data"}),
+            None,
+        )
+        .await;
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[2].response.text(), "{\"data\": [5]}");
+
+        let url = serve(|_, target| {
+            let body = match target {
+                "/events" => "{\"next\": \"abc\"}",
+                "/events?cursor=abc" => "{\"next\": \"def\"}",
+                _ => "{\"next\": null}",
+            };
+            (String::new(), body.to_string())
+        })
+        .await;
+        let pages = collect(
+            format!("{}/events", url),
+            json!({"type": "cursor", "next": "/next"}),
+            None,
+        )
+        .await;
+        let urls: Vec<String> = pages.iter().map(|p| p.url.replace(&url, "")).collect();
+        assert_eq!(
+            urls,
+            vec!["/events", "/events?cursor=abc", "/events?cursor=def"]
+        );
+
+        let url = serve(|base, target| match target {
+            "/blocks" => (
+                format!(
+                    "Link: <{}/blocks?after=2>; rel=\"next\", </blocks?after=9>; rel=\"last\"\r\n",
+                    base
+                ),
+                "[1, 2]".to_string(),
+            ),
+            _ => (
+                "Link: </blocks?after=4>; rel=\"next\"\r\n".to_string(),
+                "[3, 4]".to_string(),
+            ),
+        })
+        .await;
+        let pages = collect(format!("{}/blocks", url), json!({"type": "link"}), Some(2)).await;
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[1].url, format!("{}/blocks?after=2", url));
+
+        // The last page links back to itself.
+        let pages = collect(format!("{}/blocks", url), json!({"type": "link"}), None).await;
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[2].url, format!("{}/blocks?after=4", url));
+    }
+
+    #[test]
+    fn parses_pagination() {
+        assert_eq!(
+            Pagination::from_json(&json!({"type": "offset", "limit": 50})).unwrap(),
+            Pagination::Offset {
+                param: "offset".to_string(),
+                limit: Some(("limit".to_string(), 50)),
+                items: None,
+            }
+        );
+        assert!(Pagination::from_json(&json!({"type": "cursor"})).is_err());
+        assert!(Pagination::from_json(&json!({"type": "scroll"})).is_err());
+    }
+}
@@ -11,6 +11,13 @@ pub mod sf {
     pub mod substreams {
         include!("sf.substreams.rs");
         // @@protoc_insertion_point(sf.substreams)
+        pub mod entity {
+            // @@protoc_insertion_point(attribute:sf.substreams.entity.v1)
+            pub mod v1 {
+                include!("sf.substreams.entity.v1.rs");
+                // @@protoc_insertion_point(sf.substreams.entity.v1)
+            }
+        }
         pub mod index {
             // @@protoc_insertion_point(attribute:sf.substreams.index.v1)
             pub mod v1 {
@@ -0,0 +1,211 @@
+use std::{
+    error::Error as _,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    pin::Pin,
+};
+
+use anyhow::{Context, Error};
+use futures03::{stream, Stream, StreamExt};
+use prost::Message;
+
+use crate::pb::sf::substreams::rpc::v2::Response;
+
+// Endpoint URLs starting with this scheme replay a recorded session from the file that
+// follows instead of connecting to a provider, e.g. `replay:///tmp/session.bin`.
+pub const REPLAY_SCHEME: &str = "replay://";
+
+// When set, every `Response` received from a live provider is recorded to this file, which
+// is truncated when the session starts.
+pub const RECORD_PATH_ENV: &str = "SUBSTREAMS_RECORD_PATH";
+
+pub type ResponseStream = Pin<Box<dyn Stream<Item = Result<Response, tonic::Status>> + Send>>;
+
+// A received response could not be appended to the recording file. `SubstreamsStream` does
+// not retry it, reconnecting would fail to record the same way.
+#[derive(Debug, thiserror::Error)]
+#[error("Unable to record substreams response: {0}")]
+pub struct RecordingError(#[source] io::Error);
+
+pub fn is_recording_error(status: &tonic::Status) -> bool {
+    status
+        .source()
+        .is_some_and(|source| source.is::<RecordingError>())
+}
+
+// Where the responses of a `SubstreamsEndpoint` come from.
+#[derive(Clone, Debug, Default)]
+pub enum SessionMode {
+    #[default]
+    Live,
+    // Live session whose responses are appended to a recording file.
+    Record(PathBuf),
+    // Responses are read back from a recording file, no network is involved.
+    Replay(PathBuf),
+}
+
+// Creates `path` empty, dropping what an earlier session recorded there.
+pub fn start_recording(path: &Path) -> Result<(), Error> {
+    File::create(path).context(format!("create recording file '{}'", path.display()))?;
+    Ok(())
+}
+
+// Appends each successfully received response to `path`, created by `start_recording`, as a
+// length-delimited protobuf message. Appending keeps the responses of every reconnection of
+// the session. Errors are forwarded untouched and not recorded, a replay simply ends where
+// the live session broke. A response that cannot be written is replaced by a
+// `RecordingError`, so a recording never silently misses part of the session.
+pub fn record_responses(responses: ResponseStream, path: &Path) -> Result<ResponseStream, Error> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .context(format!("open recording file '{}'", path.display()))?;
+
+    Ok(Box::pin(responses.map(move |response| {
+        let response = response?;
+        file.write_all(&response.encode_length_delimited_to_vec())
+            .map_err(|e| tonic::Status::from_error(Box::new(RecordingError(e))))?;
+        Ok(response)
+    })))
+}
+
+pub fn replay_responses(path: &Path) -> Result<ResponseStream, Error> {
+    let responses = read_recording(path)?;
+
+    Ok(Box::pin(stream::iter(responses.into_iter().map(Ok))))
+}
+
+pub fn read_recording(path: &Path) -> Result<Vec<Response>, Error> {
+    let content =
+        std::fs::read(path).context(format!("read recording file '{}'", path.display()))?;
+
+    let mut buf = content.as_slice();
+    let mut responses = Vec::new();
+    while !buf.is_empty() {
+        responses.push(
+            Response::decode_length_delimited(&mut buf)
+                .context(format!("decode recording file '{}'", path.display()))?,
+        );
+    }
+
+    Ok(responses)
+}
+
+pub fn write_recording(path: &Path, responses: &[Response]) -> Result<(), Error> {
+    let mut file =
+        File::create(path).context(format!("create recording file '{}'", path.display()))?;
+
+    for response in responses {
+        file.write_all(&response.encode_length_delimited_to_vec())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mock_server::{MockEvent, MockSubstreamsServer};
+    use crate::substreams::SubstreamsEndpoint;
+    use crate::substreams_stream::{BlockResponse, SubstreamsStream};
+
+    const MODULE: &str = "map_events";
+
+    async fn outputs(endpoint: SubstreamsEndpoint) -> Vec<Vec<u8>> {
+        SubstreamsStream::new(Arc::new(endpoint), None, None, MODULE.to_string(), 1, 0)
+            .filter_map(|response| async move {
+                match response.unwrap() {
+                    BlockResponse::New(data) => Some(data.output?.map_output?.value),
+                    BlockResponse::Undo(_) => None,
+                }
+            })
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_session_without_network() {
+        let path = std::env::temp_dir().join(format!("unifiedstreams-{}.rec", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let server = MockSubstreamsServer::start(vec![
+            vec![
+                MockEvent::session("trace"),
+                MockEvent::data(MODULE, 1, vec![1]),
+                MockEvent::Disconnect,
+            ],
+            vec![MockEvent::data(MODULE, 2, vec![2])],
+        ])
+        .await
+        .unwrap();
+
+        let live = SubstreamsEndpoint::new(server.url(), None)
+            .await
+            .unwrap()
+            .with_recording(path.clone())
+            .unwrap();
+        assert_eq!(outputs(live).await, vec![vec![1], vec![2]]);
+        drop(server);
+
+        assert_eq!(read_recording(&path).unwrap().len(), 3);
+
+        let replay = SubstreamsEndpoint::new(format!("{}{}", REPLAY_SCHEME, path.display()), None)
+            .await
+            .unwrap();
+        assert!(replay.info().await.is_err());
+        assert_eq!(outputs(replay).await, vec![vec![1], vec![2]]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recording_replaces_an_earlier_session() {
+        let path = std::env::temp_dir().join(format!(
+            "unifiedstreams-{}-rerecord.rec",
+            std::process::id()
+        ));
+
+        for output in [1, 2] {
+            let server =
+                MockSubstreamsServer::start(vec![vec![MockEvent::data(MODULE, 1, vec![output])]])
+                    .await
+                    .unwrap();
+            let live = SubstreamsEndpoint::new(server.url(), None)
+                .await
+                .unwrap()
+                .with_recording(path.clone())
+                .unwrap();
+            assert_eq!(outputs(live).await, vec![vec![output]]);
+        }
+
+        let replay = SubstreamsEndpoint::replay(path.clone());
+        assert_eq!(outputs(replay).await, vec![vec![2]]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recording_failures_end_the_stream() {
+        let server = MockSubstreamsServer::start(vec![vec![MockEvent::data(MODULE, 1, vec![1])]])
+            .await
+            .unwrap();
+        let live = SubstreamsEndpoint::new(server.url(), None)
+            .await
+            .unwrap()
+            .with_recording(PathBuf::from("/dev/full"))
+            .unwrap();
+
+        let mut stream =
+            SubstreamsStream::new(Arc::new(live), None, None, MODULE.to_string(), 1, 0);
+        let error = stream.next().await.unwrap().err().unwrap();
+
+        assert!(error
+            .to_string()
+            .contains("Unable to record substreams response"));
+        assert!(stream.next().await.is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,937 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Error};
+use lazy_static::lazy_static;
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use crate::api::{send_api_request, ApiError, ApiRequest, ApiResponse};
+use crate::http_client::HttpClient;
+use crate::json_rpc::{send_request, JsonRpcRequest, RpcError};
+
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+// Block tags whose meaning moves with the chain head, a request using one is never cached.
+const MOVING_BLOCK_TAGS: [&str; 4] = ["latest", "pending", "safe", "finalized"];
+
+lazy_static! {
+    // None until a cache is configured, calls then go straight to the network.
+    static ref SHARED_CACHE: RwLock<Option<Arc<ResponseCache>>> = RwLock::new(None);
+}
+
+pub fn shared_cache() -> Option<Arc<ResponseCache>> {
+    SHARED_CACHE.read().unwrap().clone()
+}
+
+// Installs the cache used by `rpc_call` and `api_call`, None disables caching.
+pub fn configure_shared_cache(config: Option<CacheConfig>) -> Result<(), Error> {
+    let cache = config.map(ResponseCache::new).transpose()?;
+    *SHARED_CACHE.write().unwrap() = cache.map(Arc::new);
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CacheBackendConfig {
+    // Least recently used entries are evicted past `max_entries`.
+    Memory { max_entries: usize },
+    // One metadata and one body file per entry, kept across processes. The oldest written
+    // entries are evicted past `max_entries`.
+    Disk { path: PathBuf, max_entries: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheConfig {
+    pub backend: CacheBackendConfig,
+    // Lifetime of cached RPC results. None keeps results addressed by block hash until
+    // evicted and does not cache the others, see `RpcCaching`.
+    pub rpc_ttl: Option<Duration>,
+    // Lifetime of HTTP responses without `Cache-Control: max-age`. None only caches those
+    // with a `max-age`, or with an `ETag`/`Last-Modified` to revalidate against.
+    pub http_ttl: Option<Duration>,
+}
+
+impl CacheConfig {
+    // `{"backend": "memory", "max_entries": 10000, "rpc_ttl_ms", "http_ttl_ms"}` or
+    // `{"backend": "disk", "path": "/var/cache/unifiedstreams", "max_entries": 10000,
+    // "rpc_ttl_ms", "http_ttl_ms"}`.
+    pub fn from_json(input: &str) -> Result<Self, Error> {
+        let value: Value = serde_json::from_str(input).context("Invalid JSON for cache config")?;
+
+        let max_entries = value["max_entries"]
+            .as_u64()
+            .map(|n| n as usize)
+            .unwrap_or(DEFAULT_MAX_ENTRIES);
+        let backend = match value["backend"].as_str().unwrap_or("memory") {
+            "memory" => CacheBackendConfig::Memory { max_entries },
+            "disk" => CacheBackendConfig::Disk {
+                path: value["path"]
+                    .as_str()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| anyhow!("the disk cache requires a 'path'"))?,
+                max_entries,
+            },
+            x => return Err(anyhow!("unknown cache backend '{}'", x)),
+        };
+
+        let millis = |name: &str| match &value[name] {
+            Value::Null => Ok(None),
+            v => v
+                .as_u64()
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .ok_or_else(|| anyhow!("'{}' must be a number of milliseconds", name)),
+        };
+
+        Ok(CacheConfig {
+            backend,
+            rpc_ttl: millis("rpc_ttl_ms")?,
+            http_ttl: millis("http_ttl_ms")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheEntry {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // None never expires. An expired entry with a validator is revalidated, not dropped.
+    pub expires_at: Option<SystemTime>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
+
+    fn can_revalidate(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    fn to_response(&self) -> ApiResponse {
+        ApiResponse {
+            status: self.status,
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// Entries ordered by last use, the lowest tick being evicted first.
+struct MemoryCache {
+    max_entries: usize,
+    entries: HashMap<String, (CacheEntry, u64)>,
+    by_use: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl MemoryCache {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.get_mut(key) {
+            self.by_use.remove(used);
+            *used = self.tick;
+            self.by_use.insert(self.tick, key.to_string());
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<CacheEntry> {
+        self.touch(key);
+        self.entries.get(key).map(|(entry, _)| entry.clone())
+    }
+
+    fn put(&mut self, key: &str, entry: CacheEntry) {
+        self.remove(key);
+        while self.entries.len() >= self.max_entries.max(1) {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.by_use.insert(self.tick, key.to_string());
+        self.entries.insert(key.to_string(), (entry, self.tick));
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.by_use.remove(&used);
+        }
+    }
+}
+
+// Files are named after the SHA-256 digest of the key, which may hold credentials and is
+// never written. The whole digest is stored in the metadata to tell collisions apart.
+// `entries` counts the metadata files, recounted whenever entries are evicted as other
+// processes may share the directory.
+struct DiskCache {
+    path: PathBuf,
+    max_entries: usize,
+    entries: usize,
+}
+
+fn key_digest(key: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+    crate::eth::encode_data(digest.as_ref())[2..].to_string()
+}
+
+// Written next to its destination then renamed, so readers never see half a file. Every
+// write gets its own temporary file, concurrent writers never share one.
+fn write_atomic(path: &Path, content: &[u8]) -> Result<(), Error> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = path.with_file_name(name);
+    fs::write(&tmp, content).context(format!("write '{}'", tmp.display()))?;
+    fs::rename(&tmp, path).context(format!("rename '{}'", tmp.display()))
+}
+
+impl DiskCache {
+    fn open(path: &Path, max_entries: usize) -> Result<Self, Error> {
+        fs::create_dir_all(path).context(format!("create cache directory '{}'", path.display()))?;
+        let mut cache = DiskCache {
+            path: path.to_path_buf(),
+            max_entries,
+            entries: 0,
+        };
+        cache.entries = cache.meta_files()?.len();
+        Ok(cache)
+    }
+
+    fn files(&self, digest: &str) -> (PathBuf, PathBuf) {
+        let name = &digest[..16];
+        (
+            self.path.join(format!("{}.json", name)),
+            self.path.join(format!("{}.body", name)),
+        )
+    }
+
+    // The metadata files, the oldest written first.
+    fn meta_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.path).context(format!("read '{}'", self.path.display()))? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("json") {
+                let written = entry.metadata()?.modified().unwrap_or(UNIX_EPOCH);
+                files.push((written, path));
+            }
+        }
+        files.sort();
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn evict(&mut self) -> Result<(), Error> {
+        let files = self.meta_files()?;
+        let excess = files.len().saturating_sub(self.max_entries.max(1));
+        for meta_path in &files[..excess] {
+            let _ = fs::remove_file(meta_path);
+            let _ = fs::remove_file(meta_path.with_extension("body"));
+        }
+        self.entries = files.len() - excess;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        let digest = key_digest(key);
+        let (meta_path, body_path) = self.files(&digest);
+        let meta: Value = serde_json::from_slice(&fs::read(meta_path).ok()?).ok()?;
+        if meta["key_digest"].as_str() != Some(digest.as_str()) {
+            return None;
+        }
+
+        Some(CacheEntry {
+            status: meta["status"].as_u64()? as u16,
+            headers: meta["headers"]
+                .as_array()?
+                .iter()
+                .filter_map(|header| {
+                    Some((
+                        header[0].as_str()?.to_string(),
+                        header[1].as_str()?.to_string(),
+                    ))
+                })
+                .collect(),
+            body: fs::read(body_path).ok()?,
+            expires_at: meta["expires_at_ms"]
+                .as_u64()
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+            etag: meta["etag"].as_str().map(str::to_string),
+            last_modified: meta["last_modified"].as_str().map(str::to_string),
+        })
+    }
+
+    fn put(&mut self, key: &str, entry: &CacheEntry) -> Result<(), Error> {
+        fs::create_dir_all(&self.path)
+            .context(format!("create cache directory '{}'", self.path.display()))?;
+
+        let digest = key_digest(key);
+        let (meta_path, body_path) = self.files(&digest);
+        let is_new = !meta_path.exists();
+        let meta = json!({
+            "key_digest": digest,
+            "status": entry.status,
+            "headers": entry.headers.iter().map(|(k, v)| json!([k, v])).collect::<Vec<_>>(),
+            "expires_at_ms": entry.expires_at.map(unix_millis),
+            "etag": entry.etag,
+            "last_modified": entry.last_modified,
+        });
+
+        // The body goes first, metadata without its body is never read.
+        write_atomic(&body_path, &entry.body)?;
+        write_atomic(&meta_path, meta.to_string().as_bytes())?;
+
+        if is_new {
+            self.entries += 1;
+        }
+        if self.entries > self.max_entries.max(1) {
+            self.evict()?;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) {
+        let (meta_path, body_path) = self.files(&key_digest(key));
+        if fs::remove_file(meta_path).is_ok() {
+            self.entries = self.entries.saturating_sub(1);
+        }
+        let _ = fs::remove_file(body_path);
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.entries = 0;
+        let Ok(entries) = fs::read_dir(&self.path) else {
+            return Ok(());
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("json" | "body" | "tmp")
+            ) {
+                fs::remove_file(&path).context(format!("remove '{}'", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+enum CacheBackend {
+    Memory(MemoryCache),
+    Disk(DiskCache),
+}
+
+pub struct ResponseCache {
+    backend: Mutex<CacheBackend>,
+    config: CacheConfig,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Result<Self, Error> {
+        let backend = match &config.backend {
+            CacheBackendConfig::Memory { max_entries } => CacheBackend::Memory(MemoryCache {
+                max_entries: *max_entries,
+                entries: HashMap::new(),
+                by_use: BTreeMap::new(),
+                tick: 0,
+            }),
+            CacheBackendConfig::Disk { path, max_entries } => {
+                CacheBackend::Disk(DiskCache::open(path, *max_entries)?)
+            }
+        };
+
+        Ok(ResponseCache {
+            backend: Mutex::new(backend),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    // Returns the entry even when expired, see `CacheEntry::is_fresh`.
+    pub fn get(&self, key: &str) -> Option<CacheEntry> {
+        match &mut *self.backend.lock().unwrap() {
+            CacheBackend::Memory(memory) => memory.get(key),
+            CacheBackend::Disk(disk) => disk.get(key),
+        }
+    }
+
+    pub fn put(&self, key: &str, entry: CacheEntry) {
+        match &mut *self.backend.lock().unwrap() {
+            CacheBackend::Memory(memory) => memory.put(key, entry),
+            CacheBackend::Disk(disk) => {
+                // A cache that cannot be written only costs a network call.
+                let _ = disk.put(key, &entry);
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        match &mut *self.backend.lock().unwrap() {
+            CacheBackend::Memory(memory) => memory.remove(key),
+            CacheBackend::Disk(disk) => disk.remove(key),
+        }
+    }
+
+    pub fn clear(&self) -> Result<(), Error> {
+        match &mut *self.backend.lock().unwrap() {
+            CacheBackend::Memory(memory) => {
+                memory.entries.clear();
+                memory.by_use.clear();
+                Ok(())
+            }
+            CacheBackend::Disk(disk) => disk.clear(),
+        }
+    }
+}
+
+fn references_moving_block(params: &Value) -> bool {
+    match params {
+        Value::String(s) => MOVING_BLOCK_TAGS.contains(&s.as_str()),
+        Value::Array(values) => values.iter().any(references_moving_block),
+        Value::Object(map) => map.values().any(references_moving_block),
+        _ => false,
+    }
+}
+
+// A block hash, or an EIP-1898 `{"blockHash": ...}` block parameter.
+fn is_block_hash(param: &Value) -> bool {
+    match param {
+        Value::String(s) => s.len() == 66 && s.starts_with("0x"),
+        Value::Object(map) => map.get("blockHash").is_some_and(|hash| !hash.is_null()),
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RpcCaching {
+    Never,
+    // Addressed by block hash, the answer can never change.
+    Permanent,
+    // Addressed by block number or transaction hash, a reorg can change the answer. Only
+    // cached with an `rpc_ttl`.
+    Expiring,
+}
+
+// How the answer to `request` may be cached. Only reads are cached, and only when they
+// name their block: those taking an optional block parameter must pass it, and
+// `eth_getLogs` needs a `blockHash` or both ends of its range. Moving tags such as
+// `latest` or `pending` anywhere in the parameters rule caching out.
+pub fn rpc_caching(request: &JsonRpcRequest) -> RpcCaching {
+    let params = &request.params;
+    if references_moving_block(params) {
+        return RpcCaching::Never;
+    }
+
+    let block = |index: usize| match params.get(index) {
+        None | Some(Value::Null) => RpcCaching::Never,
+        Some(param) if is_block_hash(param) => RpcCaching::Permanent,
+        Some(_) => RpcCaching::Expiring,
+    };
+    match request.method.as_str() {
+        "eth_chainId" | "net_version" => RpcCaching::Permanent,
+        "eth_getBlockByHash"
+        | "eth_getBlockTransactionCountByHash"
+        | "eth_getTransactionByBlockHashAndIndex"
+        | "eth_getUncleByBlockHashAndIndex"
+        | "debug_traceBlockByHash"
+        | "eth_getBlockByNumber"
+        | "eth_getBlockReceipts"
+        | "eth_getBlockTransactionCountByNumber"
+        | "eth_getTransactionByBlockNumberAndIndex"
+        | "eth_getUncleByBlockNumberAndIndex"
+        | "debug_traceBlockByNumber"
+        | "trace_block" => block(0),
+        // A transaction hash, the block it is included in can still be reorged.
+        "eth_getTransactionByHash"
+        | "eth_getTransactionReceipt"
+        | "debug_traceTransaction"
+        | "trace_transaction"
+        | "trace_replayTransaction" => match params.get(0) {
+            None | Some(Value::Null) => RpcCaching::Never,
+            Some(_) => RpcCaching::Expiring,
+        },
+        "eth_getBalance" | "eth_getCode" | "eth_getTransactionCount" | "eth_call" => block(1),
+        "eth_getStorageAt" => block(2),
+        "eth_getLogs" => {
+            let filter = &params[0];
+            if !filter["blockHash"].is_null() {
+                RpcCaching::Permanent
+            } else if !filter["fromBlock"].is_null() && !filter["toBlock"].is_null() {
+                RpcCaching::Expiring
+            } else {
+                RpcCaching::Never
+            }
+        }
+        _ => RpcCaching::Never,
+    }
+}
+
+fn rpc_key(endpoint: &str, request: &JsonRpcRequest) -> String {
+    format!("rpc {} {} {}", endpoint, request.method, request.params)
+}
+
+// `send_request` answered from `cache` when possible. Results are cached only when
+// `rpc_caching` allows it and they are not null, e.g. a receipt not yet mined.
+pub async fn cached_rpc_request(
+    client: &HttpClient,
+    cache: Option<&ResponseCache>,
+    endpoint: &str,
+    request: &JsonRpcRequest,
+    id: u64,
+) -> Result<Value, RpcError> {
+    let cache = cache.filter(|cache| match rpc_caching(request) {
+        RpcCaching::Never => false,
+        RpcCaching::Permanent => true,
+        RpcCaching::Expiring => cache.config.rpc_ttl.is_some(),
+    });
+    let Some(cache) = cache else {
+        return send_request(client, endpoint, request, id).await;
+    };
+
+    let key = rpc_key(endpoint, request);
+    let now = SystemTime::now();
+    if let Some(entry) = cache.get(&key) {
+        if entry.is_fresh(now) {
+            if let Ok(value) = serde_json::from_slice(&entry.body) {
+                return Ok(value);
+            }
+        }
+        cache.remove(&key);
+    }
+
+    let result = send_request(client, endpoint, request, id).await?;
+    if !result.is_null() {
+        cache.put(
+            &key,
+            CacheEntry {
+                status: 200,
+                headers: Vec::new(),
+                body: result.to_string().into_bytes(),
+                expires_at: cache.config.rpc_ttl.map(|ttl| now + ttl),
+                etag: None,
+                last_modified: None,
+            },
+        );
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+fn cache_control(response: &ApiResponse) -> CacheControl {
+    let mut control = CacheControl::default();
+    let Some(header) = response.header("cache-control") else {
+        return control;
+    };
+
+    for directive in header.split(',') {
+        let directive = directive.trim().to_lowercase();
+        match directive.split_once('=') {
+            Some(("max-age", age)) => control.max_age = age.trim_matches('"').parse().ok(),
+            Some(_) => {}
+            None if directive == "no-store" => control.no_store = true,
+            None if directive == "no-cache" => control.no_cache = true,
+            None => {}
+        }
+    }
+    control
+}
+
+fn api_key(request: &ApiRequest) -> Option<String> {
+    let url = request.full_url().ok()?;
+    let mut headers: Vec<String> = request
+        .headers
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key.to_lowercase(), value))
+        .collect();
+    headers.sort();
+    Some(format!(
+        "{} {}\n{}",
+        request.method,
+        url,
+        headers.join("\n")
+    ))
+}
+
+// The entry to store for a 200 response, None when it must not be cached.
+fn api_entry(
+    response: &ApiResponse,
+    http_ttl: Option<Duration>,
+    now: SystemTime,
+) -> Option<CacheEntry> {
+    let control = cache_control(response);
+    if response.status != 200 || control.no_store || response.header("vary") == Some("*") {
+        return None;
+    }
+
+    let etag = response.header("etag").map(str::to_string);
+    let last_modified = response.header("last-modified").map(str::to_string);
+    let can_revalidate = etag.is_some() || last_modified.is_some();
+
+    let expires_at = match (control.no_cache, control.max_age, http_ttl) {
+        (true, _, _) => now,
+        (false, Some(max_age), _) => now + Duration::from_secs(max_age),
+        (false, None, Some(ttl)) => now + ttl,
+        (false, None, None) => now,
+    };
+    // Already stale entries are only worth keeping to revalidate.
+    if expires_at <= now && !can_revalidate {
+        return None;
+    }
+
+    Some(CacheEntry {
+        status: response.status,
+        headers: response.headers.clone(),
+        body: response.body.clone(),
+        expires_at: Some(expires_at),
+        etag,
+        last_modified,
+    })
+}
+
+// `send_api_request` for GET requests answered from `cache` while fresh, honoring
+// `Cache-Control` (`max-age`, `no-cache`, `no-store`). Stale entries with an `ETag` or
+// `Last-Modified` are revalidated, a 304 serving the cached body.
+pub async fn cached_api_request(
+    client: &HttpClient,
+    cache: Option<&ResponseCache>,
+    request: &ApiRequest,
+) -> Result<ApiResponse, ApiError> {
+    let key = api_key(request).filter(|_| request.method == Method::GET);
+    let (Some(cache), Some(key)) = (cache, key) else {
+        return send_api_request(client, request).await;
+    };
+
+    let now = SystemTime::now();
+    let cached = cache.get(&key);
+    let mut request = request.clone();
+    match &cached {
+        Some(entry) if entry.is_fresh(now) => return Ok(entry.to_response()),
+        Some(entry) if entry.can_revalidate() => {
+            if let Some(etag) = &entry.etag {
+                request
+                    .headers
+                    .push(("If-None-Match".to_string(), etag.clone()));
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request
+                    .headers
+                    .push(("If-Modified-Since".to_string(), last_modified.clone()));
+            }
+        }
+        Some(_) => cache.remove(&key),
+        None => {}
+    }
+
+    let response = send_api_request(client, &request).await?;
+    if response.status == 304 {
+        if let Some(entry) = cached {
+            // The 304 carries the new freshness, the body stays the cached one.
+            let mut refreshed = entry.to_response();
+            for (name, value) in &response.headers {
+                refreshed
+                    .headers
+                    .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+                refreshed.headers.push((name.clone(), value.clone()));
+            }
+            match api_entry(&refreshed, cache.config.http_ttl, now) {
+                Some(updated) => cache.put(&key, updated),
+                None => cache.remove(&key),
+            }
+            return Ok(refreshed);
+        }
+    }
+
+    match api_entry(&response, cache.config.http_ttl, now) {
+        Some(entry) => cache.put(&key, entry),
+        None => cache.remove(&key),
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::mock_http_server::{MockHttpResponse, MockHttpServer};
+
+    fn memory_cache(max_entries: usize) -> ResponseCache {
+        ResponseCache::new(CacheConfig {
+            backend: CacheBackendConfig::Memory { max_entries },
+            rpc_ttl: None,
+            http_ttl: None,
+        })
+        .unwrap()
+    }
+
+    fn entry(body: &str) -> CacheEntry {
+        CacheEntry {
+            status: 200,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+            expires_at: None,
+            etag: None,
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_and_persists_on_disk() {
+        let cache = memory_cache(2);
+        cache.put("a", entry("1"));
+        cache.put("b", entry("2"));
+        cache.get("a");
+        cache.put("c", entry("3"));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+
+        let dir = std::env::temp_dir().join(format!("unifiedstreams-cache-{}", std::process::id()));
+        let config = CacheConfig {
+            backend: CacheBackendConfig::Disk {
+                path: dir.clone(),
+                max_entries: 10,
+            },
+            rpc_ttl: None,
+            http_ttl: None,
+        };
+        ResponseCache::new(config.clone())
+            .unwrap()
+            .put("a", entry("1"));
+        let reopened = ResponseCache::new(config).unwrap();
+        assert_eq!(reopened.get("a"), Some(entry("1")));
+        reopened.clear().unwrap();
+        assert!(reopened.get("a").is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bounds_the_disk_cache_and_never_writes_keys() {
+        let dir = std::env::temp_dir().join(format!(
+            "unifiedstreams-cache-bounded-{}",
+            std::process::id()
+        ));
+        let cache = ResponseCache::new(CacheConfig {
+            backend: CacheBackendConfig::Disk {
+                path: dir.clone(),
+                max_entries: 2,
+            },
+            rpc_ttl: None,
+            http_ttl: None,
+        })
+        .unwrap();
+
+        // Entries are given successive write times, the first one being the oldest.
+        for (written, key) in [
+            "authorization: secret-a",
+            "authorization: secret-b",
+            "authorization: secret-c",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            cache.put(key, entry("1"));
+            let meta_path = dir.join(format!("{}.json", &key_digest(key)[..16]));
+            let meta = fs::File::options().write(true).open(meta_path).unwrap();
+            meta.set_modified(UNIX_EPOCH + Duration::from_secs(written as u64 + 1))
+                .unwrap();
+        }
+        assert!(cache.get("authorization: secret-a").is_none());
+        assert!(cache.get("authorization: secret-b").is_some());
+        assert!(cache.get("authorization: secret-c").is_some());
+
+        let files: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 4);
+        for file in files {
+            assert!(!String::from_utf8_lossy(&fs::read(file).unwrap()).contains("secret"));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn never_caches_moving_blocks() {
+        let caching =
+            |method: &str, params: Value| rpc_caching(&JsonRpcRequest::new(method, params));
+        let hash = format!("0x{}", "ab".repeat(32));
+
+        assert_eq!(
+            caching("eth_getBlockByHash", json!([hash, false])),
+            RpcCaching::Permanent
+        );
+        assert_eq!(
+            caching("eth_getBlockByNumber", json!(["0x10", false])),
+            RpcCaching::Expiring
+        );
+        assert_eq!(
+            caching("eth_getBlockByNumber", json!(["latest", false])),
+            RpcCaching::Never
+        );
+        assert_eq!(
+            caching("eth_getBlockReceipts", json!([hash])),
+            RpcCaching::Permanent
+        );
+        assert_eq!(
+            caching("eth_getTransactionReceipt", json!([hash])),
+            RpcCaching::Expiring
+        );
+        assert_eq!(
+            caching("eth_call", json!([{"to": "0xa"}, "0x10"])),
+            RpcCaching::Expiring
+        );
+        assert_eq!(
+            caching("eth_call", json!([{"to": "0xa"}, {"blockHash": hash}])),
+            RpcCaching::Permanent
+        );
+        assert_eq!(
+            caching("eth_call", json!([{"to": "0xa"}])),
+            RpcCaching::Never
+        );
+        assert_eq!(
+            caching("eth_getLogs", json!([{"blockHash": hash}])),
+            RpcCaching::Permanent
+        );
+        assert_eq!(
+            caching(
+                "eth_getLogs",
+                json!([{"fromBlock": "0x1", "toBlock": "0x2"}])
+            ),
+            RpcCaching::Expiring
+        );
+        assert_eq!(
+            caching(
+                "eth_getLogs",
+                json!([{"fromBlock": "0x1", "toBlock": "pending"}])
+            ),
+            RpcCaching::Never
+        );
+        assert_eq!(
+            caching("eth_getLogs", json!([{"fromBlock": "0x1"}])),
+            RpcCaching::Never
+        );
+        assert_eq!(caching("eth_blockNumber", json!([])), RpcCaching::Never);
+        assert_eq!(
+            caching("eth_sendRawTransaction", json!(["0x00"])),
+            RpcCaching::Never
+        );
+    }
+
+    // Answers every JSON-RPC request with the number of requests received so far.
+    async fn serve_counter() -> MockHttpServer {
+        MockHttpServer::start(|_, n| {
+            MockHttpResponse::json(
+                "200 OK",
+                &json!({"jsonrpc": "2.0", "id": 1, "result": n + 1}),
+            )
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn caches_by_number_results_only_with_a_ttl() {
+        let server = serve_counter().await;
+        let url = server.url();
+        let client = HttpClient::new(HttpClientConfig::default()).unwrap();
+        let by_number = JsonRpcRequest::new("eth_getBlockByNumber", json!(["0x10", false]));
+        let by_hash = JsonRpcRequest::new(
+            "eth_getBlockByHash",
+            json!([format!("0x{}", "ab".repeat(32)), false]),
+        );
+
+        let cache = memory_cache(10);
+        let call = |request| cached_rpc_request(&client, Some(&cache), &url, request, 1);
+        assert_eq!(call(&by_number).await.unwrap(), json!(1));
+        assert_eq!(call(&by_number).await.unwrap(), json!(2));
+        assert_eq!(call(&by_hash).await.unwrap(), json!(3));
+        assert_eq!(call(&by_hash).await.unwrap(), json!(3));
+
+        let cache = ResponseCache::new(CacheConfig {
+            backend: CacheBackendConfig::Memory { max_entries: 10 },
+            rpc_ttl: Some(Duration::from_secs(60)),
+            http_ttl: None,
+        })
+        .unwrap();
+        let call = |request| cached_rpc_request(&client, Some(&cache), &url, request, 1);
+        assert_eq!(call(&by_number).await.unwrap(), json!(4));
+        assert_eq!(call(&by_number).await.unwrap(), json!(4));
+        assert_eq!(server.hits(), 4);
+    }
+
+    // Answers with `ETag: "v1"` and `Cache-Control: no-cache`, then 304 when revalidated.
+    async fn serve_etag() -> MockHttpServer {
+        MockHttpServer::start(|request, _| {
+            let headers = ["ETag: \"v1\"", "Cache-Control: no-cache"];
+            if request.header("if-none-match") == Some("\"v1\"") {
+                MockHttpResponse::new("304 Not Modified", &headers, "")
+            } else {
+                MockHttpResponse::new("200 OK", &headers, "hello")
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn revalidates_with_etag() {
+        let server = serve_etag().await;
+        let url = server.url();
+        let client = HttpClient::new(HttpClientConfig::default()).unwrap();
+        let cache = memory_cache(10);
+        let request = ApiRequest::get(&url);
+
+        for _ in 0..2 {
+            let response = cached_api_request(&client, Some(&cache), &request)
+                .await
+                .unwrap();
+            assert_eq!(response.status, 200);
+            assert_eq!(response.text(), "hello");
+        }
+        assert_eq!(server.hits(), 2);
+
+        let mut control = ApiResponse {
+            status: 200,
+            headers: vec![(
+                "Cache-Control".to_string(),
+                "public, max-age=60".to_string(),
+            )],
+            body: Vec::new(),
+        };
+        assert_eq!(cache_control(&control).max_age, Some(60));
+        let now = SystemTime::now();
+        assert_eq!(
+            api_entry(&control, None, now).unwrap().expires_at,
+            Some(now + Duration::from_secs(60))
+        );
+        control.headers = vec![("Cache-Control".to_string(), "no-store".to_string())];
+        assert!(api_entry(&control, Some(Duration::from_secs(60)), now).is_none());
+    }
+}
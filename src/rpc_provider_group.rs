@@ -0,0 +1,604 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use futures03::future::join_all;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+use crate::http_client::{shared_client, HttpClient};
+use crate::json_rpc::{send_request, JsonRpcRequest, RpcError};
+
+// A provider failing this many times in a row is only tried after the healthy ones.
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+// Weight of the latest call in the provider score and latency averages.
+const SCORE_WEIGHT: f64 = 0.2;
+
+lazy_static! {
+    // Groups are kept across calls so the round-robin position and health scores survive
+    // between FFI calls using the same providers.
+    static ref SHARED_GROUPS: Mutex<HashMap<String, Arc<RpcProviderGroup>>> =
+        Mutex::new(HashMap::new());
+}
+
+// Returns the group for these comma separated endpoints and strategy, creating it on first
+// use.
+pub fn shared_group(endpoints: &str, strategy: &str) -> Result<Arc<RpcProviderGroup>, Error> {
+    let key = format!("{}|{}", endpoints, strategy);
+    let mut groups = SHARED_GROUPS.lock().unwrap();
+    if let Some(group) = groups.get(&key) {
+        return Ok(group.clone());
+    }
+
+    let urls: Vec<String> = endpoints
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            if url.starts_with("http") {
+                url.to_string()
+            } else {
+                format!("https://{}", url)
+            }
+        })
+        .collect();
+
+    let group = Arc::new(RpcProviderGroup::new(urls, RpcStrategy::parse(strategy)?)?);
+    groups.insert(key, group.clone());
+    Ok(group)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RpcStrategy {
+    // Providers are tried in the configured order, healthy ones first.
+    Fallback,
+    // Each call starts with the next provider, falling back to the others.
+    RoundRobin,
+    // The request is sent to `providers` providers at once and the answer given by at
+    // least `agree` of them is returned, unless another answer reached `agree` too.
+    Quorum { agree: usize, providers: usize },
+}
+
+impl RpcStrategy {
+    // `fallback`, `round_robin` or `quorum:<agree>/<providers>`. `quorum:<agree>` sends to
+    // every provider of the group.
+    pub fn parse(strategy: &str) -> Result<Self, Error> {
+        match strategy.trim() {
+            "" | "fallback" => Ok(RpcStrategy::Fallback),
+            "round_robin" | "round-robin" => Ok(RpcStrategy::RoundRobin),
+            x if x.starts_with("quorum:") => {
+                let spec = x.trim_start_matches("quorum:");
+                let (agree, providers) = match spec.split_once('/') {
+                    Some((agree, providers)) => (agree, providers.parse().ok()),
+                    None => (spec, Some(0)),
+                };
+                match (agree.parse(), providers) {
+                    (Ok(agree), Some(providers)) if agree > 0 => {
+                        Ok(RpcStrategy::Quorum { agree, providers })
+                    }
+                    _ => Err(anyhow!(
+                        "invalid quorum '{}', expected quorum:<agree>/<providers>",
+                        x
+                    )),
+                }
+            }
+            x => Err(anyhow!("unknown RPC strategy '{}'", x)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProviderHealth {
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    // Moving average of the latency of successful calls.
+    pub latency: Option<Duration>,
+    // Between 0 and 1, moving towards 1 on success and 0 on failure.
+    pub score: f64,
+}
+
+impl Default for ProviderHealth {
+    fn default() -> Self {
+        ProviderHealth {
+            successes: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            latency: None,
+            score: 1.0,
+        }
+    }
+}
+
+impl ProviderHealth {
+    pub fn healthy(&self) -> bool {
+        self.consecutive_failures < MAX_CONSECUTIVE_FAILURES
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "successes": self.successes,
+            "failures": self.failures,
+            "consecutive_failures": self.consecutive_failures,
+            "latency_ms": self.latency.map(|l| l.as_millis() as u64),
+            "score": self.score,
+            "healthy": self.healthy(),
+        })
+    }
+}
+
+// Outcome of the call to one provider.
+#[derive(Clone, Debug)]
+pub struct ProviderAttempt {
+    pub provider: String,
+    pub latency: Duration,
+    pub outcome: Result<Value, String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcGroupResponse {
+    pub result: Value,
+    // The provider whose answer is returned.
+    pub provider: String,
+    // Providers which gave that same answer, more than one only in quorum mode.
+    pub agreeing: Vec<String>,
+    pub attempts: Vec<ProviderAttempt>,
+}
+
+impl RpcGroupResponse {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "result": self.result,
+            "provider": self.provider,
+            "agreeing": self.agreeing,
+            "attempts": self.attempts.iter().map(|attempt| {
+                let mut value = json!({
+                    "provider": attempt.provider,
+                    "latency_ms": attempt.latency.as_millis() as u64,
+                });
+                match &attempt.outcome {
+                    Ok(_) => value["ok"] = json!(true),
+                    Err(e) => {
+                        value["ok"] = json!(false);
+                        value["error"] = json!(e);
+                    }
+                }
+                value
+            }).collect::<Vec<_>>(),
+        })
+    }
+}
+
+// Errors that say something about the provider rather than the request. Other JSON-RPC
+// errors (e.g. a reverted `eth_call`) are a valid answer and are not retried elsewhere.
+fn is_provider_failure(error: &RpcError) -> bool {
+    match error {
+        RpcError::JsonRpc(error) => is_provider_error_code(error.code),
+        // Server errors and rate limiting are the provider's whatever the body says. Other
+        // statuses are judged on the JSON-RPC error they come with, e.g. a reverted call
+        // answered with 400, and are the provider's without one (bad key, wrong URL).
+        RpcError::Http { status, error, .. } => {
+            *status >= 500
+                || *status == 429
+                || error
+                    .as_ref()
+                    .map_or(true, |error| is_provider_error_code(error.code))
+        }
+        RpcError::InvalidRequest(_) => false,
+        _ => true,
+    }
+}
+
+// Internal errors and rate limiting.
+fn is_provider_error_code(code: i64) -> bool {
+    matches!(code, -32603 | -32005)
+}
+
+struct Provider {
+    url: String,
+    health: Mutex<ProviderHealth>,
+}
+
+// A group of JSON-RPC providers serving the same chain, called according to an
+// `RpcStrategy`. Every call updates the health of the providers involved, which decides
+// the order they are tried in.
+pub struct RpcProviderGroup {
+    providers: Vec<Provider>,
+    strategy: RpcStrategy,
+    next: AtomicUsize,
+    client: Option<Arc<HttpClient>>,
+}
+
+impl RpcProviderGroup {
+    pub fn new(urls: Vec<String>, strategy: RpcStrategy) -> Result<Self, Error> {
+        if urls.is_empty() {
+            return Err(anyhow!("RPC provider group requires at least one provider"));
+        }
+
+        let strategy = match strategy {
+            RpcStrategy::Quorum { agree, providers } => {
+                let providers = if providers == 0 {
+                    urls.len()
+                } else {
+                    providers
+                };
+                if agree > providers || providers > urls.len() {
+                    return Err(anyhow!(
+                        "quorum of {} out of {} providers is impossible with {} providers",
+                        agree,
+                        providers,
+                        urls.len()
+                    ));
+                }
+                RpcStrategy::Quorum { agree, providers }
+            }
+            strategy => strategy,
+        };
+
+        Ok(RpcProviderGroup {
+            providers: urls
+                .into_iter()
+                .map(|url| Provider {
+                    url,
+                    health: Mutex::new(ProviderHealth::default()),
+                })
+                .collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+            client: None,
+        })
+    }
+
+    // Uses `client` instead of the shared HTTP client.
+    pub fn with_client(mut self, client: Arc<HttpClient>) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn strategy(&self) -> RpcStrategy {
+        self.strategy
+    }
+
+    pub fn health(&self) -> Vec<(String, ProviderHealth)> {
+        self.providers
+            .iter()
+            .map(|p| (p.url.clone(), p.health.lock().unwrap().clone()))
+            .collect()
+    }
+
+    pub fn health_json(&self) -> Value {
+        Value::Array(
+            self.health()
+                .into_iter()
+                .map(|(url, health)| {
+                    let mut value = health.to_json();
+                    value["provider"] = json!(url);
+                    value
+                })
+                .collect(),
+        )
+    }
+
+    fn record(&self, index: usize, latency: Duration, success: bool) {
+        let mut health = self.providers[index].health.lock().unwrap();
+        if success {
+            health.successes += 1;
+            health.consecutive_failures = 0;
+            health.score = health.score * (1.0 - SCORE_WEIGHT) + SCORE_WEIGHT;
+            health.latency = Some(match health.latency {
+                Some(average) => {
+                    average.mul_f64(1.0 - SCORE_WEIGHT) + latency.mul_f64(SCORE_WEIGHT)
+                }
+                None => latency,
+            });
+        } else {
+            health.failures += 1;
+            health.consecutive_failures += 1;
+            health.score *= 1.0 - SCORE_WEIGHT;
+        }
+    }
+
+    // Provider indexes starting at `start` and wrapping around, healthy ones first.
+    fn order_from(&self, start: usize) -> Vec<usize> {
+        let len = self.providers.len();
+        let mut order: Vec<usize> = (0..len).map(|i| (start + i) % len).collect();
+        order.sort_by_key(|i| !self.providers[*i].health.lock().unwrap().healthy());
+        order
+    }
+
+    async fn call_provider(
+        &self,
+        index: usize,
+        request: &JsonRpcRequest,
+    ) -> (Result<Value, RpcError>, ProviderAttempt) {
+        let client = self.client.clone().unwrap_or_else(shared_client);
+        let url = &self.providers[index].url;
+
+        let started = Instant::now();
+        let result = send_request(&client, url, request, 1).await;
+        let latency = started.elapsed();
+
+        let failed = matches!(&result, Err(e) if is_provider_failure(e));
+        self.record(index, latency, !failed);
+
+        let attempt = ProviderAttempt {
+            provider: url.clone(),
+            latency,
+            outcome: match &result {
+                Ok(value) => Ok(value.clone()),
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        (result, attempt)
+    }
+
+    pub async fn call(&self, request: &JsonRpcRequest) -> Result<RpcGroupResponse, RpcError> {
+        match self.strategy {
+            RpcStrategy::Fallback => self.call_in_order(self.order_from(0), request).await,
+            RpcStrategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed) % self.providers.len();
+                self.call_in_order(self.order_from(start), request).await
+            }
+            RpcStrategy::Quorum { agree, providers } => {
+                self.call_quorum(agree, providers, request).await
+            }
+        }
+    }
+
+    async fn call_in_order(
+        &self,
+        order: Vec<usize>,
+        request: &JsonRpcRequest,
+    ) -> Result<RpcGroupResponse, RpcError> {
+        let mut attempts = Vec::new();
+        let mut last_error = None;
+
+        for index in order {
+            let (result, attempt) = self.call_provider(index, request).await;
+            attempts.push(attempt);
+
+            match result {
+                Ok(value) => {
+                    let provider = self.providers[index].url.clone();
+                    return Ok(RpcGroupResponse {
+                        result: value,
+                        agreeing: vec![provider.clone()],
+                        provider,
+                        attempts,
+                    });
+                }
+                // The error is kept in `attempts`, the next provider is tried.
+                Err(e) if is_provider_failure(&e) => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.expect("a provider group is never empty"))
+    }
+
+    // Sends to the `providers` healthiest providers at once. Answers are compared as JSON,
+    // a JSON-RPC error given by enough providers is returned as the agreed answer.
+    async fn call_quorum(
+        &self,
+        agree: usize,
+        providers: usize,
+        request: &JsonRpcRequest,
+    ) -> Result<RpcGroupResponse, RpcError> {
+        let mut order: Vec<usize> = (0..self.providers.len()).collect();
+        order.sort_by(|a, b| {
+            let a = self.providers[*a].health.lock().unwrap().score;
+            let b = self.providers[*b].health.lock().unwrap().score;
+            b.total_cmp(&a)
+        });
+        order.truncate(providers);
+
+        let results = join_all(
+            order
+                .iter()
+                .map(|index| self.call_provider(*index, request)),
+        )
+        .await;
+
+        // Groups identical answers, keeping the first error of each group to return it.
+        let mut answers: Vec<(Value, Vec<usize>, Option<RpcError>)> = Vec::new();
+        let mut attempts = Vec::with_capacity(results.len());
+        for (index, (result, attempt)) in order.iter().zip(results) {
+            attempts.push(attempt);
+            let (key, error) = match result {
+                Ok(value) => (json!({ "result": value }), None),
+                Err(e) if !is_provider_failure(&e) => {
+                    let error = match e.json_rpc_error() {
+                        Some(error) => error.to_json(),
+                        None => e.to_json(),
+                    };
+                    (json!({ "error": error }), Some(e))
+                }
+                Err(_) => continue,
+            };
+
+            match answers.iter_mut().find(|(k, _, _)| *k == key) {
+                Some((_, agreeing, _)) => agreeing.push(*index),
+                None => answers.push((key, vec![*index], error)),
+            }
+        }
+
+        // Two answers both reaching `agree` contradict each other, neither is returned.
+        let reached = answers
+            .iter()
+            .filter(|(_, agreeing, _)| agreeing.len() >= agree)
+            .count();
+        let best = answers
+            .into_iter()
+            .max_by_key(|(_, agreeing, _)| agreeing.len());
+        match best {
+            best if reached > 1 => Err(RpcError::NoQuorum {
+                required: agree,
+                agreed: best
+                    .map(|(_, agreeing, _)| agreeing.len())
+                    .unwrap_or_default(),
+            }),
+            Some((_, agreeing, Some(error))) if agreeing.len() >= agree => Err(error),
+            Some((key, agreeing, None)) if agreeing.len() >= agree => Ok(RpcGroupResponse {
+                result: key["result"].clone(),
+                provider: self.providers[agreeing[0]].url.clone(),
+                agreeing: agreeing
+                    .iter()
+                    .map(|i| self.providers[*i].url.clone())
+                    .collect(),
+                attempts,
+            }),
+            best => Err(RpcError::NoQuorum {
+                required: agree,
+                agreed: best
+                    .map(|(_, agreeing, _)| agreeing.len())
+                    .unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::HttpClientConfig;
+    use crate::mock_http_server::{closed_url, MockHttpResponse, MockHttpServer};
+
+    // A provider answering every request with `result`.
+    async fn provider(result: Value) -> String {
+        let body = json!({"jsonrpc": "2.0", "id": 1, "result": result});
+        responder("200 OK", body).await
+    }
+
+    // A provider answering every request with a JSON-RPC `error` and the HTTP `status`.
+    async fn failing_provider(status: &'static str, code: i64, message: &str) -> String {
+        let body = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": code, "message": message}});
+        responder(status, body).await
+    }
+
+    async fn responder(status: &'static str, body: Value) -> String {
+        let server = MockHttpServer::start(move |_, _| MockHttpResponse::json(status, &body)).await;
+        server.url()
+    }
+
+    fn group(urls: Vec<String>, strategy: &str) -> RpcProviderGroup {
+        let client = HttpClient::new(HttpClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        RpcProviderGroup::new(urls, RpcStrategy::parse(strategy).unwrap())
+            .unwrap()
+            .with_client(Arc::new(client))
+    }
+
+    fn request() -> JsonRpcRequest {
+        JsonRpcRequest::new("eth_blockNumber", json!([]))
+    }
+
+    #[tokio::test]
+    async fn falls_back_and_round_robins() {
+        let dead = closed_url().await;
+        let a = provider(json!("0xa")).await;
+        let b = provider(json!("0xb")).await;
+
+        let fallback = group(vec![dead.clone(), a.clone()], "fallback");
+        let response = fallback.call(&request()).await.unwrap();
+        assert_eq!(response.result, json!("0xa"));
+        assert_eq!(response.provider, a);
+        assert_eq!(response.attempts.len(), 2);
+        assert_eq!(fallback.health()[0].1.consecutive_failures, 1);
+
+        let round_robin = group(vec![a.clone(), b.clone()], "round_robin");
+        let mut providers = Vec::new();
+        for _ in 0..3 {
+            providers.push(round_robin.call(&request()).await.unwrap().provider);
+        }
+        assert_eq!(providers, vec![a.clone(), b, a]);
+    }
+
+    #[tokio::test]
+    async fn classifies_failures_on_http_status() {
+        let a = provider(json!("0xa")).await;
+        let overloaded = failing_provider("503 Service Unavailable", -32000, "overloaded").await;
+        let limited = failing_provider("429 Too Many Requests", -32000, "slow down").await;
+        let reverted = failing_provider("400 Bad Request", 3, "execution reverted").await;
+
+        let fallback = group(vec![overloaded, limited, a.clone()], "fallback");
+        let response = fallback.call(&request()).await.unwrap();
+        assert_eq!(response.provider, a);
+        assert_eq!(response.attempts.len(), 3);
+        assert!(response.attempts[0]
+            .outcome
+            .as_ref()
+            .unwrap_err()
+            .contains("503"));
+
+        // A reverted call is an answer, the next provider would revert too.
+        let fallback = group(vec![reverted, a], "fallback");
+        let err = fallback.call(&request()).await.unwrap_err();
+        assert_eq!(err.json_rpc_error().unwrap().code, 3);
+        assert_eq!(fallback.health()[0].1.consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn quorum_returns_agreed_answer() {
+        let a = provider(json!("0x10")).await;
+        let b = provider(json!("0x10")).await;
+        let c = provider(json!("0x11")).await;
+
+        let quorum = group(vec![a.clone(), b.clone(), c.clone()], "quorum:2/3");
+        let response = quorum.call(&request()).await.unwrap();
+        assert_eq!(response.result, json!("0x10"));
+        assert_eq!(response.agreeing, vec![a.clone(), b]);
+
+        let quorum = group(vec![a, c, closed_url().await], "quorum:2");
+        assert!(matches!(
+            quorum.call(&request()).await,
+            Err(RpcError::NoQuorum {
+                required: 2,
+                agreed: 1
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn quorum_fails_when_two_answers_reach_it() {
+        let a = provider(json!("0x10")).await;
+        let b = provider(json!("0x10")).await;
+        let c = provider(json!("0x11")).await;
+        let d = provider(json!("0x11")).await;
+
+        let quorum = group(vec![a, b, c, d], "quorum:2/4");
+        assert!(matches!(
+            quorum.call(&request()).await,
+            Err(RpcError::NoQuorum {
+                required: 2,
+                agreed: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(
+            RpcStrategy::parse("quorum:2/3").unwrap(),
+            RpcStrategy::Quorum {
+                agree: 2,
+                providers: 3
+            }
+        );
+        assert!(RpcStrategy::parse("quorum:0/3").is_err());
+        assert!(RpcStrategy::parse("random").is_err());
+        assert!(RpcProviderGroup::new(
+            vec!["http://a".to_string()],
+            RpcStrategy::parse("quorum:2").unwrap()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context, Error};
+use serde_json::{json, Value};
+use tokio::time::{sleep, Instant};
+use tonic::codec::CompressionEncoding;
+
+use crate::pb::sf::substreams::sink::service::v1::{
+    provider_client::ProviderClient, DeployRequest, DeploymentStatus, InfoRequest, ListRequest,
+    PackageInfo, Parameter, PauseRequest, RemoveRequest, ResumeRequest, StopRequest, UpdateRequest,
+};
+use crate::pb::sf::substreams::v1::Package;
+use crate::substreams::SubstreamsEndpoint;
+
+#[derive(Clone, Debug)]
+pub struct Deployment {
+    pub id: String,
+    pub status: DeploymentStatus,
+    pub services: HashMap<String, String>,
+    pub reason: String,
+    pub motd: String,
+    pub package_info: Option<PackageInfo>,
+    pub last_processed_block: Option<u64>,
+}
+
+impl Deployment {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "status": self.status.as_str_name(),
+            "services": self.services,
+            "reason": self.reason,
+            "motd": self.motd,
+            "package_info": self.package_info.as_ref().map(|info| json!({
+                "name": info.name,
+                "version": info.version,
+                "output_module_name": info.output_module_name,
+                "output_module_hash": info.output_module_hash,
+            })),
+            "last_processed_block": self.last_processed_block,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StatusTransition {
+    pub previous_status: DeploymentStatus,
+    pub new_status: DeploymentStatus,
+}
+
+impl StatusTransition {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "previous_status": self.previous_status.as_str_name(),
+            "new_status": self.new_status.as_str_name(),
+        })
+    }
+}
+
+// A single sink service operation, as issued from FFI hosts.
+#[derive(Clone, Debug)]
+pub enum SinkCommand {
+    Deploy {
+        package_file: String,
+        parameters: Option<String>,
+        development_mode: bool,
+    },
+    Update {
+        deployment_id: String,
+        package_file: String,
+        reset: bool,
+    },
+    Info(String),
+    List,
+    Pause(String),
+    Stop(String),
+    Resume(String),
+    Remove(String),
+    WaitForStatus {
+        deployment_id: String,
+        statuses: Vec<DeploymentStatus>,
+        poll_interval: Duration,
+        timeout: Duration,
+    },
+}
+
+// Client for the `sf.substreams.sink.service.v1.Provider` service, managing the lifecycle
+// of sink deployments hosted by a provider.
+pub struct SinkServiceClient {
+    endpoint: Arc<SubstreamsEndpoint>,
+}
+
+impl SinkServiceClient {
+    pub fn new(endpoint: Arc<SubstreamsEndpoint>) -> Self {
+        SinkServiceClient { endpoint }
+    }
+
+    fn client(
+        &self,
+    ) -> Result<
+        ProviderClient<
+            tonic::service::interceptor::InterceptedService<
+                tonic::transport::Channel,
+                impl FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> + Clone,
+            >,
+        >,
+        Error,
+    > {
+        Ok(ProviderClient::with_interceptor(
+            self.endpoint.channel(),
+            self.endpoint.auth_interceptor()?,
+        )
+        .accept_compressed(CompressionEncoding::Gzip)
+        .send_compressed(CompressionEncoding::Gzip))
+    }
+
+    pub async fn deploy(
+        &self,
+        package: Package,
+        parameters: Vec<Parameter>,
+        development_mode: bool,
+    ) -> Result<Deployment, Error> {
+        let response = self
+            .client()?
+            .deploy(DeployRequest {
+                substreams_package: Some(package),
+                development_mode,
+                parameters,
+            })
+            .await?
+            .into_inner();
+
+        Ok(Deployment {
+            status: response.status(),
+            id: response.deployment_id,
+            services: response.services,
+            reason: response.reason,
+            motd: response.motd,
+            package_info: None,
+            last_processed_block: None,
+        })
+    }
+
+    pub async fn update(
+        &self,
+        deployment_id: &str,
+        package: Package,
+        reset: bool,
+    ) -> Result<Deployment, Error> {
+        let response = self
+            .client()?
+            .update(UpdateRequest {
+                substreams_package: Some(package),
+                deployment_id: deployment_id.to_string(),
+                reset,
+            })
+            .await?
+            .into_inner();
+
+        Ok(Deployment {
+            status: response.status(),
+            id: deployment_id.to_string(),
+            services: response.services,
+            reason: response.reason,
+            motd: response.motd,
+            package_info: None,
+            last_processed_block: None,
+        })
+    }
+
+    pub async fn info(&self, deployment_id: &str) -> Result<Deployment, Error> {
+        let response = self
+            .client()?
+            .info(InfoRequest {
+                deployment_id: deployment_id.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(Deployment {
+            status: response.status(),
+            id: deployment_id.to_string(),
+            services: response.services,
+            reason: response.reason,
+            motd: response.motd,
+            package_info: response.package_info,
+            last_processed_block: response.progress.map(|p| p.last_processed_block),
+        })
+    }
+
+    pub async fn list(&self) -> Result<Vec<Deployment>, Error> {
+        let response = self.client()?.list(ListRequest {}).await?.into_inner();
+
+        Ok(response
+            .deployments
+            .into_iter()
+            .map(|deployment| Deployment {
+                status: deployment.status(),
+                id: deployment.id,
+                services: HashMap::new(),
+                reason: deployment.reason,
+                motd: deployment.motd,
+                package_info: deployment.package_info,
+                last_processed_block: deployment.progress.map(|p| p.last_processed_block),
+            })
+            .collect())
+    }
+
+    pub async fn pause(&self, deployment_id: &str) -> Result<StatusTransition, Error> {
+        let response = self
+            .client()?
+            .pause(PauseRequest {
+                deployment_id: deployment_id.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(StatusTransition {
+            previous_status: response.previous_status(),
+            new_status: response.new_status(),
+        })
+    }
+
+    pub async fn stop(&self, deployment_id: &str) -> Result<StatusTransition, Error> {
+        let response = self
+            .client()?
+            .stop(StopRequest {
+                deployment_id: deployment_id.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(StatusTransition {
+            previous_status: response.previous_status(),
+            new_status: response.new_status(),
+        })
+    }
+
+    pub async fn resume(&self, deployment_id: &str) -> Result<StatusTransition, Error> {
+        let response = self
+            .client()?
+            .resume(ResumeRequest {
+                deployment_id: deployment_id.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(StatusTransition {
+            previous_status: response.previous_status(),
+            new_status: response.new_status(),
+        })
+    }
+
+    // Removing a deployment has no new status, the deployment no longer exists afterwards.
+    pub async fn remove(&self, deployment_id: &str) -> Result<DeploymentStatus, Error> {
+        let response = self
+            .client()?
+            .remove(RemoveRequest {
+                deployment_id: deployment_id.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(response.previous_status())
+    }
+
+    // Polls `info` until the deployment reaches one of `statuses`, failing early when the
+    // deployment reports `Failing` (unless it is one of the awaited statuses).
+    pub async fn wait_for_status(
+        &self,
+        deployment_id: &str,
+        statuses: &[DeploymentStatus],
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<Deployment, Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let deployment = self.info(deployment_id).await?;
+            if statuses.contains(&deployment.status) {
+                return Ok(deployment);
+            }
+
+            if deployment.status == DeploymentStatus::Failing {
+                return Err(anyhow!(
+                    "deployment {} is failing: {}",
+                    deployment_id,
+                    deployment.reason
+                ));
+            }
+
+            if Instant::now() + poll_interval > deadline {
+                return Err(anyhow!(
+                    "deployment {} did not reach {:?} within {:?} (last status {})",
+                    deployment_id,
+                    statuses,
+                    timeout,
+                    deployment.status.as_str_name()
+                ));
+            }
+
+            sleep(poll_interval).await;
+        }
+    }
+}
+
+// Parses deployment parameters given as a flat JSON object of string values.
+pub fn parse_parameters(parameters_json: Option<&str>) -> Result<Vec<Parameter>, Error> {
+    let parameters: Value = match parameters_json {
+        Some(parameters_str) => {
+            serde_json::from_str(parameters_str).context("Invalid JSON for parameters")?
+        }
+        None => return Ok(vec![]),
+    };
+
+    let object = parameters
+        .as_object()
+        .ok_or_else(|| anyhow!("parameters must be a JSON object"))?;
+
+    object
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            Ok(Parameter {
+                key: key.clone(),
+                value,
+            })
+        })
+        .collect()
+}
+
+// Parses comma separated deployment status names such as `RUNNING,PAUSED`.
+pub fn parse_statuses(statuses: &str) -> Result<Vec<DeploymentStatus>, Error> {
+    statuses
+        .split(',')
+        .map(|name| {
+            let name = name.trim().to_uppercase();
+            DeploymentStatus::from_str_name(&name)
+                .ok_or_else(|| anyhow!("unknown deployment status '{}'", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockGrpcServer, MockService, MockServiceName};
+    use crate::pb::sf::substreams::sink::service::v1::{
+        DeployResponse, InfoResponse, PauseResponse,
+    };
+    use std::sync::Mutex;
+    use tonic::transport::Server;
+
+    struct ProviderName;
+    impl MockServiceName for ProviderName {
+        const NAME: &'static str = "sf.substreams.sink.service.v1.Provider";
+    }
+
+    async fn connect(service: MockService<ProviderName>) -> (MockGrpcServer, SinkServiceClient) {
+        let server = MockGrpcServer::start(Server::builder().add_service(service))
+            .await
+            .unwrap();
+        let endpoint = SubstreamsEndpoint::new(server.url(), None).await.unwrap();
+        (server, SinkServiceClient::new(Arc::new(endpoint)))
+    }
+
+    // Answers `Info` with each of `statuses` in turn, the last one repeating.
+    fn info_sequence(statuses: Vec<DeploymentStatus>) -> MockService<ProviderName> {
+        let statuses = Mutex::new(statuses);
+        MockService::new().unary("Info", move |request: InfoRequest| {
+            let mut statuses = statuses.lock().unwrap();
+            let status = if statuses.len() > 1 {
+                statuses.remove(0)
+            } else {
+                statuses[0]
+            };
+
+            Ok(InfoResponse {
+                status: status as i32,
+                reason: format!("{} is {}", request.deployment_id, status.as_str_name()),
+                ..Default::default()
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn deploy_sends_package_and_parameters() {
+        let received = Arc::new(Mutex::new(None));
+        let deploy_received = received.clone();
+        let service = MockService::new().unary("Deploy", move |request: DeployRequest| {
+            *deploy_received.lock().unwrap() = Some(request);
+            Ok(DeployResponse {
+                status: DeploymentStatus::Starting as i32,
+                deployment_id: "dep-1".to_string(),
+                ..Default::default()
+            })
+        });
+        let (_server, client) = connect(service).await;
+
+        let parameters =
+            parse_parameters(Some(r#"{"dsn": "postgres://db", "workers": 4}"#)).unwrap();
+        let deployment = client
+            .deploy(Package::default(), parameters, true)
+            .await
+            .unwrap();
+
+        assert_eq!(deployment.id, "dep-1");
+        assert_eq!(deployment.status, DeploymentStatus::Starting);
+
+        let request = received.lock().unwrap().take().unwrap();
+        assert!(request.development_mode);
+        assert!(request.substreams_package.is_some());
+        let mut parameters: Vec<(String, String)> = request
+            .parameters
+            .into_iter()
+            .map(|p| (p.key, p.value))
+            .collect();
+        parameters.sort();
+        assert_eq!(
+            parameters,
+            vec![
+                ("dsn".to_string(), "postgres://db".to_string()),
+                ("workers".to_string(), "4".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn status_transitions_are_returned() {
+        let service = MockService::new().unary("Pause", |_: PauseRequest| {
+            Ok(PauseResponse {
+                previous_status: DeploymentStatus::Running as i32,
+                new_status: DeploymentStatus::Pausing as i32,
+            })
+        });
+        let (_server, client) = connect(service).await;
+
+        let transition = client.pause("dep-1").await.unwrap();
+
+        assert_eq!(
+            transition.to_json(),
+            json!({"previous_status": "RUNNING", "new_status": "PAUSING"})
+        );
+        let err = client.stop("dep-1").await.unwrap_err();
+        assert!(err.to_string().contains("nimplemented"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn wait_for_status_polls_until_reached() {
+        let service = info_sequence(vec![
+            DeploymentStatus::Starting,
+            DeploymentStatus::Starting,
+            DeploymentStatus::Running,
+        ]);
+        let (_server, client) = connect(service).await;
+
+        let deployment = client
+            .wait_for_status(
+                "dep-1",
+                &[DeploymentStatus::Running],
+                Duration::from_millis(1),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(deployment.status, DeploymentStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn wait_for_status_stops_on_failing_deployments() {
+        let service = info_sequence(vec![DeploymentStatus::Starting, DeploymentStatus::Failing]);
+        let (_server, client) = connect(service).await;
+
+        let err = client
+            .wait_for_status(
+                "dep-1",
+                &[DeploymentStatus::Running],
+                Duration::from_millis(1),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("dep-1 is FAILING"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn wait_for_status_times_out() {
+        let (_server, client) = connect(info_sequence(vec![DeploymentStatus::Starting])).await;
+
+        let err = client
+            .wait_for_status(
+                "dep-1",
+                &[DeploymentStatus::Running],
+                Duration::from_millis(20),
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("last status STARTING"), "{}", err);
+    }
+
+    #[test]
+    fn parses_statuses_and_parameters() {
+        assert_eq!(
+            parse_statuses("running, PAUSED").unwrap(),
+            vec![DeploymentStatus::Running, DeploymentStatus::Paused]
+        );
+        assert!(parse_statuses("RUNNING,asleep").is_err());
+
+        assert!(parse_parameters(None).unwrap().is_empty());
+        assert!(parse_parameters(Some("[]")).is_err());
+        assert!(parse_parameters(Some("{")).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{anyhow, Context, Error};
+use rusqlite::{
+    params,
+    types::{Value as SqlValue, ValueRef},
+    Connection, OptionalExtension, Transaction,
+};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::entity_store::EntityValue;
+use crate::pb::sf::substreams::entity::v1::{
+    entity_change::Operation, EntityChange, EntityChanges,
+};
+
+// Internal tables are prefixed so they can never collide with an entity table, entities
+// using the prefix are rejected.
+const INTERNAL_TABLE_PREFIX: &str = "_substreams_";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS _substreams_cursor (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        cursor TEXT NOT NULL,
+        block_num INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS _substreams_history (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        block_num INTEGER NOT NULL,
+        entity TEXT NOT NULL,
+        entity_id TEXT NOT NULL,
+        previous TEXT
+    );
+    CREATE INDEX IF NOT EXISTS _substreams_history_block_num ON _substreams_history (block_num);
+";
+
+// Lands `EntityChanges` into a SQLite database, one table per entity with an `id` primary
+// key and a column per observed field. Each block is applied in a single transaction that
+// also journals the previous rows in `_substreams_history` and saves the cursor in
+// `_substreams_cursor`, so the database and the cursor can never disagree and undo signals
+// can be rolled back.
+pub struct SqliteSink {
+    conn: Connection,
+}
+
+impl SqliteSink {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let conn = Connection::open(path.as_ref()).context(format!(
+            "open sqlite database '{}'",
+            path.as_ref().display()
+        ))?;
+        Self::with_connection(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteSink { conn })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn load_cursor(&self) -> Result<Option<String>, Error> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT cursor FROM _substreams_cursor WHERE id = 0",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn apply_block(
+        &mut self,
+        block_num: u64,
+        final_block_height: u64,
+        changes: &EntityChanges,
+        cursor: &str,
+    ) -> Result<(), Error> {
+        let tx = self.conn.transaction()?;
+
+        for change in &changes.entity_changes {
+            apply_change(&tx, block_num, change)?;
+        }
+
+        // Blocks at or below the final height can no longer be undone.
+        tx.execute(
+            "DELETE FROM _substreams_history WHERE block_num <= ?1",
+            params![final_block_height as i64],
+        )?;
+        save_cursor(&tx, cursor, block_num)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Rolls back every change recorded after `last_valid_block`, newest first.
+    pub fn undo(&mut self, last_valid_block: u64, last_valid_cursor: &str) -> Result<(), Error> {
+        let tx = self.conn.transaction()?;
+
+        let entries: Vec<(String, String, Option<String>)> = {
+            let mut stmt = tx.prepare(
+                "SELECT entity, entity_id, previous FROM _substreams_history WHERE block_num > ?1 ORDER BY seq DESC",
+            )?;
+            let rows = stmt.query_map(params![last_valid_block as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            rows.collect::<Result<_, _>>()?
+        };
+
+        for (entity, id, previous) in entries {
+            let table = quote_identifier(&entity);
+            match previous {
+                None => {
+                    tx.execute(&format!("DELETE FROM {} WHERE id = ?1", table), params![id])?;
+                }
+                Some(previous) => {
+                    let row: Map<String, JsonValue> = serde_json::from_str(&previous)?;
+                    let columns: Vec<(String, SqlValue)> = row
+                        .into_iter()
+                        .map(|(name, value)| (name, json_to_sql(value)))
+                        .collect();
+                    replace_row(&tx, &table, &columns)?;
+                }
+            }
+        }
+
+        tx.execute(
+            "DELETE FROM _substreams_history WHERE block_num > ?1",
+            params![last_valid_block as i64],
+        )?;
+        save_cursor(&tx, last_valid_cursor, last_valid_block)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn apply_change(tx: &Transaction, block_num: u64, change: &EntityChange) -> Result<(), Error> {
+    let operation = Operation::try_from(change.operation)
+        .map_err(|_| anyhow!("unknown operation {} on {}", change.operation, change.id))?;
+
+    // SQLite identifiers are case insensitive, and it reserves its own `sqlite_` prefix.
+    let entity = change.entity.to_ascii_lowercase();
+    if entity.starts_with(INTERNAL_TABLE_PREFIX) || entity.starts_with("sqlite_") {
+        return Err(anyhow!(
+            "entity name '{}' is reserved for internal tables",
+            change.entity
+        ));
+    }
+
+    let columns: Vec<(String, EntityValue)> = change
+        .fields
+        .iter()
+        .filter(|field| field.name != "id")
+        .map(|field| {
+            let value = field
+                .new_value
+                .as_ref()
+                .map(EntityValue::from)
+                .unwrap_or(EntityValue::Null);
+            (field.name.clone(), value)
+        })
+        .collect();
+
+    let table = quote_identifier(&change.entity);
+    ensure_table(tx, &change.entity, &columns)?;
+
+    let previous = read_row(tx, &table, &change.id)?;
+
+    match operation {
+        Operation::Create | Operation::Update => {
+            let mut names = vec!["id".to_string()];
+            let mut values = vec![SqlValue::Text(change.id.clone())];
+            for (name, value) in &columns {
+                names.push(quote_identifier(name));
+                values.push(entity_value_to_sql(value));
+            }
+
+            let placeholders: Vec<String> = (1..=values.len()).map(|i| format!("?{}", i)).collect();
+            let updates: Vec<String> = names[1..]
+                .iter()
+                .map(|name| format!("{} = excluded.{}", name, name))
+                .collect();
+            let conflict = if updates.is_empty() {
+                "DO NOTHING".to_string()
+            } else {
+                format!("DO UPDATE SET {}", updates.join(", "))
+            };
+
+            tx.execute(
+                &format!(
+                    "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT(id) {}",
+                    table,
+                    names.join(", "),
+                    placeholders.join(", "),
+                    conflict
+                ),
+                rusqlite::params_from_iter(values),
+            )?;
+        }
+        Operation::Delete => {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE id = ?1", table),
+                params![change.id],
+            )?;
+        }
+        Operation::Final => return Ok(()),
+        Operation::Unset => {
+            return Err(anyhow!(
+                "operation is not set on {} {}",
+                change.entity,
+                change.id
+            ))
+        }
+    }
+
+    tx.execute(
+        "INSERT INTO _substreams_history (block_num, entity, entity_id, previous) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            block_num as i64,
+            change.entity,
+            change.id,
+            previous.map(|row| JsonValue::Object(row).to_string())
+        ],
+    )?;
+
+    Ok(())
+}
+
+// Creates the entity table on first sight and adds a column for every field not seen yet.
+fn ensure_table(
+    tx: &Transaction,
+    entity: &str,
+    columns: &[(String, EntityValue)],
+) -> Result<(), Error> {
+    let table = quote_identifier(entity);
+    tx.execute(
+        &format!("CREATE TABLE IF NOT EXISTS {} (id TEXT PRIMARY KEY)", table),
+        [],
+    )?;
+
+    let existing: HashSet<String> = {
+        let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(1))?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    for (name, value) in columns {
+        if !existing.contains(name) {
+            tx.execute(
+                &format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table,
+                    quote_identifier(name),
+                    column_type(value)
+                ),
+                [],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_row(
+    tx: &Transaction,
+    table: &str,
+    id: &str,
+) -> Result<Option<Map<String, JsonValue>>, Error> {
+    let mut stmt = tx.prepare(&format!("SELECT * FROM {} WHERE id = ?1", table))?;
+    let names: Vec<String> = stmt.column_names().iter().map(|n| n.to_string()).collect();
+
+    Ok(stmt
+        .query_row(params![id], |row| {
+            let mut object = Map::new();
+            for (i, name) in names.iter().enumerate() {
+                object.insert(name.clone(), sql_to_json(row.get_ref(i)?));
+            }
+            Ok(object)
+        })
+        .optional()?)
+}
+
+fn replace_row(tx: &Transaction, table: &str, columns: &[(String, SqlValue)]) -> Result<(), Error> {
+    let names: Vec<String> = columns
+        .iter()
+        .map(|(name, _)| quote_identifier(name))
+        .collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+
+    tx.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+            table,
+            names.join(", "),
+            placeholders.join(", ")
+        ),
+        rusqlite::params_from_iter(columns.iter().map(|(_, value)| value)),
+    )?;
+
+    Ok(())
+}
+
+fn save_cursor(tx: &Transaction, cursor: &str, block_num: u64) -> Result<(), Error> {
+    tx.execute(
+        "INSERT OR REPLACE INTO _substreams_cursor (id, cursor, block_num) VALUES (0, ?1, ?2)",
+        params![cursor, block_num as i64],
+    )?;
+    Ok(())
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn column_type(value: &EntityValue) -> &'static str {
+    match value {
+        EntityValue::Int32(_) | EntityValue::Bool(_) => "INTEGER",
+        _ => "TEXT",
+    }
+}
+
+fn entity_value_to_sql(value: &EntityValue) -> SqlValue {
+    match value {
+        EntityValue::Int32(v) => SqlValue::Integer(*v as i64),
+        EntityValue::Bool(v) => SqlValue::Integer(*v as i64),
+        EntityValue::BigDecimal(v)
+        | EntityValue::BigInt(v)
+        | EntityValue::String(v)
+        | EntityValue::Bytes(v) => SqlValue::Text(v.clone()),
+        EntityValue::Array(_) => SqlValue::Text(value.to_json().to_string()),
+        EntityValue::Null => SqlValue::Null,
+    }
+}
+
+fn sql_to_json(value: ValueRef) -> JsonValue {
+    match value {
+        ValueRef::Null => JsonValue::Null,
+        ValueRef::Integer(v) => JsonValue::from(v),
+        ValueRef::Real(v) => JsonValue::from(v),
+        ValueRef::Text(v) => JsonValue::String(String::from_utf8_lossy(v).to_string()),
+        ValueRef::Blob(v) => JsonValue::String(String::from_utf8_lossy(v).to_string()),
+    }
+}
+
+fn json_to_sql(value: JsonValue) -> SqlValue {
+    match value {
+        JsonValue::Null => SqlValue::Null,
+        JsonValue::Bool(v) => SqlValue::Integer(v as i64),
+        JsonValue::Number(v) => match v.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(v.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(v) => SqlValue::Text(v),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::sf::substreams::entity::v1::{value::Typed, Field, Value};
+
+    fn change(operation: Operation, id: &str, fields: Vec<(&str, Typed)>) -> EntityChange {
+        EntityChange {
+            entity: "Transfer".to_string(),
+            id: id.to_string(),
+            ordinal: 0,
+            operation: operation as i32,
+            fields: fields
+                .into_iter()
+                .map(|(name, typed)| Field {
+                    name: name.to_string(),
+                    new_value: Some(Value { typed: Some(typed) }),
+                    old_value: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn amount(sink: &SqliteSink, id: &str) -> Option<String> {
+        sink.connection()
+            .query_row(
+                "SELECT amount FROM Transfer WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+    }
+
+    #[test]
+    fn applies_changes_and_rolls_back_on_undo() {
+        let mut sink = SqliteSink::open_in_memory().unwrap();
+        assert_eq!(sink.load_cursor().unwrap(), None);
+
+        sink.apply_block(
+            1,
+            0,
+            &EntityChanges {
+                entity_changes: vec![change(
+                    Operation::Create,
+                    "t1",
+                    vec![("amount", Typed::Bigint("10".to_string()))],
+                )],
+            },
+            "c1",
+        )
+        .unwrap();
+        sink.apply_block(
+            2,
+            0,
+            &EntityChanges {
+                entity_changes: vec![
+                    change(
+                        Operation::Update,
+                        "t1",
+                        vec![
+                            ("amount", Typed::Bigint("20".to_string())),
+                            ("confirmed", Typed::Bool(true)),
+                        ],
+                    ),
+                    change(
+                        Operation::Create,
+                        "t2",
+                        vec![("amount", Typed::Bigint("5".to_string()))],
+                    ),
+                ],
+            },
+            "c2",
+        )
+        .unwrap();
+
+        assert_eq!(amount(&sink, "t1").as_deref(), Some("20"));
+        assert_eq!(sink.load_cursor().unwrap().as_deref(), Some("c2"));
+
+        sink.undo(1, "c1").unwrap();
+
+        assert_eq!(amount(&sink, "t1").as_deref(), Some("10"));
+        assert_eq!(amount(&sink, "t2"), None);
+        assert_eq!(sink.load_cursor().unwrap().as_deref(), Some("c1"));
+    }
+
+    #[test]
+    fn final_blocks_are_not_undone() {
+        let mut sink = SqliteSink::open_in_memory().unwrap();
+
+        sink.apply_block(
+            1,
+            1,
+            &EntityChanges {
+                entity_changes: vec![change(
+                    Operation::Create,
+                    "t1",
+                    vec![("amount", Typed::Bigint("10".to_string()))],
+                )],
+            },
+            "c1",
+        )
+        .unwrap();
+        sink.apply_block(
+            2,
+            1,
+            &EntityChanges {
+                entity_changes: vec![change(Operation::Delete, "t1", vec![])],
+            },
+            "c2",
+        )
+        .unwrap();
+
+        sink.undo(0, "c0").unwrap();
+
+        assert_eq!(amount(&sink, "t1").as_deref(), Some("10"));
+    }
+
+    #[test]
+    fn internal_table_names_are_reserved() {
+        let mut sink = SqliteSink::open_in_memory().unwrap();
+
+        for entity in [
+            "_substreams_cursor",
+            "_Substreams_History",
+            "sqlite_sequence",
+        ] {
+            let mut reserved = change(Operation::Create, "t1", vec![]);
+            reserved.entity = entity.to_string();
+            let changes = EntityChanges {
+                entity_changes: vec![reserved],
+            };
+
+            let err = sink.apply_block(1, 0, &changes, "c1").unwrap_err();
+            assert!(err.to_string().contains("reserved"), "{}", err);
+        }
+
+        let mut entity = change(Operation::Create, "t1", vec![]);
+        entity.entity = "_cursor".to_string();
+        let changes = EntityChanges {
+            entity_changes: vec![entity],
+        };
+        sink.apply_block(1, 0, &changes, "c1").unwrap();
+        assert_eq!(sink.load_cursor().unwrap().as_deref(), Some("c1"));
+    }
+}
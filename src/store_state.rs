@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Error};
+use prost::Message;
+use serde_json::{json, Value as JsonValue};
+
+use crate::eth::encode_data;
+use crate::pb::sf::substreams::rpc::v2 as rpc;
+use crate::pb::sf::substreams::v1::{
+    module::{kind_store::UpdatePolicy, Kind},
+    store_delta::Operation,
+    Module, StoreDelta, StoreDeltas,
+};
+
+// The `value_type` of a store module, telling how its raw values are encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreValueType {
+    Int64,
+    Float64,
+    BigInt,
+    BigDecimal,
+    String,
+    Bytes,
+    Proto(String),
+}
+
+impl StoreValueType {
+    pub fn parse(value_type: &str) -> Result<Self, Error> {
+        Ok(match value_type {
+            "int64" => StoreValueType::Int64,
+            "float64" => StoreValueType::Float64,
+            "bigint" => StoreValueType::BigInt,
+            // `bigfloat` is the deprecated name of `bigdecimal`.
+            "bigdecimal" | "bigfloat" => StoreValueType::BigDecimal,
+            "string" => StoreValueType::String,
+            "bytes" => StoreValueType::Bytes,
+            x if x.starts_with("proto:") => {
+                StoreValueType::Proto(x.trim_start_matches("proto:").to_string())
+            }
+            x => return Err(anyhow!("unknown store value type '{}'", x)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreValue {
+    Int64(i64),
+    Float64(f64),
+    // Arbitrary precision numbers are kept in their decimal string form.
+    BigInt(String),
+    BigDecimal(String),
+    String(String),
+    Bytes(Vec<u8>),
+    // Encoded message of the given fully qualified type.
+    Proto { type_name: String, value: Vec<u8> },
+}
+
+impl StoreValue {
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            StoreValue::Int64(v) => json!(v),
+            StoreValue::Float64(v) => json!(v),
+            StoreValue::BigInt(v) | StoreValue::BigDecimal(v) | StoreValue::String(v) => json!(v),
+            StoreValue::Bytes(v) => json!(encode_data(v)),
+            StoreValue::Proto { type_name, value } => json!({
+                "type": type_name,
+                "value": encode_data(value),
+            }),
+        }
+    }
+}
+
+// Store deltas are received both as `sf.substreams.v1.StoreDelta` (module outputs) and as
+// `sf.substreams.rpc.v2.StoreDelta` (debug outputs and initial snapshots), with identical
+// fields.
+impl From<&rpc::StoreDelta> for StoreDelta {
+    fn from(delta: &rpc::StoreDelta) -> Self {
+        StoreDelta {
+            operation: delta.operation,
+            ordinal: delta.ordinal,
+            key: delta.key.clone(),
+            old_value: delta.old_value.clone(),
+            new_value: delta.new_value.clone(),
+        }
+    }
+}
+
+// Reconstructs the key/value state of a store module from its deltas. Applied deltas are
+// kept per block until final so a reorg can be undone using their `old_value`.
+pub struct StoreStateTracker {
+    value_type: StoreValueType,
+    update_policy: UpdatePolicy,
+    state: BTreeMap<String, Vec<u8>>,
+    history: Vec<(u64, StoreDelta)>,
+}
+
+impl StoreStateTracker {
+    pub fn new(value_type: StoreValueType, update_policy: UpdatePolicy) -> Self {
+        StoreStateTracker {
+            value_type,
+            update_policy,
+            state: BTreeMap::new(),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn for_module(module: &Module) -> Result<Self, Error> {
+        match &module.kind {
+            Some(Kind::KindStore(store)) => Ok(Self::new(
+                StoreValueType::parse(&store.value_type)?,
+                store.update_policy(),
+            )),
+            _ => Err(anyhow!("module '{}' is not a store module", module.name)),
+        }
+    }
+
+    // Applies the deltas of a block in ordinal order, or none of them when one has an
+    // invalid operation.
+    pub fn apply(&mut self, block_num: u64, deltas: &[StoreDelta]) -> Result<(), Error> {
+        let mut operations: Vec<(Operation, &StoreDelta)> = deltas
+            .iter()
+            .map(|delta| match Operation::try_from(delta.operation) {
+                Ok(operation) if operation != Operation::Unset => Ok((operation, delta)),
+                _ => Err(anyhow!(
+                    "invalid operation {} for key '{}'",
+                    delta.operation,
+                    delta.key
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        operations.sort_by_key(|(_, delta)| delta.ordinal);
+
+        for (operation, delta) in operations {
+            if operation == Operation::Delete {
+                self.state.remove(&delta.key);
+            } else {
+                self.state
+                    .insert(delta.key.clone(), delta.new_value.clone());
+            }
+            self.history.push((block_num, delta.clone()));
+        }
+
+        Ok(())
+    }
+
+    pub fn apply_encoded(&mut self, block_num: u64, bytes: &[u8]) -> Result<(), Error> {
+        let deltas = StoreDeltas::decode(bytes).context("decode store deltas")?;
+        self.apply(block_num, &deltas.store_deltas)
+    }
+
+    pub fn apply_rpc(&mut self, block_num: u64, deltas: &[rpc::StoreDelta]) -> Result<(), Error> {
+        let deltas: Vec<StoreDelta> = deltas.iter().map(StoreDelta::from).collect();
+        self.apply(block_num, &deltas)
+    }
+
+    // Undoes, newest first, every delta applied after `block_num`.
+    pub fn undo_to(&mut self, block_num: u64) {
+        while let Some((applied_at, _)) = self.history.last() {
+            if *applied_at <= block_num {
+                break;
+            }
+
+            let (_, delta) = self.history.pop().unwrap();
+            match Operation::try_from(delta.operation) {
+                Ok(Operation::Create) => {
+                    self.state.remove(&delta.key);
+                }
+                _ => {
+                    self.state.insert(delta.key, delta.old_value);
+                }
+            }
+        }
+    }
+
+    // Drops the deltas kept for blocks up to `block_num` included, usually the final block
+    // height of the stream: those blocks are irreversible so their `old_value` is never read.
+    pub fn finalize(&mut self, block_num: u64) {
+        self.history
+            .retain(|(applied_at, _)| *applied_at > block_num);
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.is_empty()
+    }
+
+    pub fn get_raw(&self, key: &str) -> Option<&[u8]> {
+        self.state.get(key).map(Vec::as_slice)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<StoreValue>, Error> {
+        self.get_raw(key).map(|raw| self.decode(raw)).transpose()
+    }
+
+    pub fn keys_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a String> {
+        self.state
+            .range(prefix.to_string()..)
+            .map(|(key, _)| key)
+            .take_while(move |key| key.starts_with(prefix))
+    }
+
+    // Decodes a raw store value according to the store's value type. Numeric stores keep
+    // their values as decimal strings, `set_sum` stores prefix them with `set:` or `sum:`.
+    pub fn decode(&self, raw: &[u8]) -> Result<StoreValue, Error> {
+        if let StoreValueType::Bytes = self.value_type {
+            return Ok(StoreValue::Bytes(raw.to_vec()));
+        }
+        if let StoreValueType::Proto(type_name) = &self.value_type {
+            return Ok(StoreValue::Proto {
+                type_name: type_name.clone(),
+                value: raw.to_vec(),
+            });
+        }
+
+        let text = std::str::from_utf8(raw).context("store value is not valid UTF-8")?;
+        let text = match self.update_policy {
+            UpdatePolicy::SetSum => text
+                .strip_prefix("set:")
+                .or_else(|| text.strip_prefix("sum:"))
+                .unwrap_or(text),
+            _ => text,
+        };
+
+        Ok(match &self.value_type {
+            StoreValueType::Int64 => StoreValue::Int64(
+                text.parse()
+                    .context(format!("'{}' is not a valid int64", text))?,
+            ),
+            StoreValueType::Float64 => StoreValue::Float64(
+                text.parse()
+                    .context(format!("'{}' is not a valid float64", text))?,
+            ),
+            StoreValueType::BigInt => StoreValue::BigInt(text.to_string()),
+            StoreValueType::BigDecimal => StoreValue::BigDecimal(text.to_string()),
+            _ => StoreValue::String(text.to_string()),
+        })
+    }
+
+    pub fn to_json(&self) -> Result<JsonValue, Error> {
+        let mut object = serde_json::Map::new();
+        for (key, raw) in &self.state {
+            object.insert(key.clone(), self.decode(raw)?.to_json());
+        }
+        Ok(JsonValue::Object(object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(operation: Operation, ordinal: u64, key: &str, old: &str, new: &str) -> StoreDelta {
+        StoreDelta {
+            operation: operation as i32,
+            ordinal,
+            key: key.to_string(),
+            old_value: old.as_bytes().to_vec(),
+            new_value: new.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn applies_in_ordinal_order_and_undoes() {
+        let mut tracker = StoreStateTracker::new(StoreValueType::Int64, UpdatePolicy::Add);
+
+        tracker
+            .apply(
+                1,
+                &[
+                    delta(Operation::Update, 2, "balance:a", "5", "7"),
+                    delta(Operation::Create, 1, "balance:a", "", "5"),
+                ],
+            )
+            .unwrap();
+        tracker
+            .apply(
+                2,
+                &[
+                    delta(Operation::Update, 1, "balance:a", "7", "9"),
+                    delta(Operation::Create, 2, "balance:b", "", "1"),
+                ],
+            )
+            .unwrap();
+        tracker
+            .apply(3, &[delta(Operation::Delete, 1, "balance:a", "9", "")])
+            .unwrap();
+
+        assert_eq!(tracker.get("balance:a").unwrap(), None);
+        assert_eq!(tracker.keys_with_prefix("balance:").count(), 1);
+
+        tracker.undo_to(1);
+
+        assert_eq!(
+            tracker.get("balance:a").unwrap(),
+            Some(StoreValue::Int64(7))
+        );
+        assert_eq!(tracker.get("balance:b").unwrap(), None);
+    }
+
+    #[test]
+    fn decodes_set_sum_values() {
+        let mut tracker = StoreStateTracker::new(StoreValueType::BigInt, UpdatePolicy::SetSum);
+        tracker
+            .apply(
+                1,
+                &[
+                    delta(Operation::Create, 1, "a", "", "set:10"),
+                    delta(Operation::Create, 2, "b", "", "sum:-3"),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(tracker.to_json().unwrap(), json!({"a": "10", "b": "-3"}));
+        assert!(StoreValueType::parse("uint8").is_err());
+    }
+
+    #[test]
+    fn bytes_are_0x_hex_encoded() {
+        let mut tracker = StoreStateTracker::new(StoreValueType::Bytes, UpdatePolicy::Set);
+        tracker
+            .apply(1, &[delta(Operation::Create, 1, "a", "", "\u{1}\u{ab}")])
+            .unwrap();
+
+        assert_eq!(tracker.to_json().unwrap(), json!({"a": "0x01c2ab"}));
+    }
+
+    #[test]
+    fn invalid_deltas_leave_the_state_untouched() {
+        let mut tracker = StoreStateTracker::new(StoreValueType::String, UpdatePolicy::Set);
+
+        let result = tracker.apply(
+            1,
+            &[
+                delta(Operation::Create, 1, "a", "", "x"),
+                delta(Operation::Unset, 2, "b", "", "y"),
+            ],
+        );
+
+        assert!(result.is_err());
+        assert!(tracker.is_empty());
+        assert!(tracker.history.is_empty());
+    }
+}
@@ -1,5 +1,6 @@
-use std::{fmt::Display, sync::Arc, time::Duration};
+use std::{fmt::Display, path::PathBuf, sync::Arc, time::Duration};
 
+use anyhow::anyhow;
 use http::{uri::Scheme, Uri};
 use tonic::{
     codec::CompressionEncoding,
@@ -8,13 +9,20 @@ use tonic::{
     transport::{Channel, ClientTlsConfig},
 };
 
-use crate::pb::sf::substreams::rpc::v2::{stream_client::StreamClient, Request, Response};
+use crate::pb::sf::firehose::v2::{info_response::BlockIdEncoding, InfoRequest, InfoResponse};
+use crate::pb::sf::substreams::rpc::v2::{
+    endpoint_info_client::EndpointInfoClient, stream_client::StreamClient, Request,
+};
+use crate::recording::{
+    record_responses, replay_responses, start_recording, ResponseStream, SessionMode, REPLAY_SCHEME,
+};
 
 #[derive(Clone, Debug)]
 pub struct SubstreamsEndpoint {
     pub uri: String,
     pub token: Option<String>,
     channel: Channel,
+    mode: SessionMode,
 }
 
 impl Display for SubstreamsEndpoint {
@@ -25,6 +33,10 @@ impl Display for SubstreamsEndpoint {
 
 impl SubstreamsEndpoint {
     pub async fn new<S: AsRef<str>>(url: S, token: Option<String>) -> Result<Self, anyhow::Error> {
+        if let Some(path) = url.as_ref().strip_prefix(REPLAY_SCHEME) {
+            return Ok(Self::replay(PathBuf::from(path)));
+        }
+
         let uri = url
             .as_ref()
             .parse::<Uri>()
@@ -47,36 +59,184 @@ impl SubstreamsEndpoint {
             uri,
             channel,
             token,
+            mode: SessionMode::Live,
         })
     }
 
+    // An endpoint serving the responses recorded in `path`. Its channel points nowhere and
+    // is never used, only `substreams` is served.
+    pub fn replay(path: PathBuf) -> Self {
+        SubstreamsEndpoint {
+            uri: format!("{}{}", REPLAY_SCHEME, path.display()),
+            token: None,
+            channel: Channel::from_static("http://127.0.0.1:9").connect_lazy(),
+            mode: SessionMode::Replay(path),
+        }
+    }
+
+    // Records every response received by `substreams` to `path`, replacing its content.
+    pub fn with_recording(mut self, path: PathBuf) -> Result<Self, anyhow::Error> {
+        if let SessionMode::Live = self.mode {
+            start_recording(&path)?;
+            self.mode = SessionMode::Record(path);
+        }
+        Ok(self)
+    }
+
+    pub fn mode(&self) -> &SessionMode {
+        &self.mode
+    }
+
     pub async fn substreams(
         self: Arc<Self>,
         request: Request,
-    ) -> Result<tonic::Streaming<Response>, anyhow::Error> {
+    ) -> Result<ResponseStream, anyhow::Error> {
+        if let SessionMode::Replay(path) = &self.mode {
+            return replay_responses(path);
+        }
+
+        let mut client =
+            StreamClient::with_interceptor(self.channel.clone(), self.auth_interceptor()?)
+                .accept_compressed(CompressionEncoding::Gzip)
+                .send_compressed(CompressionEncoding::Gzip)
+                .max_decoding_message_size(10 * 1024 * 1024);
+
+        let response_stream = client.blocks(request).await?;
+        let block_stream: ResponseStream = Box::pin(response_stream.into_inner());
+
+        match &self.mode {
+            SessionMode::Record(path) => record_responses(block_stream, path),
+            _ => Ok(block_stream),
+        }
+    }
+
+    pub async fn info(&self) -> Result<EndpointInfo, anyhow::Error> {
+        if let SessionMode::Replay(_) = self.mode {
+            return Err(anyhow!(
+                "endpoint info is not available when replaying a recorded session"
+            ));
+        }
+
+        let mut client =
+            EndpointInfoClient::with_interceptor(self.channel.clone(), self.auth_interceptor()?)
+                .accept_compressed(CompressionEncoding::Gzip)
+                .send_compressed(CompressionEncoding::Gzip);
+
+        let response = client.info(InfoRequest {}).await?;
+
+        Ok(response.into_inner().into())
+    }
+
+    pub(crate) fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
+    // Builds the interceptor adding the `authorization` header to every outgoing request.
+    pub(crate) fn auth_interceptor(
+        &self,
+    ) -> Result<
+        impl FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> + Clone,
+        anyhow::Error,
+    > {
         let token_metadata: Option<MetadataValue<tonic::metadata::Ascii>> = match self.token.clone()
         {
             Some(token) => Some(token.as_str().try_into()?),
             None => None,
         };
 
-        let mut client = StreamClient::with_interceptor(
-            self.channel.clone(),
-            move |mut r: tonic::Request<()>| {
-                if let Some(ref t) = token_metadata {
-                    r.metadata_mut().insert("authorization", t.clone());
-                }
+        Ok(move |mut r: tonic::Request<()>| {
+            if let Some(ref t) = token_metadata {
+                r.metadata_mut().insert("authorization", t.clone());
+            }
 
-                Ok(r)
-            },
-        )
-        .accept_compressed(CompressionEncoding::Gzip)
-        .send_compressed(CompressionEncoding::Gzip)
-        .max_decoding_message_size(10 * 1024 * 1024);
+            Ok(r)
+        })
+    }
+}
 
-        let response_stream = client.blocks(request).await?;
-        let block_stream = response_stream.into_inner();
+#[derive(Clone, Debug)]
+pub struct EndpointInfo {
+    pub chain_name: String,
+    pub chain_name_aliases: Vec<String>,
+    pub first_streamable_block_num: u64,
+    pub first_streamable_block_id: String,
+    pub block_id_encoding: BlockIdEncoding,
+    pub block_features: Vec<String>,
+}
+
+impl From<InfoResponse> for EndpointInfo {
+    fn from(response: InfoResponse) -> Self {
+        EndpointInfo {
+            block_id_encoding: response.block_id_encoding(),
+            chain_name: response.chain_name,
+            chain_name_aliases: response.chain_name_aliases,
+            first_streamable_block_num: response.first_streamable_block_num,
+            first_streamable_block_id: response.first_streamable_block_id,
+            block_features: response.block_features,
+        }
+    }
+}
+
+impl EndpointInfo {
+    pub fn serves_network(&self, network: &str) -> bool {
+        self.chain_name.eq_ignore_ascii_case(network)
+            || self
+                .chain_name_aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(network))
+    }
+
+    // Checks that a request for `network` starting at `start_block` can be served by this
+    // endpoint. An empty network skips the network check, and negative start blocks are
+    // relative to the chain head so they are always streamable.
+    pub fn validate(&self, network: &str, start_block: i64) -> Result<(), anyhow::Error> {
+        if !network.is_empty() && !self.serves_network(network) {
+            return Err(anyhow!(
+                "package network '{}' is not served by this endpoint (chain '{}', aliases [{}])",
+                network,
+                self.chain_name,
+                self.chain_name_aliases.join(", ")
+            ));
+        }
+
+        if start_block >= 0 && (start_block as u64) < self.first_streamable_block_num {
+            return Err(anyhow!(
+                "start block {} is before the first streamable block {} of chain '{}'",
+                start_block,
+                self.first_streamable_block_num,
+                self.chain_name
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::MockSubstreamsServer;
+
+    #[tokio::test]
+    async fn info_validates_network_and_start_block() {
+        let server = MockSubstreamsServer::start(vec![])
+            .await
+            .unwrap()
+            .with_info(InfoResponse {
+                chain_name: "mainnet".to_string(),
+                chain_name_aliases: vec!["ethereum".to_string()],
+                first_streamable_block_num: 100,
+                block_id_encoding: BlockIdEncoding::Hex as i32,
+                ..Default::default()
+            });
+
+        let endpoint = SubstreamsEndpoint::new(server.url(), None).await.unwrap();
+        let info = endpoint.info().await.unwrap();
 
-        Ok(block_stream)
+        assert_eq!(info.block_id_encoding, BlockIdEncoding::Hex);
+        assert!(info.validate("Ethereum", 100).is_ok());
+        assert!(info.validate("", -10).is_ok());
+        assert!(info.validate("polygon", 100).is_err());
+        assert!(info.validate("mainnet", 99).is_err());
     }
 }
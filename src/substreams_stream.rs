@@ -11,10 +11,12 @@ use tokio::time::sleep;
 use tokio_retry::strategy::ExponentialBackoff;
 
 use crate::pb::sf::substreams::rpc::v2::{
-    response::Message, BlockScopedData, BlockUndoSignal, Request, Response,
+    response::Message, BlockScopedData, BlockUndoSignal, Error as ModuleError, Request, Response,
 };
 use crate::pb::sf::substreams::v1::Modules;
 
+use crate::endpoint_pool::SubstreamsEndpointPool;
+use crate::recording::is_recording_error;
 use crate::substreams::SubstreamsEndpoint;
 
 pub enum BlockResponse {
@@ -34,10 +36,28 @@ impl SubstreamsStream {
         output_module_name: String,
         start_block: i64,
         end_block: u64,
+    ) -> Self {
+        Self::with_pool(
+            Arc::new(SubstreamsEndpointPool::single(endpoint)),
+            cursor,
+            modules,
+            output_module_name,
+            start_block,
+            end_block,
+        )
+    }
+
+    pub fn with_pool(
+        pool: Arc<SubstreamsEndpointPool>,
+        cursor: Option<String>,
+        modules: Option<Modules>,
+        output_module_name: String,
+        start_block: i64,
+        end_block: u64,
     ) -> Self {
         SubstreamsStream {
             stream: Box::pin(stream_blocks(
-                endpoint,
+                pool,
                 cursor,
                 modules,
                 output_module_name,
@@ -48,21 +68,24 @@ impl SubstreamsStream {
     }
 }
 
-// Create the Stream implementation that streams blocks with auto-reconnection.
+// Create the Stream implementation that streams blocks with auto-reconnection. Each
+// (re)connection asks the pool for its active endpoint, so failing over to another
+// provider resumes from `latest_cursor`.
 fn stream_blocks(
-    endpoint: Arc<SubstreamsEndpoint>,
+    pool: Arc<SubstreamsEndpointPool>,
     cursor: Option<String>,
     modules: Option<Modules>,
     output_module_name: String,
     start_block_num: i64,
     stop_block_num: u64,
 ) -> impl Stream<Item = Result<BlockResponse, Error>> {
-    let mut latest_cursor = cursor.unwrap_or_else(|| "".to_string());
+    let mut latest_cursor = cursor.unwrap_or_default();
     let mut backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
     let mut last_progress_report = Instant::now();
 
     try_stream! {
         loop {
+            let endpoint = pool.current();
             println!("Blockstreams disconnected, connecting (endpoint {}, start block {}, stop block {}, cursor {})",
                 &endpoint,
                 start_block_num,
@@ -96,6 +119,7 @@ fn stream_blocks(
                             BlockProcessedResult::BlockScopedData(block_scoped_data) => {
                                 // Reset backoff because we got a good value from the stream
                                 backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
+                                pool.report_success(&endpoint);
 
                                 let cursor = block_scoped_data.cursor.clone();
                                 yield BlockResponse::New(block_scoped_data);
@@ -105,6 +129,7 @@ fn stream_blocks(
                             BlockProcessedResult::BlockUndoSignal(block_undo_signal) => {
                                 // Reset backoff because we got a good value from the stream
                                 backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
+                                pool.report_success(&endpoint);
 
                                 let cursor = block_undo_signal.last_valid_cursor.clone();
                                 yield BlockResponse::Undo(block_undo_signal);
@@ -112,14 +137,20 @@ fn stream_blocks(
                                 latest_cursor = cursor;
                             },
                             BlockProcessedResult::Skip() => {},
+                            BlockProcessedResult::FatalError(error) => {
+                                // The provider gave up on the request (e.g. the module panicked), retrying
+                                // would fail the same way so we forward the error to the consumer.
+                                return Err(anyhow!("module {} failed: {}", error.module, error.reason))?;
+                            },
                             BlockProcessedResult::TonicError(status) => {
-                                // Unauthenticated errors are not retried, we forward the error back to the
-                                // stream consumer which handles it
-                                if status.code() == tonic::Code::Unauthenticated {
+                                // Unauthenticated and recording errors are not retried, we forward the
+                                // error back to the stream consumer which handles it
+                                if status.code() == tonic::Code::Unauthenticated || is_recording_error(&status) {
                                     return Err(anyhow::Error::new(status.clone()))?;
                                 }
 
                                 println!("Received tonic error {:#}", status);
+                                pool.report_failure(&endpoint, status);
                                 encountered_error = true;
                                 break;
                             },
@@ -137,6 +168,10 @@ fn stream_blocks(
                     // having connection errors.
 
                     println!("Unable to connect to endpoint: {:#}", e);
+                    if pool.report_failure(&endpoint, &e) {
+                        // A different provider is available, try it right away.
+                        continue;
+                    }
                 }
             }
 
@@ -154,6 +189,7 @@ enum BlockProcessedResult {
     Skip(),
     BlockScopedData(BlockScopedData),
     BlockUndoSignal(BlockUndoSignal),
+    FatalError(ModuleError),
     TonicError(tonic::Status),
 }
 
@@ -180,6 +216,7 @@ async fn process_substreams_response(
         Some(Message::BlockUndoSignal(block_undo_signal)) => {
             BlockProcessedResult::BlockUndoSignal(block_undo_signal)
         }
+        Some(Message::FatalError(error)) => BlockProcessedResult::FatalError(error),
         Some(Message::Progress(progress)) => {
             if last_progress_report.elapsed() > Duration::from_secs(30) {
                 let processed_bytes = progress.processed_bytes.unwrap_or_default();
@@ -245,3 +282,147 @@ impl Stream for SubstreamsStream {
         self.stream.poll_next_unpin(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{mock_cursor, MockEvent, MockSubstreamsServer};
+
+    const MODULE: &str = "map_events";
+
+    async fn connect(server: &MockSubstreamsServer, cursor: Option<String>) -> SubstreamsStream {
+        let endpoint = Arc::new(SubstreamsEndpoint::new(server.url(), None).await.unwrap());
+        SubstreamsStream::new(endpoint, cursor, None, MODULE.to_string(), 1, 0)
+    }
+
+    fn describe(response: &BlockResponse) -> String {
+        match response {
+            BlockResponse::New(data) => format!("new {}", data.clock.as_ref().unwrap().number),
+            BlockResponse::Undo(undo) => {
+                format!("undo {}", undo.last_valid_block.as_ref().unwrap().number)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_from_last_cursor_after_disconnect() {
+        let server = MockSubstreamsServer::start(vec![
+            vec![
+                MockEvent::session("trace-1"),
+                MockEvent::progress(),
+                MockEvent::data(MODULE, 1, vec![1]),
+                MockEvent::data(MODULE, 2, vec![2]),
+                MockEvent::Disconnect,
+            ],
+            vec![MockEvent::session("trace-2"), MockEvent::data(MODULE, 3, vec![3])],
+        ])
+        .await
+        .unwrap();
+
+        let stream = connect(&server, None).await;
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["new 1", "new 2", "new 3"]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].start_cursor, "");
+        assert_eq!(requests[1].start_cursor, mock_cursor(2));
+    }
+
+    #[tokio::test]
+    async fn starts_from_given_cursor() {
+        let server = MockSubstreamsServer::start(vec![vec![MockEvent::data(MODULE, 11, vec![])]])
+            .await
+            .unwrap();
+
+        let stream = connect(&server, Some(mock_cursor(10))).await;
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["new 11"]);
+        assert_eq!(server.requests()[0].start_cursor, mock_cursor(10));
+    }
+
+    #[tokio::test]
+    async fn undo_moves_cursor_back_to_last_valid_block() {
+        let server = MockSubstreamsServer::start(vec![
+            vec![
+                MockEvent::data(MODULE, 1, vec![1]),
+                MockEvent::data(MODULE, 2, vec![2]),
+                MockEvent::undo(1),
+                MockEvent::Disconnect,
+            ],
+            vec![MockEvent::data(MODULE, 2, vec![22])],
+        ])
+        .await
+        .unwrap();
+
+        let stream = connect(&server, None).await;
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["new 1", "new 2", "undo 1", "new 2"]);
+        assert_eq!(server.requests()[1].start_cursor, mock_cursor(1));
+    }
+
+    #[tokio::test]
+    async fn fatal_error_ends_stream_without_retrying() {
+        let server = MockSubstreamsServer::start(vec![
+            vec![
+                MockEvent::data(MODULE, 1, vec![1]),
+                MockEvent::fatal(MODULE, "wasm panicked"),
+            ],
+            vec![MockEvent::data(MODULE, 2, vec![2])],
+        ])
+        .await
+        .unwrap();
+
+        let mut stream = connect(&server, None).await;
+
+        assert_eq!(describe(&stream.next().await.unwrap().unwrap()), "new 1");
+        let err = stream.next().await.unwrap().err().unwrap();
+        assert!(err.to_string().contains("wasm panicked"), "{}", err);
+        assert!(stream.next().await.is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_error_is_not_retried() {
+        let server = MockSubstreamsServer::start(vec![
+            vec![MockEvent::Error(tonic::Status::unauthenticated("bad token"))],
+            vec![MockEvent::data(MODULE, 1, vec![1])],
+        ])
+        .await
+        .unwrap();
+
+        let mut stream = connect(&server, None).await;
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_next_endpoint_keeping_cursor() {
+        let primary = MockSubstreamsServer::start(vec![vec![
+            MockEvent::data(MODULE, 1, vec![1]),
+            MockEvent::Disconnect,
+        ]])
+        .await
+        .unwrap();
+        let secondary = MockSubstreamsServer::start(vec![vec![MockEvent::data(MODULE, 2, vec![2])]])
+            .await
+            .unwrap();
+
+        let pool = SubstreamsEndpointPool::from_urls(&[primary.url(), secondary.url()], None)
+            .await
+            .unwrap()
+            .with_max_consecutive_failures(1);
+        let stream =
+            SubstreamsStream::with_pool(Arc::new(pool), None, None, MODULE.to_string(), 1, 0);
+        let received: Vec<String> = stream.map(|r| describe(&r.unwrap())).collect().await;
+
+        assert_eq!(received, vec!["new 1", "new 2"]);
+        assert_eq!(primary.requests().len(), 1);
+        assert_eq!(secondary.requests()[0].start_cursor, mock_cursor(1));
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error};
+use futures03::{SinkExt, Stream, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::sleep,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// A connection that cannot be (re)established, or drops before delivering a notification,
+// this many times in a row ends the subscription with an error.
+const MAX_CONNECT_ATTEMPTS: u32 = 10;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SubscriptionKind {
+    NewHeads,
+    // Log filter object, e.g. `{"address": "0x...", "topics": [...]}`.
+    Logs(Value),
+    NewPendingTransactions,
+}
+
+impl SubscriptionKind {
+    pub fn parse(kind: &str, filter: Option<Value>) -> Result<Self, Error> {
+        match kind {
+            "newHeads" => Ok(SubscriptionKind::NewHeads),
+            "logs" => Ok(SubscriptionKind::Logs(filter.unwrap_or_else(|| json!({})))),
+            "newPendingTransactions" => Ok(SubscriptionKind::NewPendingTransactions),
+            x => Err(anyhow!("unknown subscription kind '{}'", x)),
+        }
+    }
+
+    pub fn params(&self) -> Value {
+        match self {
+            SubscriptionKind::NewHeads => json!(["newHeads"]),
+            SubscriptionKind::Logs(filter) => json!(["logs", filter]),
+            SubscriptionKind::NewPendingTransactions => json!(["newPendingTransactions"]),
+        }
+    }
+}
+
+// Defaults to `wss://` when no scheme is given.
+pub fn parse_ws_url(url: &str) -> String {
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+        url.to_string()
+    } else {
+        format!("wss://{}", url)
+    }
+}
+
+// An `eth_subscribe` subscription over WebSocket, yielding the `result` of every
+// `eth_subscription` notification. The connection is re-established and the subscription
+// renewed whenever it drops, notifications sent while disconnected are lost.
+pub struct WsSubscription {
+    receiver: mpsc::Receiver<Result<Value, Error>>,
+    stop: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl WsSubscription {
+    pub fn subscribe(url: &str, kind: SubscriptionKind) -> Self {
+        let (sender, receiver) = mpsc::channel(256);
+        let (stop, stop_rx) = oneshot::channel();
+
+        let task = tokio::spawn(run_subscription(parse_ws_url(url), kind, sender, stop_rx));
+
+        WsSubscription {
+            receiver,
+            stop: Some(stop),
+            task: Some(task),
+        }
+    }
+
+    // Sends `eth_unsubscribe` and closes the connection.
+    pub async fn unsubscribe(mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Stream for WsSubscription {
+    type Item = Result<Value, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for WsSubscription {
+    // The task still unsubscribes on its own, there is just no waiting for it.
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+enum SessionEnd {
+    Stopped,
+    Disconnected(Error),
+}
+
+async fn run_subscription(
+    url: String,
+    kind: SubscriptionKind,
+    sender: mpsc::Sender<Result<Value, Error>>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut failures = 0;
+
+    loop {
+        let session = tokio::select! {
+            _ = &mut stop => return,
+            session = open_session(&url, &kind) => session,
+        };
+
+        let error = match session {
+            Ok((socket, subscription_id)) => {
+                match run_session(socket, &subscription_id, &sender, &mut stop, &mut failures).await
+                {
+                    SessionEnd::Stopped => return,
+                    SessionEnd::Disconnected(e) => e,
+                }
+            }
+            Err(e) => e,
+        };
+
+        failures += 1;
+        if failures >= MAX_CONNECT_ATTEMPTS {
+            let _ = sender
+                .send(Err(error.context(format!(
+                    "subscription to {} failed {} times in a row",
+                    url, failures
+                ))))
+                .await;
+            return;
+        }
+
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(failures - 1))
+            .min(MAX_BACKOFF);
+
+        tokio::select! {
+            _ = &mut stop => return,
+            _ = sleep(backoff) => {}
+        }
+    }
+}
+
+// Connects and subscribes, returning the socket and the subscription id.
+async fn open_session(url: &str, kind: &SubscriptionKind) -> Result<(Socket, String), Error> {
+    let (mut socket, _) = connect_async(url)
+        .await
+        .context(format!("connect to {}", url))?;
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_subscribe",
+        "params": kind.params(),
+    });
+    socket.send(Message::Text(request.to_string())).await?;
+
+    while let Some(message) = socket.next().await {
+        let Some(value) = parse_message(message?) else {
+            continue;
+        };
+        if value.get("id") != Some(&json!(1)) {
+            continue;
+        }
+
+        if let Some(error) = value.get("error").filter(|e| !e.is_null()) {
+            return Err(anyhow!("eth_subscribe failed: {}", error));
+        }
+
+        let subscription_id = value
+            .get("result")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("eth_subscribe returned no subscription id: {}", value))?;
+
+        return Ok((socket, subscription_id.to_string()));
+    }
+
+    Err(anyhow!(
+        "connection closed before eth_subscribe was answered"
+    ))
+}
+
+async fn run_session(
+    mut socket: Socket,
+    subscription_id: &str,
+    sender: &mpsc::Sender<Result<Value, Error>>,
+    stop: &mut oneshot::Receiver<()>,
+    failures: &mut u32,
+) -> SessionEnd {
+    loop {
+        let message = tokio::select! {
+            _ = &mut *stop => {
+                unsubscribe(&mut socket, subscription_id).await;
+                return SessionEnd::Stopped;
+            }
+            message = socket.next() => message,
+        };
+
+        let message = match message {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => return SessionEnd::Disconnected(e.into()),
+            None => return SessionEnd::Disconnected(anyhow!("connection closed")),
+        };
+
+        if let Message::Ping(payload) = &message {
+            let _ = socket.send(Message::Pong(payload.clone())).await;
+            continue;
+        }
+        if let Message::Close(_) = &message {
+            return SessionEnd::Disconnected(anyhow!("connection closed by server"));
+        }
+
+        let Some(value) = parse_message(message) else {
+            continue;
+        };
+        if value.get("method").and_then(Value::as_str) != Some("eth_subscription") {
+            continue;
+        }
+
+        let params = &value["params"];
+        if params["subscription"].as_str() != Some(subscription_id) {
+            continue;
+        }
+
+        // The receiver is gone, nobody is listening anymore.
+        if sender.send(Ok(params["result"].clone())).await.is_err() {
+            unsubscribe(&mut socket, subscription_id).await;
+            return SessionEnd::Stopped;
+        }
+        // Only a session that delivers something counts as recovered, one that is
+        // accepted and then dropped right away keeps counting towards the limit.
+        *failures = 0;
+    }
+}
+
+async fn unsubscribe(socket: &mut Socket, subscription_id: &str) {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "eth_unsubscribe",
+        "params": [subscription_id],
+    });
+
+    // Best effort, the server drops the subscription with the connection anyway.
+    let _ = socket.send(Message::Text(request.to_string())).await;
+    let _ = socket.close(None).await;
+}
+
+fn parse_message(message: Message) -> Option<Value> {
+    match message {
+        Message::Text(text) => serde_json::from_str(&text).ok(),
+        Message::Binary(bytes) => serde_json::from_slice(&bytes).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+    use crate::mock_http_server::serve_connections;
+
+    // A local WebSocket stand-in: each connection answers `eth_subscribe` with
+    // `sub-<n>`, sends the scripted notifications and drops the connection, except the
+    // last one which stays open until the client closes it, `closed` then firing. Every
+    // received request is recorded.
+    async fn serve(
+        sessions: Vec<Vec<Value>>,
+    ) -> (String, Arc<Mutex<Vec<Value>>>, oneshot::Receiver<()>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (closed_tx, closed) = oneshot::channel();
+        let sessions = Arc::new(sessions);
+        let closed_tx = Arc::new(Mutex::new(Some(closed_tx)));
+
+        let received = requests.clone();
+        let addr = serve_connections(move |stream, n| {
+            let sessions = sessions.clone();
+            let received = received.clone();
+            let closed_tx = closed_tx.clone();
+            async move {
+                let Some(notifications) = sessions.get(n) else {
+                    return;
+                };
+                let mut socket = accept_async(stream).await.unwrap();
+                let subscription_id = format!("sub-{}", n);
+
+                let request = parse_message(socket.next().await.unwrap().unwrap()).unwrap();
+                let reply =
+                    json!({"jsonrpc": "2.0", "id": request["id"], "result": subscription_id});
+                received.lock().unwrap().push(request);
+                socket.send(Message::Text(reply.to_string())).await.unwrap();
+
+                for result in notifications {
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "eth_subscription",
+                        "params": {"subscription": subscription_id, "result": result},
+                    });
+                    socket
+                        .send(Message::Text(notification.to_string()))
+                        .await
+                        .unwrap();
+                }
+
+                if n + 1 < sessions.len() {
+                    return;
+                }
+
+                while let Some(Ok(message)) = socket.next().await {
+                    if let Some(request) = parse_message(message) {
+                        received.lock().unwrap().push(request);
+                    }
+                }
+                if let Some(closed_tx) = closed_tx.lock().unwrap().take() {
+                    let _ = closed_tx.send(());
+                }
+            }
+        })
+        .await;
+
+        (format!("ws://{}", addr), requests, closed)
+    }
+
+    #[tokio::test]
+    async fn resubscribes_after_disconnect_and_unsubscribes() {
+        let (url, requests, closed) = serve(vec![
+            vec![json!({"number": "0x1"}), json!({"number": "0x2"})],
+            vec![json!({"number": "0x3"})],
+        ])
+        .await;
+
+        let mut subscription = WsSubscription::subscribe(&url, SubscriptionKind::NewHeads);
+        let mut heads = Vec::new();
+        for _ in 0..3 {
+            heads.push(subscription.next().await.unwrap().unwrap()["number"].clone());
+        }
+        assert_eq!(heads, vec![json!("0x1"), json!("0x2"), json!("0x3")]);
+
+        subscription.unsubscribe().await;
+        closed.await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let methods: Vec<&str> = requests
+            .iter()
+            .map(|r| r["method"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            methods,
+            vec!["eth_subscribe", "eth_subscribe", "eth_unsubscribe"]
+        );
+        assert_eq!(requests[0]["params"], json!(["newHeads"]));
+        assert_eq!(requests[2]["params"], json!(["sub-1"]));
+    }
+
+    #[test]
+    fn parses_subscription_kinds() {
+        assert_eq!(
+            SubscriptionKind::parse("logs", Some(json!({"address": "0xabc"})))
+                .unwrap()
+                .params(),
+            json!(["logs", {"address": "0xabc"}])
+        );
+        assert!(SubscriptionKind::parse("syncing", None).is_err());
+        assert_eq!(parse_ws_url("eth.llamarpc.com"), "wss://eth.llamarpc.com");
+    }
+}